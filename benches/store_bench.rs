@@ -0,0 +1,87 @@
+//! Write/read throughput of the sharded `KvStore` versus the previous
+//! single-lock design: the same store with a single shard.
+//!
+//! Run with: `cargo bench --bench store_bench`
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use dodo_db::state::kv::{self, new_store, Entry, KvStore};
+
+const THREADS: &[usize] = &[1, 2, 4, 8];
+const OPS_PER_THREAD: usize = 200_000;
+const KEYS: usize = 10_000;
+/// One in `WRITE_EVERY` operations is a write, the rest are reads.
+const WRITE_EVERY: usize = 4;
+
+fn entry(i: usize) -> Entry {
+    Entry::new(Arc::new(json!({ "n": i })), 0, None, i as u64)
+}
+
+fn key(i: usize) -> String {
+    format!("key/{}", i % KEYS)
+}
+
+/// Run `op(thread, i)` OPS_PER_THREAD times on each of `threads` threads.
+fn run<F>(threads: usize, op: F) -> Duration
+where
+    F: Fn(usize, usize) + Send + Sync + 'static,
+{
+    let op = Arc::new(op);
+    let start = Instant::now();
+
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let op = op.clone();
+            thread::spawn(move || {
+                for i in 0..OPS_PER_THREAD {
+                    op(t, i);
+                }
+            })
+        })
+        .collect();
+
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    start.elapsed()
+}
+
+/// The mixed workload on a store with `shards` shards.
+fn mixed(threads: usize, shards: usize) -> Duration {
+    let store: KvStore = new_store("bench", shards);
+
+    run(threads, move |t, i| {
+        let k = key(t * 7919 + i * 31);
+        if i % WRITE_EVERY == 0 {
+            store.shard(&k).write().unwrap().insert(k, entry(i));
+        } else {
+            let _ = store.shard(&k).read().unwrap().get(&k).map(|e| e.value.clone());
+        }
+    })
+}
+
+fn ops_per_sec(threads: usize, d: Duration) -> f64 {
+    (threads * OPS_PER_THREAD) as f64 / d.as_secs_f64()
+}
+
+fn main() {
+    println!(
+        "{} ops/thread, {} keys, 1 write every {} ops, {} shards",
+        OPS_PER_THREAD,
+        KEYS,
+        WRITE_EVERY,
+        kv::DEFAULT_SHARDS
+    );
+    println!("{:>8} {:>16} {:>16} {:>8}", "threads", "single-lock/s", "sharded/s", "speedup");
+
+    for &threads in THREADS {
+        let a = ops_per_sec(threads, mixed(threads, 1));
+        let b = ops_per_sec(threads, mixed(threads, kv::DEFAULT_SHARDS));
+        println!("{:>8} {:>16.0} {:>16.0} {:>7.2}x", threads, a, b, b / a);
+    }
+}
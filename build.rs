@@ -15,7 +15,7 @@ fn main() {
     let src = Path::new("config.json");
     let dst = exe_dir.join("config.json");
 
-    match fs::copy(&src, &dst) {
+    match fs::copy(src, &dst) {
         Ok(_) => println!("cargo:warning=Copied config.json → {}", dst.display()),
         Err(e) => println!("cargo:warning=Could NOT copy config.json: {}", e),
     }
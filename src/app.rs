@@ -2,27 +2,61 @@ use axum::Router;
 use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
 use tracing::Level;
 
-use crate::routes::{kv_routes, pubsub_routes, system_routes};
-use crate::state::kv::KvStore;
+use crate::routes::{
+    collection_routes, db_routes, kv_routes, lock_routes, pubsub_routes, queue_routes, system_routes,
+};
+use crate::state::db::DbRegistry;
+use crate::state::kv::{KvStore, DEFAULT_DB};
 use crate::config::AppConfig;
 
-/// Build the complete Axum application:
-/// - /kv       (key/value operations)
+/// Build the routes served by a single database:
+/// - /kv       (key/value and collection operations)
+/// - /locks    (leases with fencing tokens)
+/// - /queues   (work queues with visibility timeouts)
 /// - /pubsub   (subscribe/unsubscribe for events)
-/// - /system   (alive + version)
 ///
-/// `store` is cloned as needed.
-/// `cfg` is passed to /system/version so the server can expose its version.
-pub fn build_app(store: KvStore, cfg: AppConfig) -> Router {
+/// The default database is mounted at the root, named databases under
+/// /db/{name}.
+pub fn database_router(store: KvStore) -> Router {
+    let name = store.name().to_string();
+
     Router::new()
         // /kv/*
-        .nest("/kv", kv_routes::routes(store.clone()))
+        .nest(
+            "/kv",
+            kv_routes::routes(store.clone()).merge(collection_routes::routes(store.clone())),
+        )
+
+        // /locks/*
+        .nest("/locks", lock_routes::routes(store.clone()))
+
+        // /queues/*
+        .nest("/queues", queue_routes::routes(store))
 
         // /pubsub/*
-        .nest("/pubsub", pubsub_routes::routes())
+        .nest("/pubsub", pubsub_routes::routes(name))
+}
+
+/// Build the complete Axum application:
+/// - /kv, /pubsub          (default database)
+/// - /db/{name}/kv, ...    (named databases)
+/// - /system               (alive + version + database management)
+///
+/// `cfg` is passed to /system/version so the server can expose its version.
+pub fn build_app(registry: DbRegistry, cfg: AppConfig) -> Router {
+    let default_db = registry
+        .get(DEFAULT_DB)
+        .expect("default database must be opened before building the app");
+
+    Router::new()
+        // /kv/*, /pubsub/* (default database)
+        .merge(default_db.router.clone())
+
+        // /db/{name}/*
+        .nest("/db", db_routes::routes(registry.clone()))
 
         // /system/*
-        .nest("/system", system_routes::routes(cfg.clone()))
+        .nest("/system", system_routes::routes(cfg.clone(), registry))
 
         // Logging middleware
         .layer(
@@ -30,4 +64,4 @@ pub fn build_app(store: KvStore, cfg: AppConfig) -> Router {
                 .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
                 .on_response(DefaultOnResponse::new().level(Level::INFO)),
         )
-}
\ No newline at end of file
+}
@@ -1,5 +1,8 @@
 use serde::Deserialize;
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::state::kv::EvictionPolicy;
+use crate::state::wal::FsyncPolicy;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
@@ -21,7 +24,7 @@ pub struct AppConfig {
 
     /// Global retention window (seconds).
     ///
-    /// If set, keys older than this will be removed:
+    /// If set, keys not written for longer than this will be removed:
     /// - On startup when loading the snapshot
     /// - Periodically by a cleanup loop (see `cleanup_interval`)
     ///
@@ -30,9 +33,60 @@ pub struct AppConfig {
 
     /// How often (seconds) to run the cleanup loop.
     ///
-    /// If `None`, no cleanup loop is started and expiration only
-    /// happens on snapshot load.
+    /// The loop removes keys past their per-key expiry and, if
+    /// `retention_seconds` is set, keys older than the retention window.
+    ///
+    /// If `None`, no cleanup loop is started and expired keys are only
+    /// dropped on snapshot load (reads already hide them).
     pub cleanup_interval: Option<u64>,
+
+    /// How often (seconds) timed-out in-flight queue messages are returned
+    /// to their queues (default: 1).
+    pub queue_requeue_interval: Option<u64>,
+
+    /// Number of lock shards per database store (default: 16).
+    pub store_shards: Option<usize>,
+
+    /// Number of previous versions kept per key for GET /kv/:key/history
+    /// and rollback (default: 0, no history).
+    pub history_depth: Option<usize>,
+
+    /// Per-prefix overrides of `history_depth` (longest matching prefix
+    /// wins; a full key name configures a single key).
+    pub history_prefixes: Option<BTreeMap<String, usize>>,
+
+    /// Key prefixes whose keys expire after this many seconds without a
+    /// read or write (sliding expiry), unless written with a TTL of their
+    /// own. Longest matching prefix wins.
+    pub sliding_prefixes: Option<BTreeMap<String, u64>>,
+
+    /// Write every mutation to an append-only log next to the snapshot
+    /// (`snapshot.json` -> `snapshot.wal`) before acknowledging it, and
+    /// replay it on startup (default: false).
+    pub wal_enabled: Option<bool>,
+
+    /// When the log is fsynced: "always", "everysec" (default) or "never".
+    #[serde(default)]
+    pub wal_fsync: FsyncPolicy,
+
+    /// Approximate memory limit (bytes) of each database's keys and values.
+    ///
+    /// If `None`, stores grow without bound.
+    pub max_memory_bytes: Option<usize>,
+
+    /// What to do when a write would exceed `max_memory_bytes`:
+    /// "noeviction" (default, reject with 507), "allkeys-lru",
+    /// "allkeys-lfu" or "volatile-ttl".
+    #[serde(default)]
+    pub eviction_policy: EvictionPolicy,
+
+    /// Path to the catalog of named databases created at runtime
+    /// (default: "databases.json").
+    pub databases_path: Option<String>,
+
+    /// Path to the file recording secondary index definitions
+    /// (default: "indexes.json").
+    pub indexes_path: Option<String>,
 }
 
 impl AppConfig {
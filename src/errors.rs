@@ -1,7 +1,67 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
 use thiserror::Error;
 
+use crate::services::json_schema::Violation;
+
 #[derive(Error, Debug)]
 pub enum DodoError {
     #[error("Key not found")]
     NotFound,
-}
\ No newline at end of file
+
+    #[error("Database not found: {0}")]
+    DatabaseNotFound(String),
+
+    #[error("Precondition failed")]
+    PreconditionFailed,
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Wrong type: {0}")]
+    WrongType(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+
+    #[error("Value does not match its schema")]
+    SchemaViolation(Vec<Violation>),
+}
+
+impl DodoError {
+    /// HTTP status code used when this error reaches a route handler.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            DodoError::NotFound => StatusCode::NOT_FOUND,
+            DodoError::DatabaseNotFound(_) => StatusCode::NOT_FOUND,
+            DodoError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            DodoError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            DodoError::WrongType(_) => StatusCode::CONFLICT,
+            DodoError::Conflict(_) => StatusCode::CONFLICT,
+            DodoError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            DodoError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            DodoError::SchemaViolation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl IntoResponse for DodoError {
+    fn into_response(self) -> Response {
+        if let DodoError::SchemaViolation(violations) = &self {
+            let body = json!({ "error": self.to_string(), "violations": violations });
+            return (self.status(), Json(body)).into_response();
+        }
+
+        (self.status(), Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
@@ -0,0 +1,4 @@
+//! Storage layer of DodoDB (sharded key–value store, WAL, queues, locks
+//! and indexes), shared by the server binary and the benchmarks.
+
+pub mod state;
@@ -13,22 +13,21 @@ mod errors;
 mod persistence;
 mod routes;
 mod services;
-mod state;
+
+use dodo_db::state;
 
 use std::path::PathBuf;
 
 use tokio::net::TcpListener;
-use tokio::task;
 use axum::serve;
 
 use tracing_subscriber::FmtSubscriber;
 use tracing::level_filters::LevelFilter;
 
 use crate::config::AppConfig;
-use crate::state::kv::{new_store, KvStore};
-use crate::persistence::{load_snapshot, autosave_loop, save_snapshot, cleanup_loop};
-use crate::services::pubsub_service;
-use crate::routes::system_routes;
+use crate::services::db_service;
+use crate::state::db::{DbRegistry, DbSpec};
+use crate::state::kv::DEFAULT_DB;
 
 #[tokio::main]
 async fn main() {
@@ -63,7 +62,6 @@ async fn main() {
     // ────────────────────────────────────────────────────────
     //
     let cfg = AppConfig::load_from_file(config_path.to_str().unwrap());
-    let cfg_for_routes = cfg.clone(); // so system_routes can access version
 
     //
     // ────────────────────────────────────────────────────────
@@ -98,53 +96,28 @@ async fn main() {
 
     //
     // ────────────────────────────────────────────────────────
-    //  Create KV store and load snapshot
-    // ────────────────────────────────────────────────────────
-    //
-    let store = new_store();
-    load_snapshot(&cfg.snapshot_path, &store, cfg.retention_seconds).await;
-
+    //  Open databases
     //
-    // ────────────────────────────────────────────────────────
-    //  Start autosave loop
+    //  Each database loads its snapshot and starts its own
+    //  autosave and (optional) cleanup loops.
     // ────────────────────────────────────────────────────────
     //
-    {
-        let store_clone = store.clone();
-        let path = cfg.snapshot_path.clone();
-        let interval = cfg.snapshot_interval;
-
-        task::spawn(async move {
-            autosave_loop(path, store_clone, interval).await;
-        });
-    }
+    let registry = DbRegistry::new();
 
-    //
-    // ────────────────────────────────────────────────────────
-    //  Start cleanup loop (optional)
-    // ────────────────────────────────────────────────────────
-    //
-    if let (Some(retention), Some(clean_interval)) =
-        (cfg.retention_seconds, cfg.cleanup_interval)
-    {
-        let store_clone = store.clone();
-        tracing::info!(
-            "Starting cleanup loop: retention={}s, interval={}s",
-            retention,
-            clean_interval
-        );
-
-        task::spawn(async move {
-            cleanup_loop(store_clone, retention, clean_interval).await;
-        });
-    }
+    let default_spec = DbSpec {
+        name: DEFAULT_DB.to_string(),
+        retention_seconds: None,
+    };
+    registry.insert(db_service::open(&cfg, default_spec).await);
+
+    db_service::load_catalog(&registry, &cfg).await;
 
     //
     // ────────────────────────────────────────────────────────
     //  Build Axum app (KV + PubSub + System routes)
     // ────────────────────────────────────────────────────────
     //
-    let app = app::build_app(store.clone(), cfg.clone());
+    let app = app::build_app(registry.clone(), cfg.clone());
 
     //
     // ────────────────────────────────────────────────────────
@@ -159,7 +132,7 @@ async fn main() {
     tracing::info!("Listening on http://{}", addr);
 
     serve(listener, app)
-        .with_graceful_shutdown(shutdown(store.clone(), cfg.snapshot_path.clone()))
+        .with_graceful_shutdown(shutdown(registry.clone()))
         .await
         .expect("Server error");
 }
@@ -169,12 +142,12 @@ async fn main() {
 //  Graceful shutdown handler
 // ─────────────────────────────────────────────────────────────
 //
-async fn shutdown(store: KvStore, path: String) {
+async fn shutdown(registry: DbRegistry) {
     tokio::signal::ctrl_c()
         .await
         .expect("Failed to listen for shutdown signal");
 
-    tracing::warn!("CTRL+C received — saving snapshot…");
-    save_snapshot(&path, &store).await;
-    tracing::info!("Snapshot saved. Goodbye.");
+    tracing::warn!("CTRL+C received — saving snapshots…");
+    db_service::save_all(&registry).await;
+    tracing::info!("Snapshots saved. Goodbye.");
 }
\ No newline at end of file
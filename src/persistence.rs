@@ -1,16 +1,39 @@
-use std::{fs, io::Write};
+use std::{collections::VecDeque, fs, io::Write, path::Path};
 
 use chrono::Utc;
+use serde::Deserialize;
 use serde_json::{Map, Value};
 use tokio::time::{sleep, Duration};
 
-use crate::state::kv::{Entry, KvStore};
+use std::sync::Arc;
+
+use crate::state::kv::{json_size, next_version, observe_version, DataType, Entry, KvStore, Revision};
+use crate::state::lock::LocksDoc;
+use crate::state::queue::Queue;
+use crate::state::wal::{self, Record, Wal};
+
+/// Parse a snapshot value string back into JSON.
+///
+/// Snapshots store each value as JSON text. Text that is not valid JSON
+/// (e.g. written by an older version) is kept as a plain JSON string rather
+/// than dropped.
+fn parse_stored_value(key: &str, raw: &str) -> Arc<Value> {
+    match serde_json::from_str::<Value>(raw) {
+        Ok(v) => Arc::new(v),
+        Err(_) => {
+            tracing::warn!("Snapshot value for key '{}' is not valid JSON, loading as string", key);
+            Arc::new(Value::String(raw.to_string()))
+        }
+    }
+}
 
 /// Load snapshot from disk into memory.
 ///
 /// `retention_seconds`:
 /// - If `Some`, entries older than `now - retention_seconds` are dropped.
 /// - If `None`, everything in the snapshot is loaded.
+///
+/// Entries whose per-key `expires_at` has already passed are always dropped.
 pub async fn load_snapshot(
     path: &str,
     store: &KvStore,
@@ -43,37 +66,117 @@ pub async fn load_snapshot(
     let now = Utc::now().timestamp();
     let max_age = retention_seconds.map(|s| s as i64);
 
-    let mut kv = store.write().unwrap();
+    let mut kv = store.write_all();
     kv.clear();
 
+    let mut unversioned: Vec<String> = Vec::new();
+
     for (k, v) in obj {
-        // New format: { "value": "...", "created_at": 123456789 }
+        // History is restored even if the entry itself is gone (deleted,
+        // expired or past retention), so it can still be rolled back.
+        let history = load_history(k, v);
+        if !history.is_empty() {
+            kv.set_history(k.clone(), history);
+        }
+
+        // Deleted key that only has a history: { "deleted": true, "history": [...] }
+        if v.get("deleted").and_then(|d| d.as_bool()) == Some(true) {
+            continue;
+        }
+
+        // New format:
+        // { "value": "...", "created_at": 123456789, "updated_at": 123456789,
+        //   "expires_at": 123456789 | null, "version": 42, "write_count": 3,
+        //   "type": "json" | "list" | "set" | "hash" | "zset",
+        //   "sliding": 1800 | null, "last_accessed_at": 123456789 (sliding keys only),
+        //   "history": [{ "version": 41, "updated_at": 123456789, "type": "json", "value": "..." }] }
         if let Some(entry_obj) = v.as_object() {
-            let value = entry_obj
+            let raw = entry_obj
                 .get("value")
                 .and_then(|vv| vv.as_str())
-                .unwrap_or("")
-                .to_string();
+                .unwrap_or("");
 
             let created_at = entry_obj
                 .get("created_at")
                 .and_then(|vv| vv.as_i64())
                 .unwrap_or(now);
 
+            // Older snapshots only know when the key was last set.
+            let updated_at = entry_obj
+                .get("updated_at")
+                .and_then(|vv| vv.as_i64())
+                .unwrap_or(created_at);
+
+            let expires_at = entry_obj
+                .get("expires_at")
+                .and_then(|vv| vv.as_i64());
+
+            let version = entry_obj
+                .get("version")
+                .and_then(|vv| vv.as_u64());
+
+            let write_count = entry_obj
+                .get("write_count")
+                .and_then(|vv| vv.as_u64())
+                .unwrap_or(1);
+
+            let data_type = entry_obj
+                .get("type")
+                .and_then(|vv| DataType::deserialize(vv).ok())
+                .unwrap_or_default();
+
+            // Sliding keys keep counting idle time across restarts.
+            let sliding = entry_obj
+                .get("sliding")
+                .and_then(|vv| vv.as_u64());
+
+            let last_accessed_at = entry_obj
+                .get("last_accessed_at")
+                .and_then(|vv| vv.as_i64())
+                .unwrap_or(updated_at);
+
+            let last_active = if sliding.is_some() { last_accessed_at } else { updated_at };
+
             if let Some(max_age_sec) = max_age {
-                if now - created_at > max_age_sec {
+                if now - last_active > max_age_sec {
                     // Too old, skip
                     continue;
                 }
             }
 
-            kv.insert(
-                k.clone(),
-                Entry {
-                    value,
-                    created_at,
-                },
-            );
+            if expires_at.is_some_and(|t| t <= now)
+                || sliding.is_some_and(|secs| last_accessed_at.saturating_add_unsigned(secs) <= now)
+            {
+                continue;
+            }
+
+            // Snapshots without versions get fresh ones once all stored
+            // versions have been observed (see below).
+            match version {
+                Some(v) => observe_version(v),
+                None => unversioned.push(k.clone()),
+            }
+
+            let mut entry =
+                Entry::new(parse_stored_value(k, raw), created_at, expires_at, version.unwrap_or(0));
+            entry.updated_at = updated_at;
+            entry.write_count = write_count;
+            if sliding.is_some() {
+                entry.sliding = sliding;
+                entry.access.set_last_millis(last_accessed_at.saturating_mul(1000));
+            }
+
+            if data_type.accepts(&entry.value) {
+                entry.data_type = data_type;
+            } else {
+                tracing::warn!(
+                    "Snapshot value for key '{}' is not a valid {}, loading as json",
+                    k,
+                    data_type.name()
+                );
+            }
+
+            kv.insert(k.clone(), entry);
         }
         // Old format: "value-as-string" (no metadata)
         else if let Some(s) = v.as_str() {
@@ -87,56 +190,308 @@ pub async fn load_snapshot(
 
             kv.insert(
                 k.clone(),
-                Entry {
-                    value: s.to_string(),
-                    created_at,
-                },
+                Entry::new(parse_stored_value(k, s), created_at, None, 0),
             );
+            unversioned.push(k.clone());
         }
     }
 
+    for k in unversioned {
+        kv.modify(&k, |entry| entry.version = next_version());
+    }
+
     tracing::info!("Loaded snapshot: {} entries", kv.len());
 }
 
-/// Save the current KV state to `path`.
-///
-/// Only non-expired keys are written. Expiration is handled entirely by the
-/// cleanup loop and by `load_snapshot`, so here we simply serialize current
-/// entries.
-pub async fn save_snapshot(path: &str, store: &KvStore) {
-    let kv = store.read().unwrap();
+/// Parse the "history" array of a snapshot entry (newest first).
+fn load_history(key: &str, v: &Value) -> VecDeque<Revision> {
+    let Some(items) = v.get("history").and_then(|h| h.as_array()) else {
+        return VecDeque::new();
+    };
 
-    let mut obj = Map::new();
-    for (k, entry) in kv.iter() {
-        obj.insert(
-            k.clone(),
+    items
+        .iter()
+        .filter_map(|item| {
+            let version = item.get("version")?.as_u64()?;
+            observe_version(version);
+
+            Some(Revision {
+                version,
+                updated_at: item.get("updated_at").and_then(|t| t.as_i64()).unwrap_or(0),
+                data_type: item
+                    .get("type")
+                    .and_then(|t| DataType::deserialize(t).ok())
+                    .unwrap_or_default(),
+                value: parse_stored_value(key, item.get("value")?.as_str()?),
+            })
+        })
+        .collect()
+}
+
+/// Snapshot form of a key's history (values as JSON text, like entries).
+fn save_history(revisions: &VecDeque<Revision>) -> Value {
+    revisions
+        .iter()
+        .map(|r| {
             serde_json::json!({
-                "value": entry.value,
-                "created_at": entry.created_at,
-            }),
-        );
+                "version": r.version,
+                "updated_at": r.updated_at,
+                "type": r.data_type,
+                "value": r.value.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Write-ahead log of the database whose snapshot is at `snapshot_path`:
+/// `snapshot.json` -> `snapshot.wal`.
+pub fn wal_path(snapshot_path: &str) -> String {
+    Path::new(snapshot_path)
+        .with_extension("wal")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Queues of the database whose snapshot is at `snapshot_path`:
+/// `snapshot.json` -> `snapshot.queues.json`.
+pub fn queues_path(snapshot_path: &str) -> String {
+    let path = Path::new(snapshot_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("snapshot");
+    path.with_file_name(format!("{}.queues.json", stem))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Locks of the database whose snapshot is at `snapshot_path`:
+/// `snapshot.json` -> `snapshot.locks.json`.
+pub fn locks_path(snapshot_path: &str) -> String {
+    let path = Path::new(snapshot_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("snapshot");
+    path.with_file_name(format!("{}.locks.json", stem))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Load the leases and fencing token high-water mark saved next to the
+/// snapshot at `snapshot_path` (see `save_snapshot`).
+pub fn load_locks(snapshot_path: &str, store: &KvStore) {
+    let path = locks_path(snapshot_path);
+    let data = match fs::read_to_string(&path) {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+
+    match serde_json::from_str::<LocksDoc>(&data) {
+        Ok(doc) => {
+            let count = doc.leases.len();
+            store.locks().lock().load(doc);
+            tracing::info!("Loaded {} leases from {}", count, path);
+        }
+        Err(e) => tracing::warn!("Failed to parse locks file {}: {e}", path),
     }
+}
 
-    drop(kv); // release lock before I/O
+/// Load the queues saved next to the snapshot at `snapshot_path` (see
+/// `save_snapshot`). A queue that cannot be read is skipped.
+pub fn load_queues(snapshot_path: &str, store: &KvStore) {
+    let path = queues_path(snapshot_path);
+    let data = match fs::read_to_string(&path) {
+        Ok(d) => d,
+        Err(_) => return,
+    };
 
-    let json = match serde_json::to_string_pretty(&Value::Object(obj)) {
-        Ok(j) => j,
+    let saved: Map<String, Value> = match serde_json::from_str(&data) {
+        Ok(m) => m,
         Err(e) => {
-            tracing::warn!("Failed to serialize snapshot JSON: {e}");
+            tracing::warn!("Failed to parse queues file {}: {e}", path);
             return;
         }
     };
 
-    match fs::File::create(path) {
-        Ok(mut file) => {
-            if let Err(e) = file.write_all(json.as_bytes()) {
-                tracing::warn!("Failed to write snapshot file: {e}");
-            } else {
-                tracing::info!("Snapshot saved");
+    let mut queues = store.queues().lock();
+    let mut loaded = 0;
+    for (name, doc) in saved {
+        match Queue::deserialize(doc) {
+            Ok(queue) => {
+                queues.load(name, queue);
+                loaded += 1;
             }
+            Err(e) => tracing::warn!("Skipping queue '{}' in {}: {e}", name, path),
         }
-        Err(e) => tracing::warn!("Failed to create snapshot file: {e}"),
     }
+
+    tracing::info!("Loaded {} queues from {}", loaded, path);
+}
+
+/// Apply the records of the write-ahead log at `path` on top of the
+/// snapshot just loaded into `store` (which must not log yet).
+///
+/// Replaying records the snapshot already contains is harmless: each key
+/// record sets a key to its final state, and queue changes the saved
+/// queue already has are skipped by version, so the store ends up as it
+/// was when the last record was written.
+pub fn replay_wal(path: &str, store: &KvStore) {
+    let records = match wal::read_records(path) {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::warn!("Failed to read WAL {}: {e}", path);
+            return;
+        }
+    };
+    if records.is_empty() {
+        return;
+    }
+
+    let count = records.len();
+    for record in records {
+        apply_record(store, record);
+    }
+
+    tracing::info!("Replayed WAL {}: {} records", path, count);
+}
+
+/// Apply one WAL record to `store` (the records of a batch in order).
+fn apply_record(store: &KvStore, record: Record) {
+    match record {
+        Record::Set { key, mut entry } => {
+            observe_version(entry.version);
+            entry.size = json_size(&entry.value);
+            store.shard(&key).write().unwrap().insert(key, entry);
+        }
+        Record::Delete { key } => {
+            store.shard(&key).write().unwrap().remove(&key);
+        }
+        Record::Expire { key } | Record::Evict { key } => {
+            store.shard(&key).write().unwrap().evict(&key);
+        }
+        Record::Clear => store.write_all().clear(),
+        Record::Queue { queue, version, at, change } => {
+            store.queues().lock().replay(queue, version, at, &change);
+        }
+        Record::DropQueue { queue, version } => {
+            store.queues().lock().replay_remove(&queue, version);
+        }
+        Record::Lock { lock, grant } => {
+            store.locks().lock().replay_grant(lock, grant);
+        }
+        Record::Unlock { lock, token } => {
+            store.locks().lock().replay_release(&lock, token);
+        }
+        Record::Batch { records } => {
+            for record in records {
+                apply_record(store, record);
+            }
+        }
+    }
+}
+
+/// Save the current KV state to `path`.
+///
+/// Only non-expired keys are written. Retention is handled by the cleanup
+/// loop and by `load_snapshot`; here we only skip keys whose per-key expiry
+/// has already passed.
+///
+/// Queues and locks are saved at the same point to files next to it (see
+/// `queues_path` and `locks_path`); the queues file is removed when there
+/// are none. The locks file is always written, since it also keeps the
+/// fencing token high-water mark.
+///
+/// Files are replaced atomically (written next to them, then renamed).
+/// Once both are saved, the store's write-ahead log drops the records
+/// they cover.
+pub async fn save_snapshot(path: &str, store: &KvStore) {
+    let kv = store.read_all();
+    let queues = store.queues().lock();
+    let locks = store.locks().lock();
+    let now = Utc::now().timestamp();
+
+    // No writer can append while every shard is read-locked and the
+    // queues and locks are locked, so this is exactly where the
+    // snapshot's state ends in the log.
+    let checkpoint = store.wal().map(|wal| wal.position());
+
+    let saved_locks = locks.save(now);
+    drop(locks);
+
+    let saved_queues: Map<String, Value> = queues
+        .iter()
+        .filter_map(|(name, queue)| Some((name.clone(), serde_json::to_value(queue).ok()?)))
+        .collect();
+    drop(queues);
+
+    let mut obj = Map::new();
+    for (k, entry) in kv.iter().filter(|(_, e)| !e.is_expired(now)) {
+        let mut item = serde_json::json!({
+            "value": entry.value.to_string(),
+            "created_at": entry.created_at,
+            "updated_at": entry.updated_at,
+            "expires_at": entry.expires_at,
+            "version": entry.version,
+            "write_count": entry.write_count,
+            "type": entry.data_type,
+        });
+        if let Some(secs) = entry.sliding {
+            item["sliding"] = secs.into();
+            item["last_accessed_at"] = entry.last_active().into();
+        }
+        obj.insert(k.clone(), item);
+    }
+
+    for (k, revisions) in kv.histories() {
+        let item = obj
+            .entry(k.clone())
+            .or_insert_with(|| serde_json::json!({ "deleted": true }));
+        item["history"] = save_history(revisions);
+    }
+
+    drop(kv); // release locks before I/O
+
+    let queues_file = queues_path(path);
+    let queues_written = if saved_queues.is_empty() {
+        match fs::remove_file(&queues_file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    } else {
+        write_json(&queues_file, &Value::Object(saved_queues))
+    };
+    if let Err(e) = queues_written {
+        tracing::warn!("Failed to write queues file {}: {e}", queues_file);
+        return;
+    }
+
+    let locks_file = locks_path(path);
+    let locks_written = serde_json::to_value(&saved_locks)
+        .map_err(std::io::Error::from)
+        .and_then(|doc| write_json(&locks_file, &doc));
+    if let Err(e) = locks_written {
+        tracing::warn!("Failed to write locks file {}: {e}", locks_file);
+        return;
+    }
+
+    if let Err(e) = write_json(path, &Value::Object(obj)) {
+        tracing::warn!("Failed to write snapshot file: {e}");
+        return;
+    }
+    tracing::info!("Snapshot saved");
+
+    if let (Some(wal), Some(position)) = (store.wal(), checkpoint) {
+        if let Err(e) = wal.truncate_before(position) {
+            tracing::warn!("Failed to truncate WAL {}: {e}", wal.path().display());
+        }
+    }
+}
+
+/// Write `value` as pretty JSON to `path`, atomically (written next to
+/// it, then renamed).
+fn write_json(path: &str, value: &Value) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+
+    let tmp = format!("{}.tmp", path);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(json.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
 }
 
 /// Background task that periodically saves the snapshot.
@@ -147,12 +502,21 @@ pub async fn autosave_loop(path: String, store: KvStore, every_sec: u64) {
     }
 }
 
+/// Background task that fsyncs the write-ahead log once a second
+/// (`FsyncPolicy::EverySec`).
+pub async fn wal_sync_loop(wal: Arc<Wal>) {
+    loop {
+        sleep(Duration::from_secs(1)).await;
+        wal.sync();
+    }
+}
+
 /// Background task that periodically removes expired keys
-/// based on `retention_seconds`.
+/// based on their per-key expiry and, if set, `retention_seconds`.
 ///
-/// If `retention_seconds` is `0`, everything is immediately expired.
-pub async fn cleanup_loop(store: KvStore, retention_seconds: u64, every_sec: u64) {
-    if retention_seconds == 0 {
+/// If `retention_seconds` is `Some(0)`, everything is immediately expired.
+pub async fn cleanup_loop(store: KvStore, retention_seconds: Option<u64>, every_sec: u64) {
+    if retention_seconds == Some(0) {
         tracing::warn!(
             "cleanup_loop started with retention_seconds = 0; all keys will be removed"
         );
@@ -164,18 +528,31 @@ pub async fn cleanup_loop(store: KvStore, retention_seconds: u64, every_sec: u64
     }
 }
 
-/// Deletes entries from the store that are older than `retention_seconds`.
-fn purge_expired(store: &KvStore, retention_seconds: u64) {
+/// Deletes entries from the store whose per-key expiry has passed or that
+/// were last written more than `retention_seconds` ago (if set).
+fn purge_expired(store: &KvStore, retention_seconds: Option<u64>) {
     let now = Utc::now().timestamp();
-    let max_age = retention_seconds as i64;
+    let max_age = retention_seconds.map(|s| s as i64);
+
+    let mut removed = 0;
+    let mut after = 0;
 
-    let mut map = store.write().unwrap();
-    let before = map.len();
+    // One shard at a time: purging does not need a consistent cross-shard
+    // view, and this keeps writers to other shards unblocked.
+    for shard in store.shards() {
+        let mut map = shard.write().unwrap();
+        let before = map.len();
 
-    map.retain(|_k, entry| now - entry.created_at <= max_age);
+        // Sliding keys age from their last access, others from their
+        // last write.
+        map.retain(|_k, entry| {
+            !entry.is_expired(now)
+                && max_age.is_none_or(|max| now - entry.last_active() <= max)
+        });
 
-    let after = map.len();
-    let removed = before.saturating_sub(after);
+        after += map.len();
+        removed += before.saturating_sub(map.len());
+    }
 
     if removed > 0 {
         tracing::info!(
@@ -184,4 +561,205 @@ fn purge_expired(store: &KvStore, retention_seconds: u64) {
             after
         );
     }
-}
\ No newline at end of file
+
+    let leases = store.locks().lock().purge(now);
+    if leases > 0 {
+        tracing::info!("Cleanup: removed {} expired leases", leases);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::kv::new_store;
+    use crate::state::lock::Grant;
+    use crate::state::wal::FsyncPolicy;
+    use serde_json::json;
+
+    /// A fresh WAL path under the system temp dir.
+    fn temp_wal(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("dodo-wal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("snapshot.wal").to_string_lossy().into_owned()
+    }
+
+    fn set(store: &KvStore, key: &str, value: Value) {
+        let entry = Entry::new(Arc::new(value), Utc::now().timestamp(), None, next_version());
+        store.shard(key).write().unwrap().insert(key.to_string(), entry);
+    }
+
+    fn value(store: &KvStore, key: &str) -> Option<Value> {
+        store.shard(key).read().unwrap().get(key).map(|e| (*e.value).clone())
+    }
+
+    #[test]
+    fn replay_after_truncation_applies_only_the_tail() {
+        let path = temp_wal("truncate");
+        let wal = Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap());
+        let store = new_store("t", 4).with_wal(wal.clone());
+
+        set(&store, "a", json!(1));
+        set(&store, "b", json!(2));
+        let checkpoint = wal.position();
+        set(&store, "c", json!(3));
+        store.shard("a").write().unwrap().remove("a");
+        wal.truncate_before(checkpoint).unwrap();
+
+        // The snapshot taken at the checkpoint holds a and b.
+        let restored = new_store("t", 4);
+        set(&restored, "a", json!(1));
+        set(&restored, "b", json!(2));
+        replay_wal(&path, &restored);
+
+        assert_eq!(value(&restored, "a"), None);
+        assert_eq!(value(&restored, "b"), Some(json!(2)));
+        assert_eq!(value(&restored, "c"), Some(json!(3)));
+        assert_eq!(restored.read_all().iter().count(), 2);
+    }
+
+    fn grant(owner: &str, token: u64) -> Grant {
+        Grant {
+            owner: owner.to_string(),
+            token,
+            expires_at: i64::MAX,
+        }
+    }
+
+    #[test]
+    fn lock_tokens_keep_growing_after_a_restart() {
+        let path = temp_wal("locks");
+        let store = new_store("t", 4).with_wal(Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap()));
+        let (released, held) = {
+            let mut locks = store.locks().lock();
+            let released = locks.next_token(0);
+            locks.grant("a", grant("w1", released));
+            locks.release("a");
+            let held = locks.next_token(0);
+            locks.grant("b", grant("w2", held));
+            (released, held)
+        };
+        drop(store);
+
+        // From the log alone.
+        let restored = new_store("t", 4);
+        replay_wal(&path, &restored);
+        let mut locks = restored.locks().lock();
+        assert!(locks.get("a", 0).is_none());
+        assert_eq!(locks.get("b", 0).map(|g| g.token), Some(held));
+        assert!(locks.next_token(0) > held);
+
+        // From the snapshot form, once the log is gone.
+        let doc = serde_json::to_value(locks.save(0)).unwrap();
+        let restored = new_store("t", 4);
+        let mut locks = restored.locks().lock();
+        locks.load(LocksDoc::deserialize(doc).unwrap());
+        let next = locks.next_token(0);
+        assert!(next > held && next > released);
+    }
+
+    #[test]
+    fn replay_ignores_a_torn_last_record() {
+        let path = temp_wal("torn");
+        let store = new_store("t", 4).with_wal(Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap()));
+        set(&store, "a", json!({"n": 1}));
+        set(&store, "a", json!({"n": 2}));
+        drop(store);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"op":"set","key":"a","entry":{"value":{"n":"#).unwrap();
+        drop(file);
+
+        assert_eq!(wal::read_records(&path).unwrap().len(), 2);
+
+        let restored = new_store("t", 4);
+        replay_wal(&path, &restored);
+        assert_eq!(value(&restored, "a"), Some(json!({"n": 2})));
+    }
+
+    #[test]
+    fn records_after_a_torn_one_survive_the_next_restart() {
+        let path = temp_wal("torn-reopen");
+        let store = new_store("t", 4).with_wal(Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap()));
+        set(&store, "a", json!(1));
+        drop(store);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"op":"set","key":"b","entry":{"value":"#).unwrap();
+        drop(file);
+
+        // Restart: replay, then log new writes after the torn record.
+        let store = new_store("t", 4);
+        replay_wal(&path, &store);
+        let store = store.with_wal(Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap()));
+        set(&store, "b", json!(2));
+        set(&store, "c", json!(3));
+        drop(store);
+
+        assert_eq!(wal::read_records(&path).unwrap().len(), 3);
+
+        let restored = new_store("t", 4);
+        replay_wal(&path, &restored);
+        assert_eq!(value(&restored, "a"), Some(json!(1)));
+        assert_eq!(value(&restored, "b"), Some(json!(2)));
+        assert_eq!(value(&restored, "c"), Some(json!(3)));
+    }
+
+    #[test]
+    fn replay_applies_a_batch_all_or_nothing() {
+        let path = temp_wal("batch");
+        let store = new_store("t", 4).with_wal(Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap()));
+        set(&store, "a", json!(1));
+        set(&store, "c", json!(3));
+        let before_batch = fs::metadata(&path).unwrap().len();
+
+        store.write_all().batch(|map| {
+            let now = Utc::now().timestamp();
+            map.insert("a".to_string(), Entry::new(Arc::new(json!(10)), now, None, next_version()));
+            map.insert("b".to_string(), Entry::new(Arc::new(json!(20)), now, None, next_version()));
+            map.remove("c");
+        });
+        drop(store);
+
+        let records = wal::read_records(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(matches!(&records[2], Record::Batch { records } if records.len() == 3));
+
+        // The whole batch is replayed...
+        let restored = new_store("t", 4);
+        replay_wal(&path, &restored);
+        assert_eq!(value(&restored, "a"), Some(json!(10)));
+        assert_eq!(value(&restored, "b"), Some(json!(20)));
+        assert_eq!(value(&restored, "c"), None);
+
+        // ...or, cut in the middle by a crash, none of it.
+        let len = fs::metadata(&path).unwrap().len();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(before_batch + (len - before_batch) / 2).unwrap();
+        drop(file);
+
+        let restored = new_store("t", 4);
+        replay_wal(&path, &restored);
+        assert_eq!(value(&restored, "a"), Some(json!(1)));
+        assert_eq!(value(&restored, "b"), None);
+        assert_eq!(value(&restored, "c"), Some(json!(3)));
+    }
+
+    #[test]
+    fn replaying_twice_gives_the_same_store() {
+        let path = temp_wal("twice");
+        let store = new_store("t", 4).with_wal(Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap()));
+        set(&store, "a", json!(1));
+        set(&store, "b", json!(2));
+        store.shard("b").write().unwrap().remove("b");
+        store.write_all().clear();
+        set(&store, "c", json!(3));
+
+        let restored = new_store("t", 4);
+        replay_wal(&path, &restored);
+        replay_wal(&path, &restored);
+
+        assert_eq!(value(&restored, "a"), None);
+        assert_eq!(value(&restored, "b"), None);
+        assert_eq!(value(&restored, "c"), Some(json!(3)));
+    }
+}
@@ -0,0 +1,421 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::errors::DodoError;
+use crate::services::collection_service::{self, End, ScoredMember};
+use crate::state::kv::KvStore;
+
+/// Build the routes of the native collection types, mounted under /kv
+/// next to the plain JSON routes.
+pub fn routes(store: KvStore) -> Router {
+    Router::new()
+        .route("/:key/list/push", post(list_push))
+        .route("/:key/list/pop", post(list_pop))
+        .route("/:key/list/range", get(list_range))
+        .route("/:key/list/trim", post(list_trim))
+        .route("/:key/list/len", get(list_len))
+        .route("/:key/set/add", post(set_add))
+        .route("/:key/set/remove", post(set_remove))
+        .route("/:key/set/members", get(set_members))
+        .route("/:key/set/ismember", get(set_is_member))
+        .route("/:key/hash", get(hash_fields))
+        .route(
+            "/:key/hash/:field",
+            get(hash_get)
+                .put(hash_set)
+                .delete(hash_del),
+        )
+        .route("/:key/zset/add", post(zset_add))
+        .route("/:key/zset/incr", post(zset_incr))
+        .route("/:key/zset/rank", get(zset_rank))
+        .route("/:key/zset/range", get(zset_range))
+        .route("/:key/zset/range_by_score", get(zset_range_by_score))
+        .route("/:key/zset/remove", post(zset_remove))
+        .route("/:key/zset/remove_by_score", post(zset_remove_by_score))
+        .route("/:key/zset/len", get(zset_len))
+        .with_state(store)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// Lists
+// ─────────────────────────────────────────────────────────────
+//
+
+#[derive(Debug, Deserialize)]
+struct PushParams {
+    #[serde(default)]
+    end: End,
+}
+
+#[derive(Debug, Deserialize)]
+struct PopParams {
+    #[serde(default)]
+    end: End,
+    count: Option<usize>,
+}
+
+/// Inclusive index range; negative indices count from the end.
+#[derive(Debug, Deserialize)]
+struct RangeParams {
+    start: Option<i64>,
+    stop: Option<i64>,
+}
+
+impl RangeParams {
+    /// Defaults to the whole list.
+    fn bounds(&self) -> (i64, i64) {
+        (self.start.unwrap_or(0), self.stop.unwrap_or(-1))
+    }
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/list/push?end=front|back   body: [values...]
+// Return { "len": <new length> }
+// ─────────────────────────────────────────────────────────────
+//
+async fn list_push(
+    Path(key): Path<String>,
+    Query(params): Query<PushParams>,
+    State(store): State<KvStore>,
+    Json(values): Json<Vec<Value>>,
+) -> Result<Json<Value>, DodoError>
+{
+    let len = collection_service::list_push(&store, &key, params.end, values)?;
+    Ok(Json(json!({ "len": len })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/list/pop?end=front|back&count=<n>
+// Return the removed values (at most `count`, default 1)
+// ─────────────────────────────────────────────────────────────
+//
+async fn list_pop(
+    Path(key): Path<String>,
+    Query(params): Query<PopParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<Value>>, DodoError>
+{
+    let count = params.count.unwrap_or(1);
+    Ok(Json(collection_service::list_pop(&store, &key, params.end, count)?))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/list/range?start=0&stop=-1
+// ─────────────────────────────────────────────────────────────
+//
+async fn list_range(
+    Path(key): Path<String>,
+    Query(params): Query<RangeParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<Value>>, DodoError>
+{
+    let (start, stop) = params.bounds();
+    Ok(Json(collection_service::list_range(&store, &key, start, stop)?))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/list/trim?start=<i>&stop=<j>
+// Keep only the given range; return { "len": <new length> }
+// ─────────────────────────────────────────────────────────────
+//
+async fn list_trim(
+    Path(key): Path<String>,
+    Query(params): Query<RangeParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let (start, stop) = params.bounds();
+    let len = collection_service::list_trim(&store, &key, start, stop)?;
+    Ok(Json(json!({ "len": len })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/list/len
+// ─────────────────────────────────────────────────────────────
+//
+async fn list_len(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let len = collection_service::list_len(&store, &key)?;
+    Ok(Json(json!({ "len": len })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/set/add      body: ["member", ...]
+// POST /kv/{key}/set/remove   body: ["member", ...]
+// Return how many members were added / removed
+// ─────────────────────────────────────────────────────────────
+//
+async fn set_add(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+    Json(members): Json<Vec<String>>,
+) -> Result<Json<Value>, DodoError>
+{
+    let added = collection_service::set_add(&store, &key, members)?;
+    Ok(Json(json!({ "added": added })))
+}
+
+async fn set_remove(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+    Json(members): Json<Vec<String>>,
+) -> Result<Json<Value>, DodoError>
+{
+    let removed = collection_service::set_remove(&store, &key, members)?;
+    Ok(Json(json!({ "removed": removed })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/set/members
+// GET /kv/{key}/set/ismember?member=<m>
+// ─────────────────────────────────────────────────────────────
+//
+async fn set_members(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<Value>>, DodoError>
+{
+    Ok(Json(collection_service::set_members(&store, &key)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct MemberParams {
+    member: String,
+}
+
+async fn set_is_member(
+    Path(key): Path<String>,
+    Query(params): Query<MemberParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let is_member = collection_service::set_is_member(&store, &key, &params.member)?;
+    Ok(Json(json!({ "member": params.member, "is_member": is_member })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/hash
+// Return the field names
+// ─────────────────────────────────────────────────────────────
+//
+async fn hash_fields(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<String>>, DodoError>
+{
+    Ok(Json(collection_service::hash_fields(&store, &key)?))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/hash/{field}      Return the field value (404 if missing)
+// PUT /kv/{key}/hash/{field}      Set the field; return { "created": bool }
+// DELETE /kv/{key}/hash/{field}   Return { "deleted": bool }
+// ─────────────────────────────────────────────────────────────
+//
+async fn hash_get(
+    Path((key, field)): Path<(String, String)>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    Ok(Json(collection_service::hash_get(&store, &key, &field)?))
+}
+
+async fn hash_set(
+    Path((key, field)): Path<(String, String)>,
+    State(store): State<KvStore>,
+    Json(value): Json<Value>,
+) -> Result<Json<Value>, DodoError>
+{
+    let created = collection_service::hash_set(&store, &key, &field, value)?;
+    Ok(Json(json!({ "created": created })))
+}
+
+async fn hash_del(
+    Path((key, field)): Path<(String, String)>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let deleted = collection_service::hash_del(&store, &key, &field)?;
+    Ok(Json(json!({ "deleted": deleted })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// Sorted sets
+// ─────────────────────────────────────────────────────────────
+//
+
+#[derive(Debug, Deserialize)]
+struct ZIncrParams {
+    member: String,
+    by: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZRankParams {
+    member: String,
+    #[serde(default)]
+    rev: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZRangeParams {
+    start: Option<i64>,
+    stop: Option<i64>,
+    #[serde(default)]
+    rev: bool,
+}
+
+/// Inclusive score range, unbounded by default.
+#[derive(Debug, Deserialize)]
+struct ScoreParams {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ScoreParams {
+    fn bounds(&self) -> (f64, f64) {
+        (
+            self.min.unwrap_or(f64::NEG_INFINITY),
+            self.max.unwrap_or(f64::INFINITY),
+        )
+    }
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/zset/add   body: [{ "member": "...", "score": 1.5 }, ...]
+// Add members or update their scores; return { "added": <new members> }
+// ─────────────────────────────────────────────────────────────
+//
+async fn zset_add(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+    Json(members): Json<Vec<ScoredMember>>,
+) -> Result<Json<Value>, DodoError>
+{
+    let added = collection_service::zset_add(&store, &key, members)?;
+    Ok(Json(json!({ "added": added })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/zset/incr?member=<m>&by=<n>
+// Add n (default 1) to the member's score; return the new score
+// ─────────────────────────────────────────────────────────────
+//
+async fn zset_incr(
+    Path(key): Path<String>,
+    Query(params): Query<ZIncrParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<ScoredMember>, DodoError>
+{
+    let score = collection_service::zset_incr(&store, &key, &params.member, params.by.unwrap_or(1.0))?;
+    Ok(Json(ScoredMember { member: params.member, score }))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/zset/rank?member=<m>&rev=true
+// Return { "member", "rank", "score" } (404 if not a member)
+// rev=true ranks from the highest score (leaderboards)
+// ─────────────────────────────────────────────────────────────
+//
+async fn zset_rank(
+    Path(key): Path<String>,
+    Query(params): Query<ZRankParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let (rank, score) = collection_service::zset_rank(&store, &key, &params.member, params.rev)?;
+    Ok(Json(json!({ "member": params.member, "rank": rank, "score": score })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/zset/range?start=0&stop=-1&rev=true
+// Members by rank (inclusive, negative ranks count from the end)
+//
+// GET /kv/{key}/zset/range_by_score?min=<a>&max=<b>
+// Members with min <= score <= max, lowest first
+// ─────────────────────────────────────────────────────────────
+//
+async fn zset_range(
+    Path(key): Path<String>,
+    Query(params): Query<ZRangeParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<ScoredMember>>, DodoError>
+{
+    let (start, stop) = (params.start.unwrap_or(0), params.stop.unwrap_or(-1));
+    Ok(Json(collection_service::zset_range(&store, &key, start, stop, params.rev)?))
+}
+
+async fn zset_range_by_score(
+    Path(key): Path<String>,
+    Query(params): Query<ScoreParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<ScoredMember>>, DodoError>
+{
+    let (min, max) = params.bounds();
+    Ok(Json(collection_service::zset_range_by_score(&store, &key, min, max)?))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/zset/remove   body: ["member", ...]
+// POST /kv/{key}/zset/remove_by_score?min=<a>&max=<b>
+// Return { "removed": <count> }
+// ─────────────────────────────────────────────────────────────
+//
+async fn zset_remove(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+    Json(members): Json<Vec<String>>,
+) -> Result<Json<Value>, DodoError>
+{
+    let removed = collection_service::zset_remove(&store, &key, members)?;
+    Ok(Json(json!({ "removed": removed })))
+}
+
+async fn zset_remove_by_score(
+    Path(key): Path<String>,
+    Query(params): Query<ScoreParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let (min, max) = params.bounds();
+    let removed = collection_service::zset_remove_by_score(&store, &key, min, max)?;
+    Ok(Json(json!({ "removed": removed })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/zset/len
+// ─────────────────────────────────────────────────────────────
+//
+async fn zset_len(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let len = collection_service::zset_len(&store, &key)?;
+    Ok(Json(json!({ "len": len })))
+}
@@ -0,0 +1,64 @@
+use axum::{
+    extract::{Request, State},
+    response::{IntoResponse, Response},
+    routing::any,
+    Router,
+};
+use tower::ServiceExt;
+
+use crate::errors::DodoError;
+use crate::state::db::DbRegistry;
+
+/// Build the /db routes: /db/{name}/kv/..., /db/{name}/pubsub/...
+pub fn routes(registry: DbRegistry) -> Router {
+    Router::new()
+        .route("/:name/*rest", any(dispatch))
+        .with_state(registry)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// ANY /db/{name}/{rest}
+// Forward the request to the router of database {name},
+// as if it had been sent to /{rest}
+// ─────────────────────────────────────────────────────────────
+//
+async fn dispatch(
+    State(registry): State<DbRegistry>,
+    req: Request,
+) -> Response
+{
+    // Inside the /db nest the path is "/{name}/{rest}". Work on the raw
+    // (still percent-encoded) path so keys survive the round trip.
+    let path = req.uri().path().trim_start_matches('/');
+    let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+
+    let db = match registry.get(name) {
+        Some(db) => db,
+        None => return DodoError::DatabaseNotFound(name.to_string()).into_response(),
+    };
+
+    let uri = match req.uri().query() {
+        Some(q) => format!("/{}?{}", rest, q),
+        None => format!("/{}", rest),
+    };
+
+    // Build a fresh request so path parameters matched here do not leak
+    // into the database router.
+    let (parts, body) = req.into_parts();
+    let mut inner = Request::new(body);
+    *inner.method_mut() = parts.method;
+    *inner.version_mut() = parts.version;
+    *inner.headers_mut() = parts.headers;
+    *inner.uri_mut() = match uri.parse() {
+        Ok(u) => u,
+        Err(_) => {
+            return DodoError::InvalidRequest("invalid path".to_string()).into_response()
+        }
+    };
+
+    match db.router.clone().oneshot(inner).await {
+        Ok(resp) => resp,
+        Err(never) => match never {},
+    }
+}
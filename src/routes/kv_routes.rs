@@ -1,13 +1,30 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    routing::{get, put, delete, post},
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Json, Router,
 };
-use serde_json::Value;
+use chrono::Utc;
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
 
-use crate::state::kv::KvStore;
-use crate::services::{kv_service, pubsub_service};
+use crate::errors::DodoError;
+use crate::state::kv::{KvStore, Revision};
+use crate::services::index_service::{self, IndexHit};
+use crate::services::kv_service::{
+    self, BulkDelete, KeyDeleted, KeyPage, KeyPattern, KeyValue, KeyVersion, PatchKind, Precondition,
+    TxOp, TxOutcome,
+};
+use crate::services::query_service::{self, Query as ValueQuery, QueryPage};
 
 /// Build all KV routes under /kv
 pub fn routes(store: KvStore) -> Router {
@@ -16,122 +33,533 @@ pub fn routes(store: KvStore) -> Router {
             "/:key",
             get(get_key)
                 .put(put_key)
+                .patch(patch_key)
                 .delete(delete_key),
         )
-        .route("/", get(list_keys))
+        .route("/", get(list_keys).delete(delete_keys))
         .route("/all", get(get_all))
         .route("/all/pretty", get(get_all_pretty))
+        .route(
+            "/:key/at/*pointer",
+            get(get_key_at)
+                .put(put_key_at)
+                .delete(delete_key_at),
+        )
+        .route("/:key/meta", get(key_meta))
+        .route("/:key/history", get(key_history))
+        .route("/:key/rollback", post(rollback_key))
         .route("/:key/exists", get(key_exists))
+        .route("/:key/ttl", get(key_ttl))
+        .route("/:key/persist", post(persist_key))
+        .route("/:key/incr", post(incr_key))
+        .route("/:key/decr", post(decr_key))
+        .route("/tx", post(run_transaction))
+        .route("/mget", post(mget))
+        .route("/mset", post(mset))
+        .route("/mdel", post(mdel))
         .route("/clear", post(clear_all))
         .route("/count", get(count_keys))
+        .route("/index/:name", get(query_index))
+        .route("/query", post(run_query))
         .with_state(store)
 }
 
+//
+// ─────────────────────────────────────────────────────────────
+// ETag helpers
+// Versions are exposed as strong ETags: "<version>"
+// ─────────────────────────────────────────────────────────────
+//
+
+/// ETag header value for an entry version.
+fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Parse the entity-tag list of an If-Match / If-None-Match header.
+///
+/// Returns `None` for `*`. Tags that are not one of our versions are
+/// skipped, so they simply never match.
+fn parse_etags(raw: &str) -> Option<Vec<u64>> {
+    if raw.trim() == "*" {
+        return None;
+    }
+
+    Some(
+        raw.split(',')
+            .filter_map(|tag| {
+                let tag = tag.trim();
+                let tag = tag.strip_prefix("W/").unwrap_or(tag);
+                tag.trim_matches('"').parse::<u64>().ok()
+            })
+            .collect(),
+    )
+}
+
+/// Translate If-Match / If-None-Match into a write precondition.
+///
+/// If-Match takes precedence. For writes only `If-None-Match: *`
+/// (create-only) is supported.
+fn write_precondition(headers: &HeaderMap) -> Result<Precondition, DodoError> {
+    let header_str = |name: header::HeaderName| {
+        headers
+            .get(&name)
+            .map(|v| {
+                v.to_str()
+                    .map_err(|_| DodoError::InvalidRequest(format!("invalid {} header", name)))
+            })
+            .transpose()
+    };
+
+    if let Some(raw) = header_str(header::IF_MATCH)? {
+        return Ok(match parse_etags(raw) {
+            None => Precondition::Exists,
+            Some(versions) => Precondition::VersionIn(versions),
+        });
+    }
+
+    if let Some(raw) = header_str(header::IF_NONE_MATCH)? {
+        return match parse_etags(raw) {
+            None => Ok(Precondition::Absent),
+            Some(_) => Err(DodoError::InvalidRequest(
+                "only If-None-Match: * is supported for writes".to_string(),
+            )),
+        };
+    }
+
+    Ok(Precondition::None)
+}
+
 //
 // ─────────────────────────────────────────────────────────────
 // PUT /kv/{key}
 // Set or update value for a key
 // ─────────────────────────────────────────────────────────────
 //
-/*async fn put_key(
+/// Optional expiry for PUT /kv/{key}.
+///
+/// - `ttl`: relative, seconds from now
+/// - `expire_at`: absolute, Unix timestamp (seconds)
+/// - `sliding=true`: `ttl` counts from the last read or write instead
+///
+/// At most one of `ttl` / `expire_at` may be given.
+#[derive(Debug, Deserialize)]
+struct PutParams {
+    ttl: Option<u64>,
+    expire_at: Option<i64>,
+    #[serde(default)]
+    sliding: bool,
+}
+
+async fn put_key(
     Path(key): Path<String>,
+    Query(params): Query<PutParams>,
     State(store): State<KvStore>,
+    headers: HeaderMap,
     Json(new_value): Json<Value>,
-) -> StatusCode
+) -> Result<impl IntoResponse, DodoError>
 {
-    // Get old value (if exists)
-    let old_value = kv_service::get(&store, &key);
-
-    // Set new value (applies created_at timestamp in kv_service)
-    kv_service::set(&store, key.clone(), new_value.clone());
-
-    // Notify Pub/Sub listeners
-    pubsub_service::notify_value_changed(&key, old_value.as_ref(), &new_value).await;
+    let expiry = kv_service::resolve_expiry(params.ttl, params.expire_at, params.sliding)?;
+    let cond = write_precondition(&headers)?;
 
-    StatusCode::OK
-}*/
+    let version = kv_service::set(&store, key, new_value, expiry, &cond)?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(version))]))
+}
 
-// TEMPORARY TEST HANDLER
-async fn put_key(
+//
+// ─────────────────────────────────────────────────────────────
+// PATCH /kv/{key}
+// Content-Type: application/merge-patch+json  (RFC 7396)
+//               application/json-patch+json   (RFC 6902)
+// Returns the patched value; 409 if a JSON Patch `test` fails
+// ─────────────────────────────────────────────────────────────
+//
+async fn patch_key(
     Path(key): Path<String>,
     State(store): State<KvStore>,
-    Json(new_value): Json<Value>,
-) -> StatusCode
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, DodoError>
 {
-    // Only touch the KV store, no Pub/Sub.
-    kv_service::set(&store, key, new_value);
-    StatusCode::OK
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let kind = match content_type.as_str() {
+        "application/merge-patch+json" => PatchKind::Merge,
+        "application/json-patch+json" => PatchKind::Json,
+        other => return Err(DodoError::UnsupportedMediaType(other.to_string())),
+    };
+
+    let patch: Value = serde_json::from_slice(&body)
+        .map_err(|e| DodoError::InvalidRequest(format!("invalid patch JSON: {}", e)))?;
+
+    let cond = write_precondition(&headers)?;
+    let (value, version) = kv_service::patch(&store, &key, kind, &patch, &cond)?;
+
+    Ok(([(header::ETAG, etag(version))], Json(value)))
+}
+
+/// Longest a long-poll GET may wait.
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+/// Parse a `wait` duration: a number with an optional unit
+/// (`ms`, `s`, `m`; seconds by default), e.g. `30s`, `500ms`, `2`.
+fn parse_wait(raw: &str) -> Result<Duration, DodoError> {
+    let raw = raw.trim();
+    let (number, scale) = if let Some(n) = raw.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = raw.strip_suffix('s') {
+        (n, 1.0)
+    } else if let Some(n) = raw.strip_suffix('m') {
+        (n, 60.0)
+    } else {
+        (raw, 1.0)
+    };
+
+    match number.trim().parse::<f64>() {
+        Ok(n) if n.is_finite() && n >= 0.0 => Ok(Duration::from_secs_f64(n * scale).min(MAX_WAIT)),
+        _ => Err(DodoError::InvalidRequest(format!(
+            "invalid wait '{}' (expected e.g. 30s or 500ms)",
+            raw
+        ))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GetParams {
+    wait: Option<String>,
+    since_version: Option<u64>,
 }
 
 //
 // ─────────────────────────────────────────────────────────────
-// GET /kv/{key}
-// Return JSON value or 404
+// GET /kv/{key}?wait=<duration>&since_version=<n>
+// Return JSON value (with ETag) or 404; 304 on If-None-Match hit
+//
+// With `wait` (e.g. 30s, max 5m) the request long-polls: it answers as
+// soon as the key is newer than `since_version` (or, without it, as
+// soon as the key is written or deleted), and with 304 when `wait` runs
+// out first
 // ─────────────────────────────────────────────────────────────
 //
 async fn get_key(
     Path(key): Path<String>,
+    Query(params): Query<GetParams>,
     State(store): State<KvStore>,
-) -> Result<Json<Value>, StatusCode>
+    headers: HeaderMap,
+) -> Result<Response, DodoError>
 {
-    match kv_service::get(&store, &key) {
-        Some(value) => Ok(Json(value)),
-        None => Err(StatusCode::NOT_FOUND),
+    let entry = match params.wait.as_deref().map(parse_wait).transpose()? {
+        Some(wait) => {
+            let (entry, changed) = kv_service::get_changed(&store, &key, params.since_version, wait).await;
+            if !changed {
+                return Ok(match entry {
+                    Some(e) => (StatusCode::NOT_MODIFIED, [(header::ETAG, etag(e.version))]).into_response(),
+                    None => StatusCode::NOT_MODIFIED.into_response(),
+                });
+            }
+            entry
+        }
+        None => kv_service::get(&store, &key),
+    };
+
+    let entry = entry.ok_or(DodoError::NotFound)?;
+    let tag = [(header::ETAG, etag(entry.version))];
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(parse_etags)
+        .is_some_and(|tags| tags.is_none_or(|t| t.contains(&entry.version)));
+
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, tag).into_response());
     }
+
+    Ok((tag, Json(entry.value)).into_response())
 }
 
 //
 // ─────────────────────────────────────────────────────────────
 // DELETE /kv/{key}
-// Remove a key if it exists
+// Remove a key if it exists (honors If-Match)
 // ─────────────────────────────────────────────────────────────
 //
 async fn delete_key(
     Path(key): Path<String>,
     State(store): State<KvStore>,
-) -> StatusCode
+    headers: HeaderMap,
+) -> Result<StatusCode, DodoError>
 {
-    kv_service::delete(&store, &key);
-    StatusCode::OK
+    let cond = write_precondition(&headers)?;
+    kv_service::delete(&store, &key, &cond)?;
+    Ok(StatusCode::OK)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET    /kv/{key}/at/{json-pointer}
+// PUT    /kv/{key}/at/{json-pointer}
+// DELETE /kv/{key}/at/{json-pointer}
+// Read / replace / remove a nested part of the stored JSON.
+// e.g. /kv/user/at/address/city  ->  pointer "/address/city"
+// ─────────────────────────────────────────────────────────────
+//
+async fn get_key_at(
+    Path((key, pointer)): Path<(String, String)>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let pointer = format!("/{}", pointer);
+    Ok(Json(kv_service::get_at(&store, &key, &pointer)?))
+}
+
+async fn put_key_at(
+    Path((key, pointer)): Path<(String, String)>,
+    State(store): State<KvStore>,
+    headers: HeaderMap,
+    Json(new_value): Json<Value>,
+) -> Result<impl IntoResponse, DodoError>
+{
+    let pointer = format!("/{}", pointer);
+    let cond = write_precondition(&headers)?;
+
+    let version = kv_service::set_at(&store, &key, &pointer, new_value, &cond)?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(version))]))
+}
+
+async fn delete_key_at(
+    Path((key, pointer)): Path<(String, String)>,
+    State(store): State<KvStore>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, DodoError>
+{
+    let pointer = format!("/{}", pointer);
+    let cond = write_precondition(&headers)?;
+
+    let version = kv_service::delete_at(&store, &key, &pointer, &cond)?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(version))]))
+}
+
+/// Optional key filter shared by the listing routes.
+#[derive(Debug, Deserialize)]
+struct PrefixParams {
+    prefix: Option<String>,
+}
+
+impl PrefixParams {
+    fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or("")
+    }
+}
+
+/// Default and maximum page size for GET /kv with pagination.
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    prefix: Option<String>,
+    start_after: Option<String>,
+    limit: Option<usize>,
+    #[serde(default)]
+    with_meta: bool,
 }
 
 //
 // ─────────────────────────────────────────────────────────────
 // GET /kv
-// List keys only
+// List keys only, in lexicographic order.
+//
+// GET /kv?prefix=orders/&start_after=<cursor>&limit=100
+// With any of these parameters, return one page:
+//   { "keys": [...], "next": <cursor> | null }
+//
+// GET /kv?with_meta=true
+// Same, with each key replaced by its metadata (see GET /kv/:key/meta)
 // ─────────────────────────────────────────────────────────────
 //
 async fn list_keys(
+    Query(params): Query<ListParams>,
     State(store): State<KvStore>,
-) -> Json<Vec<String>>
+) -> Response
 {
-    Json(kv_service::list(&store))
+    if params.prefix.is_none() && params.start_after.is_none() && params.limit.is_none() {
+        let keys = kv_service::list(&store, "");
+        if params.with_meta {
+            return Json(kv_service::meta_many(&store, &keys)).into_response();
+        }
+        return Json(keys).into_response();
+    }
+
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let page: KeyPage = kv_service::list_page(
+        &store,
+        params.prefix.as_deref().unwrap_or(""),
+        params.start_after.as_deref(),
+        limit,
+    );
+
+    if params.with_meta {
+        let metas = kv_service::meta_many(&store, &page.keys);
+        return Json(json!({ "keys": metas, "next": page.next })).into_response();
+    }
+
+    Json(page).into_response()
 }
 
 //
 // ─────────────────────────────────────────────────────────────
-// GET /kv/all
-// Return full JSON object of all key/value pairs
+// GET /kv/all?prefix=<p>
+// Return full JSON object of all (matching) key/value pairs
 // ─────────────────────────────────────────────────────────────
 //
 async fn get_all(
+    Query(params): Query<PrefixParams>,
     State(store): State<KvStore>,
-) -> Json<Value>
+) -> Json<BTreeMap<String, Arc<Value>>>
 {
-    Json(kv_service::get_all(&store))
+    Json(kv_service::get_all(&store, params.prefix()))
 }
 
 //
 // ─────────────────────────────────────────────────────────────
-// GET /kv/all/pretty
+// GET /kv/all/pretty?prefix=<p>
 // Return pretty-printed JSON as text
 // ─────────────────────────────────────────────────────────────
 //
 async fn get_all_pretty(
+    Query(params): Query<PrefixParams>,
     State(store): State<KvStore>,
 ) -> String
 {
-    kv_service::get_all_pretty(&store)
+    kv_service::get_all_pretty(&store, params.prefix())
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexParams {
+    eq: Option<String>,
+    gt: Option<String>,
+    gte: Option<String>,
+    lt: Option<String>,
+    lte: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Index bounds are JSON scalars; anything that does not parse as JSON
+/// is taken as a string (`?eq=shipped` is the same as `?eq="shipped"`).
+fn parse_bound(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/index/{name}?eq=<v>
+// GET /kv/index/{name}?gte=<a>&lt=<b>&limit=100
+// Keys whose indexed field matches, ordered by field value:
+//   [{ "key": "...", "value": ... }]
+// ─────────────────────────────────────────────────────────────
+//
+async fn query_index(
+    Path(name): Path<String>,
+    Query(params): Query<IndexParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<IndexHit>>, DodoError>
+{
+    let eq = params.eq.as_deref().map(parse_bound);
+    let lower_value = params.gt.as_deref().or(params.gte.as_deref()).map(parse_bound);
+    let upper_value = params.lt.as_deref().or(params.lte.as_deref()).map(parse_bound);
+
+    let (lower, upper) = match &eq {
+        Some(v) => (Bound::Included(v), Bound::Included(v)),
+        None => {
+            let lower = match &lower_value {
+                Some(v) if params.gt.is_some() => Bound::Excluded(v),
+                Some(v) => Bound::Included(v),
+                None => Bound::Unbounded,
+            };
+            let upper = match &upper_value {
+                Some(v) if params.lt.is_some() => Bound::Excluded(v),
+                Some(v) => Bound::Included(v),
+                None => Bound::Unbounded,
+            };
+            (lower, upper)
+        }
+    };
+
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    index_service::query(&store, &name, lower, upper, limit).map(Json)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/query
+// Body: { "where"?: <predicate>, "select"?: ["/field", ...],
+//         "sort"?: [{ "field": "/field", "desc"?: true }],
+//         "limit"?: n, "offset"?: n }
+// Returns { "results": [{ "key", "value" }], "next": <offset> | null,
+//           "index": <index used> | null }
+// ─────────────────────────────────────────────────────────────
+//
+async fn run_query(
+    State(store): State<KvStore>,
+    Json(query): Json<ValueQuery>,
+) -> Result<Json<QueryPage>, DodoError>
+{
+    query_service::run(&store, &query).map(Json)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/meta
+// Return timestamps, read/write counts and size of a key
+// ─────────────────────────────────────────────────────────────
+//
+async fn key_meta(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<kv_service::KeyMeta>, DodoError>
+{
+    kv_service::meta(&store, &key).map(Json).ok_or(DodoError::NotFound)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/history
+// Previous versions, newest first: [{ version, updated_at, type, value }]
+//
+// POST /kv/{key}/rollback?version=<v>
+// Write version v back as a new version (honors If-Match)
+// ─────────────────────────────────────────────────────────────
+//
+async fn key_history(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<Revision>>, DodoError>
+{
+    kv_service::history(&store, &key).map(Json).ok_or(DodoError::NotFound)
+}
+
+#[derive(Debug, Deserialize)]
+struct RollbackParams {
+    version: u64,
+}
+
+async fn rollback_key(
+    Path(key): Path<String>,
+    Query(params): Query<RollbackParams>,
+    State(store): State<KvStore>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, DodoError>
+{
+    let cond = write_precondition(&headers)?;
+
+    let version = kv_service::rollback(&store, &key, params.version, &cond)?;
+    Ok((StatusCode::OK, [(header::ETAG, etag(version))]))
 }
 
 //
@@ -148,6 +576,273 @@ async fn key_exists(
     Json(kv_service::exists(&store, &key))
 }
 
+//
+// ─────────────────────────────────────────────────────────────
+// GET /kv/{key}/ttl
+// Return remaining TTL in seconds (null = no expiry) or 404
+// ─────────────────────────────────────────────────────────────
+//
+async fn key_ttl(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let expire_at = kv_service::expiry(&store, &key).ok_or(DodoError::NotFound)?;
+    let now = Utc::now().timestamp();
+
+    Ok(Json(json!({
+        "key": key,
+        "ttl": expire_at.map(|ts| (ts - now).max(0)),
+        "expire_at": expire_at,
+    })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/persist
+// Remove the per-key expiry so the key no longer expires
+// ─────────────────────────────────────────────────────────────
+//
+async fn persist_key(
+    Path(key): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Value>, DodoError>
+{
+    let removed = kv_service::persist(&store, &key).ok_or(DodoError::NotFound)?;
+
+    Ok(Json(json!({
+        "key": key,
+        "expiry_removed": removed,
+    })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/{key}/incr?by=<n>&path=<json-pointer>
+// POST /kv/{key}/decr?by=<n>&path=<json-pointer>
+// Atomically add to / subtract from a number; returns the new value
+// ─────────────────────────────────────────────────────────────
+//
+#[derive(Debug, Deserialize)]
+struct IncrParams {
+    /// Integer or float, defaults to 1.
+    by: Option<String>,
+    /// JSON Pointer to a nested field, defaults to the whole value.
+    path: Option<String>,
+}
+
+impl IncrParams {
+    fn amount(&self, negate: bool) -> Result<serde_json::Number, DodoError> {
+        let raw = self.by.as_deref().unwrap_or("1");
+        let invalid = || DodoError::InvalidRequest(format!("'by' is not a number: {}", raw));
+        let out_of_range = || DodoError::InvalidRequest(format!("'by' is out of range: {}", raw));
+
+        // Integers stay integers over the whole i64 and u64 range; only
+        // what is neither is read as a float. Negating must stay in i64.
+        let n = match (raw.parse::<i64>(), raw.parse::<u64>()) {
+            (Ok(i), _) if negate => {
+                Some(serde_json::Number::from(i.checked_neg().ok_or_else(out_of_range)?))
+            }
+            (Ok(i), _) => Some(serde_json::Number::from(i)),
+            (_, Ok(_)) if negate => return Err(out_of_range()),
+            (_, Ok(u)) => Some(serde_json::Number::from(u)),
+            _ => raw
+                .parse::<f64>()
+                .ok()
+                .map(|f| if negate { -f } else { f })
+                .and_then(serde_json::Number::from_f64),
+        };
+
+        n.ok_or_else(invalid)
+    }
+}
+
+async fn incr_key(
+    Path(key): Path<String>,
+    Query(params): Query<IncrParams>,
+    State(store): State<KvStore>,
+) -> Result<impl IntoResponse, DodoError>
+{
+    apply_incr(&store, &key, &params, false)
+}
+
+async fn decr_key(
+    Path(key): Path<String>,
+    Query(params): Query<IncrParams>,
+    State(store): State<KvStore>,
+) -> Result<impl IntoResponse, DodoError>
+{
+    apply_incr(&store, &key, &params, true)
+}
+
+fn apply_incr(
+    store: &KvStore,
+    key: &str,
+    params: &IncrParams,
+    negate: bool,
+) -> Result<impl IntoResponse, DodoError>
+{
+    let by = params.amount(negate)?;
+    let path = params.path.as_deref().unwrap_or("");
+
+    let (value, version) = kv_service::incr(store, key, path, &by)?;
+
+    Ok((
+        [(header::ETAG, etag(version))],
+        Json(json!({
+            "key": key,
+            "path": path,
+            "value": value,
+        })),
+    ))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/tx
+// Apply an ordered list of operations atomically.
+// 200 when committed, 409 when a check failed (nothing written)
+// ─────────────────────────────────────────────────────────────
+//
+#[derive(Debug, Deserialize)]
+struct TxRequest {
+    ops: Vec<TxOp>,
+}
+
+async fn run_transaction(
+    State(store): State<KvStore>,
+    Json(req): Json<TxRequest>,
+) -> Result<(StatusCode, Json<TxOutcome>), DodoError>
+{
+    let outcome = kv_service::transaction(&store, req.ops)?;
+
+    let status = if outcome.committed {
+        StatusCode::OK
+    } else {
+        StatusCode::CONFLICT
+    };
+
+    Ok((status, Json(outcome)))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/mget   { "keys": [...] }
+// Return [{ "key", "value" }] in request order (value null if missing)
+// ─────────────────────────────────────────────────────────────
+//
+#[derive(Debug, Deserialize)]
+struct KeysRequest {
+    keys: Vec<String>,
+}
+
+async fn mget(
+    State(store): State<KvStore>,
+    Json(req): Json<KeysRequest>,
+) -> Json<Vec<KeyValue>>
+{
+    Json(kv_service::get_many(&store, &req.keys))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/mset   { "entries": { key: value, ... } }
+// Return [{ "key", "version" }] in the order of the entries
+// 507 if the entries do not fit in the memory limit
+// ─────────────────────────────────────────────────────────────
+//
+#[derive(Debug, Deserialize)]
+struct MsetRequest {
+    #[serde(deserialize_with = "entries_in_order")]
+    entries: Vec<(String, Value)>,
+}
+
+/// Read a JSON object as its members in document order, duplicates
+/// included (a `Map` would sort them and keep only the last duplicate).
+fn entries_in_order<'de, D>(deserializer: D) -> Result<Vec<(String, Value)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Entries;
+
+    impl<'de> Visitor<'de> for Entries {
+        type Value = Vec<(String, Value)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an object of key/value pairs")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(Entries)
+}
+
+async fn mset(
+    State(store): State<KvStore>,
+    Json(req): Json<MsetRequest>,
+) -> Result<Json<Vec<KeyVersion>>, DodoError>
+{
+    let versions = kv_service::set_many(&store, req.entries)?;
+    Ok(Json(versions))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /kv/mdel   { "keys": [...] }
+// Return [{ "key", "deleted" }] in request order
+// ─────────────────────────────────────────────────────────────
+//
+async fn mdel(
+    State(store): State<KvStore>,
+    Json(req): Json<KeysRequest>,
+) -> Json<Vec<KeyDeleted>>
+{
+    Json(kv_service::delete_many(&store, &req.keys))
+}
+
+#[derive(Debug, Deserialize)]
+struct BulkDeleteParams {
+    prefix: Option<String>,
+    glob: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// DELETE /kv?prefix=<p>   or   DELETE /kv?glob=<pattern>
+// Remove every matching key; return { dry_run, count, keys }
+// With dry_run=true only report the keys that would be removed
+// ─────────────────────────────────────────────────────────────
+//
+async fn delete_keys(
+    Query(params): Query<BulkDeleteParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<BulkDelete>, DodoError>
+{
+    let pattern = match (params.prefix, params.glob) {
+        (Some(prefix), None) if !prefix.is_empty() => KeyPattern::Prefix(prefix),
+        (None, Some(glob)) if !glob.is_empty() => KeyPattern::Glob(glob),
+        (Some(_), Some(_)) => {
+            return Err(DodoError::InvalidRequest("use either prefix or glob, not both".to_string()))
+        }
+        _ => {
+            return Err(DodoError::InvalidRequest(
+                "a non-empty prefix or glob is required (POST /kv/clear removes every key)".to_string(),
+            ))
+        }
+    };
+
+    Ok(Json(kv_service::delete_matching(&store, &pattern, params.dry_run)))
+}
+
 //
 // ─────────────────────────────────────────────────────────────
 // POST /kv/clear
@@ -164,13 +859,14 @@ async fn clear_all(
 
 //
 // ─────────────────────────────────────────────────────────────
-// GET /kv/count
-// Return the number of keys stored
+// GET /kv/count?prefix=<p>
+// Return the number of (matching) keys stored
 // ─────────────────────────────────────────────────────────────
 //
 async fn count_keys(
+    Query(params): Query<PrefixParams>,
     State(store): State<KvStore>,
 ) -> Json<usize>
 {
-    Json(kv_service::count(&store))
+    Json(kv_service::count(&store, params.prefix()))
 }
\ No newline at end of file
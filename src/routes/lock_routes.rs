@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::errors::DodoError;
+use crate::services::lock_service::{self, Lease};
+use crate::state::kv::KvStore;
+
+/// Lease length (seconds) when a request does not give one.
+const DEFAULT_LOCK_TTL: u64 = 30;
+
+/// Longest a blocking acquire may wait (seconds).
+const MAX_LOCK_WAIT: f64 = 300.0;
+
+/// Build the lock routes under /locks.
+pub fn routes(store: KvStore) -> Router {
+    Router::new()
+        .route("/:name", get(inspect_lock))
+        .route("/:name/acquire", post(acquire_lock))
+        .route("/:name/renew", post(renew_lock))
+        .route("/:name/release", post(release_lock))
+        .with_state(store)
+}
+
+#[derive(Debug, Deserialize)]
+struct AcquireRequest {
+    owner: String,
+    ttl: Option<u64>,
+    /// Seconds to wait for the lock if it is held (default: fail at once).
+    wait: Option<f64>,
+}
+
+/// `owner` (and optionally the fencing `token` of its grant) identify
+/// the holder on renew / release.
+#[derive(Debug, Deserialize)]
+struct HolderRequest {
+    owner: String,
+    token: Option<u64>,
+    ttl: Option<u64>,
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /locks/{name}/acquire
+// Body: { "owner": "...", "ttl"?: <seconds>, "wait"?: <seconds> }
+// Return the lease { name, owner, token, expires_at, ttl },
+// or 409 if another owner still holds the lock after `wait`
+// ─────────────────────────────────────────────────────────────
+//
+async fn acquire_lock(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+    Json(req): Json<AcquireRequest>,
+) -> Result<Json<Lease>, DodoError>
+{
+    let wait = match req.wait {
+        Some(w) if w.is_finite() && w >= 0.0 => Some(Duration::from_secs_f64(w.min(MAX_LOCK_WAIT))),
+        Some(_) => {
+            return Err(DodoError::InvalidRequest(
+                "wait must be a non-negative number of seconds".to_string(),
+            ))
+        }
+        None => None,
+    };
+
+    let ttl = req.ttl.unwrap_or(DEFAULT_LOCK_TTL);
+    Ok(Json(lock_service::acquire(&store, &name, &req.owner, ttl, wait).await?))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /locks/{name}/renew
+// Body: { "owner": "...", "token"?: <n>, "ttl"?: <seconds> }
+// Extend the lease; 409 unless the caller holds the lock
+// ─────────────────────────────────────────────────────────────
+//
+async fn renew_lock(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+    Json(req): Json<HolderRequest>,
+) -> Result<Json<Lease>, DodoError>
+{
+    let ttl = req.ttl.unwrap_or(DEFAULT_LOCK_TTL);
+    Ok(Json(lock_service::renew(&store, &name, &req.owner, req.token, ttl)?))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /locks/{name}/release
+// Body: { "owner": "...", "token"?: <n> }
+// Return { "released": true }; 409 unless the caller holds the lock
+// ─────────────────────────────────────────────────────────────
+//
+async fn release_lock(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+    Json(req): Json<HolderRequest>,
+) -> Result<Json<Value>, DodoError>
+{
+    lock_service::release(&store, &name, &req.owner, req.token)?;
+    Ok(Json(json!({ "released": true })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /locks/{name}
+// Return the current lease, 404 if the lock is free
+// ─────────────────────────────────────────────────────────────
+//
+async fn inspect_lock(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+) -> Result<Json<Lease>, DodoError>
+{
+    lock_service::inspect(&store, &name)
+        .map(Json)
+        .ok_or(DodoError::NotFound)
+}
@@ -1,3 +1,7 @@
+pub mod collection_routes;
+pub mod db_routes;
 pub mod kv_routes;
+pub mod lock_routes;
 pub mod pubsub_routes;
+pub mod queue_routes;
 pub mod system_routes;
\ No newline at end of file
@@ -1,4 +1,5 @@
 use axum::{
+    extract::State,
     http::StatusCode,
     routing::post,
     Json,
@@ -9,22 +10,26 @@ use serde_json::Value;
 
 use crate::services::pubsub_service::{subscribe, unsubscribe, SubscribeRequest};
 
-pub fn routes() -> Router {
+/// Build the Pub/Sub routes for database `db`.
+pub fn routes(db: String) -> Router {
     Router::new()
         .route("/subscribe", post(handle_subscribe))
         .route("/unsubscribe", post(handle_unsubscribe))
+        .with_state(db)
 }
 
 async fn handle_subscribe(
+    State(db): State<String>,
     Json(req): Json<SubscribeRequest>,
 ) -> Result<Json<Value>, StatusCode> {
     // If deserialization succeeds, we always answer 200
     // so the C# client’s EnsureSuccessStatusCode() is happy.
-    let resp = subscribe(req).await;
+    let resp = subscribe(&db, req).await;
     Ok(Json(resp))
 }
 
 async fn handle_unsubscribe(
+    State(db): State<String>,
     Json(payload): Json<Value>,
 ) -> Result<Json<Value>, StatusCode> {
     let id = payload
@@ -32,6 +37,6 @@ async fn handle_unsubscribe(
         .and_then(|v| v.as_u64())
         .ok_or(StatusCode::BAD_REQUEST)?;
 
-    let resp = unsubscribe(id).await;
+    let resp = unsubscribe(&db, id).await;
     Ok(Json(resp))
 }
\ No newline at end of file
@@ -0,0 +1,194 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::errors::DodoError;
+use crate::services::queue_service::{self, QueueStats};
+use crate::state::kv::KvStore;
+use crate::state::queue::Message;
+
+/// Visibility timeout (seconds) when a dequeue does not give one.
+const DEFAULT_VISIBILITY: u64 = 30;
+
+/// Build the queue routes under /queues.
+pub fn routes(store: KvStore) -> Router {
+    Router::new()
+        .route("/", get(list_queues))
+        .route("/:name", get(queue_stats).delete(delete_queue))
+        .route("/:name/enqueue", post(enqueue))
+        .route("/:name/dequeue", post(dequeue))
+        .route("/:name/ack", post(ack))
+        .route("/:name/nack", post(nack))
+        .route("/:name/dead", get(dead_letters))
+        .route("/:name/dead/requeue", post(requeue_dead))
+        .route("/:name/settings", put(update_settings))
+        .with_state(store)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /queues/{name}/enqueue   body: [message bodies...]
+// Return { "ids": [...] }
+// ─────────────────────────────────────────────────────────────
+//
+async fn enqueue(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+    Json(bodies): Json<Vec<Value>>,
+) -> Result<Json<Value>, DodoError>
+{
+    let ids = queue_service::enqueue(&store, &name, bodies)?;
+    Ok(Json(json!({ "ids": ids })))
+}
+
+#[derive(Debug, Deserialize)]
+struct DequeueParams {
+    count: Option<usize>,
+    visibility: Option<u64>,
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /queues/{name}/dequeue?count=<n>&visibility=<seconds>
+// Return up to n messages (default 1):
+//   [{ "id", "body", "enqueued_at", "deliveries", "receipt", "visible_at" }]
+// They are redelivered unless acked before `visible_at`
+// ─────────────────────────────────────────────────────────────
+//
+async fn dequeue(
+    Path(name): Path<String>,
+    Query(params): Query<DequeueParams>,
+    State(store): State<KvStore>,
+) -> Result<Json<Vec<Message>>, DodoError>
+{
+    let count = params.count.unwrap_or(1);
+    let visibility = params.visibility.unwrap_or(DEFAULT_VISIBILITY);
+    Ok(Json(queue_service::dequeue(&store, &name, count, visibility)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct ReceiptRequest {
+    receipt: u64,
+    /// For nack: seconds before the message is visible again.
+    #[serde(default)]
+    delay: u64,
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// POST /queues/{name}/ack    body: { "receipt": <n> }
+// POST /queues/{name}/nack   body: { "receipt": <n>, "delay"?: <seconds> }
+// 409 if the receipt is no longer in flight (acked or timed out)
+// ─────────────────────────────────────────────────────────────
+//
+async fn ack(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+    Json(req): Json<ReceiptRequest>,
+) -> Result<Json<Value>, DodoError>
+{
+    queue_service::ack(&store, &name, req.receipt)?;
+    Ok(Json(json!({ "acked": true })))
+}
+
+async fn nack(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+    Json(req): Json<ReceiptRequest>,
+) -> Result<Json<Value>, DodoError>
+{
+    queue_service::nack(&store, &name, req.receipt, req.delay)?;
+    Ok(Json(json!({ "nacked": true })))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /queues
+// Return the stats of every queue, by name
+// ─────────────────────────────────────────────────────────────
+//
+async fn list_queues(
+    State(store): State<KvStore>,
+) -> Json<Vec<QueueStats>>
+{
+    Json(queue_service::list(&store))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /queues/{name}
+// Return { name, ready, in_flight, dead, max_deliveries, oldest_enqueued_at }
+// ─────────────────────────────────────────────────────────────
+//
+async fn queue_stats(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+) -> Json<QueueStats>
+{
+    Json(queue_service::stats(&store, &name))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// DELETE /queues/{name}
+// Drop the queue and all its messages; return { "deleted": bool }
+// ─────────────────────────────────────────────────────────────
+//
+async fn delete_queue(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+) -> Json<Value>
+{
+    let deleted = queue_service::delete(&store, &name);
+    Json(json!({ "deleted": deleted }))
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// GET /queues/{name}/dead
+// Messages that used up their deliveries
+//
+// POST /queues/{name}/dead/requeue
+// Move them back to the queue; return { "requeued": <n> }
+// ─────────────────────────────────────────────────────────────
+//
+async fn dead_letters(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+) -> Json<Vec<Message>>
+{
+    Json(queue_service::dead_letters(&store, &name))
+}
+
+async fn requeue_dead(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+) -> Json<Value>
+{
+    let requeued = queue_service::requeue_dead(&store, &name);
+    Json(json!({ "requeued": requeued }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SettingsRequest {
+    max_deliveries: u64,
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// PUT /queues/{name}/settings   body: { "max_deliveries": <n> }
+// Return the queue stats
+// ─────────────────────────────────────────────────────────────
+//
+async fn update_settings(
+    Path(name): Path<String>,
+    State(store): State<KvStore>,
+    Json(req): Json<SettingsRequest>,
+) -> Result<Json<QueueStats>, DodoError>
+{
+    Ok(Json(queue_service::set_max_deliveries(&store, &name, req.max_deliveries)?))
+}
@@ -1,17 +1,41 @@
 use axum::{
-    extract::State,
-    routing::get,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{delete, get},
     Json, Router,
 };
-use serde_json::json;
+use serde::Deserialize;
+use serde_json::{json, Value};
 
 use crate::config::AppConfig;
+use crate::errors::DodoError;
+use crate::services::index_service::{self, IndexInfo};
+use crate::services::schema_service::{self, SchemaRule};
+use crate::services::db_service;
+use crate::state::db::{DbRegistry, DbSpec};
+use crate::state::index::IndexDef;
+use crate::state::kv::DEFAULT_DB;
 
-pub fn routes(config: AppConfig) -> Router {
+/// Shared state for the /system routes.
+#[derive(Clone)]
+struct SystemState {
+    config: AppConfig,
+    registry: DbRegistry,
+}
+
+pub fn routes(config: AppConfig, registry: DbRegistry) -> Router {
     Router::new()
         .route("/alive", get(is_alive))
         .route("/version", get(version))
-        .with_state(config)
+        .route("/databases", get(list_databases).post(create_database))
+        .route("/databases/:name", delete(drop_database))
+        .route("/indexes", get(list_indexes).post(create_index))
+        .route("/indexes/:name", delete(drop_index))
+        .route(
+            "/schemas",
+            get(list_schemas).put(put_schema).delete(delete_schema),
+        )
+        .with_state(SystemState { config, registry })
 }
 
 /// GET /system/alive
@@ -20,8 +44,114 @@ async fn is_alive() -> &'static str {
 }
 
 /// GET /system/version
-async fn version(State(config): State<AppConfig>) -> Json<serde_json::Value> {
+async fn version(State(state): State<SystemState>) -> Json<serde_json::Value> {
     Json(json!({
-        "version": config.server_version
+        "version": state.config.server_version
     }))
-}
\ No newline at end of file
+}
+
+/// GET /system/databases
+async fn list_databases(State(state): State<SystemState>) -> Json<Vec<Value>> {
+    Json(db_service::list(&state.registry))
+}
+
+/// POST /system/databases
+/// Body: { "name": "...", "retention_seconds"?: n }
+async fn create_database(
+    State(state): State<SystemState>,
+    Json(spec): Json<DbSpec>,
+) -> Result<(StatusCode, Json<Value>), DodoError> {
+    let info = db_service::create(&state.registry, &state.config, spec).await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+/// DELETE /system/databases/{name}
+async fn drop_database(
+    State(state): State<SystemState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, DodoError> {
+    db_service::drop_database(&state.registry, &state.config, &name)?;
+    Ok(StatusCode::OK)
+}
+
+fn default_db() -> String {
+    DEFAULT_DB.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateIndexRequest {
+    #[serde(default = "default_db")]
+    db: String,
+    #[serde(flatten)]
+    def: IndexDef,
+}
+
+/// GET /system/indexes
+async fn list_indexes(State(state): State<SystemState>) -> Json<Vec<IndexInfo>> {
+    Json(index_service::list(&state.registry))
+}
+
+/// POST /system/indexes
+/// Body: { "name": "...", "prefix": "...", "field": "/json/pointer", "db"?: "..." }
+async fn create_index(
+    State(state): State<SystemState>,
+    Json(req): Json<CreateIndexRequest>,
+) -> Result<(StatusCode, Json<IndexInfo>), DodoError> {
+    let info = index_service::create(&state.registry, &state.config, &req.db, req.def)?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+#[derive(Debug, Deserialize)]
+struct DbParams {
+    #[serde(default = "default_db")]
+    db: String,
+}
+
+/// DELETE /system/indexes/{name}?db=...
+async fn drop_index(
+    State(state): State<SystemState>,
+    Path(name): Path<String>,
+    Query(params): Query<DbParams>,
+) -> Result<StatusCode, DodoError> {
+    index_service::drop_index(&state.registry, &state.config, &params.db, &name)?;
+    Ok(StatusCode::OK)
+}
+
+/// GET /system/schemas?db=...
+async fn list_schemas(Query(params): Query<DbParams>) -> Json<Vec<SchemaRule>> {
+    Json(schema_service::list(&params.db))
+}
+
+#[derive(Debug, Deserialize)]
+struct PutSchemaRequest {
+    #[serde(default = "default_db")]
+    db: String,
+    #[serde(flatten)]
+    rule: SchemaRule,
+}
+
+/// PUT /system/schemas
+/// Body: { "prefix": "...", "schema": { ... }, "db"?: "..." }
+async fn put_schema(
+    State(state): State<SystemState>,
+    Json(req): Json<PutSchemaRequest>,
+) -> Result<StatusCode, DodoError> {
+    schema_service::register(&state.registry, &req.db, req.rule)?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Debug, Deserialize)]
+struct SchemaParams {
+    #[serde(default = "default_db")]
+    db: String,
+    prefix: String,
+}
+
+/// DELETE /system/schemas?prefix=...&db=...
+async fn delete_schema(
+    State(state): State<SystemState>,
+    Query(params): Query<SchemaParams>,
+) -> Result<StatusCode, DodoError> {
+    schema_service::unregister(&state.registry, &params.db, &params.prefix)?;
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,668 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Number, Value};
+
+use crate::errors::DodoError;
+use crate::services::json_ops;
+use crate::services::kv_service::{self, Precondition};
+use crate::state::kv::{DataType, KvStore, ZScores};
+
+//
+// ─────────────────────────────────────────────────────────────
+// Lists (stored as a JSON array)
+// ─────────────────────────────────────────────────────────────
+//
+
+/// End of a list to push to / pop from.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum End {
+    Front,
+    #[default]
+    Back,
+}
+
+/// Resolve an inclusive `start..=stop` range, where negative indices
+/// count from the end (`-1` is the last element), into `lo..hi`.
+/// `None` if the range is empty.
+fn resolve_range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len.saturating_add(start) } else { start }.max(0);
+    let stop = if stop < 0 { len.saturating_add(stop) } else { stop }.min(len - 1);
+
+    (start <= stop).then(|| (start as usize, stop as usize + 1))
+}
+
+fn as_array(doc: &Value) -> &Vec<Value> {
+    // `update_typed` / `view` guarantee the shape of typed values.
+    doc.as_array().expect("typed value is an array")
+}
+
+fn as_array_mut(doc: &mut Value) -> &mut Vec<Value> {
+    doc.as_array_mut().expect("typed value is an array")
+}
+
+fn as_object(doc: &Value) -> &Map<String, Value> {
+    doc.as_object().expect("typed value is an object")
+}
+
+fn as_object_mut(doc: &mut Value) -> &mut Map<String, Value> {
+    doc.as_object_mut().expect("typed value is an object")
+}
+
+/// Push `values` to one end of a list (in order, so pushing `[a, b]` to
+/// the front leaves `b` first). Returns the new length.
+pub fn list_push(store: &KvStore, key: &str, end: End, values: Vec<Value>) -> Result<usize, DodoError> {
+    let changed = !values.is_empty();
+
+    let (len, _) = kv_service::update_typed(store, key, DataType::List, None, &Precondition::None, |doc| {
+        let list = as_array_mut(doc);
+        match end {
+            End::Back => list.extend(values),
+            End::Front => {
+                for v in values {
+                    list.insert(0, v);
+                }
+            }
+        }
+        Ok((list.len(), changed))
+    })?;
+
+    Ok(len)
+}
+
+/// Remove and return up to `count` values from one end of a list
+/// (closest to that end first).
+pub fn list_pop(store: &KvStore, key: &str, end: End, count: usize) -> Result<Vec<Value>, DodoError> {
+    let (popped, _) = kv_service::update_typed(store, key, DataType::List, None, &Precondition::None, |doc| {
+        let list = as_array_mut(doc);
+        let n = count.min(list.len());
+
+        let popped: Vec<Value> = match end {
+            End::Front => list.drain(..n).collect(),
+            End::Back => list.drain(list.len() - n..).rev().collect(),
+        };
+
+        let changed = !popped.is_empty();
+        Ok((popped, changed))
+    })?;
+
+    Ok(popped)
+}
+
+/// Values of a list between `start` and `stop` (inclusive, negative
+/// indices count from the end).
+pub fn list_range(store: &KvStore, key: &str, start: i64, stop: i64) -> Result<Vec<Value>, DodoError> {
+    kv_service::view(store, key, DataType::List, |doc| {
+        let list = as_array(doc);
+        match resolve_range(list.len(), start, stop) {
+            Some((lo, hi)) => list[lo..hi].to_vec(),
+            None => Vec::new(),
+        }
+    })
+}
+
+/// Keep only the values between `start` and `stop` (inclusive, negative
+/// indices count from the end). Returns the new length.
+pub fn list_trim(store: &KvStore, key: &str, start: i64, stop: i64) -> Result<usize, DodoError> {
+    let (len, _) = kv_service::update_typed(store, key, DataType::List, None, &Precondition::None, |doc| {
+        let list = as_array_mut(doc);
+        let before = list.len();
+
+        match resolve_range(before, start, stop) {
+            Some((lo, hi)) => {
+                list.truncate(hi);
+                list.drain(..lo);
+            }
+            None => list.clear(),
+        }
+
+        Ok((list.len(), list.len() != before))
+    })?;
+
+    Ok(len)
+}
+
+/// Number of values in a list (0 if the key is missing).
+pub fn list_len(store: &KvStore, key: &str) -> Result<usize, DodoError> {
+    kv_service::view(store, key, DataType::List, |doc| as_array(doc).len())
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// Sets (stored as a sorted JSON array of unique strings)
+// ─────────────────────────────────────────────────────────────
+//
+
+fn find_member(set: &[Value], member: &str) -> Result<usize, usize> {
+    set.binary_search_by(|v| v.as_str().unwrap_or_default().cmp(member))
+}
+
+/// Add members to a set. Returns how many were not already members.
+pub fn set_add(store: &KvStore, key: &str, members: Vec<String>) -> Result<usize, DodoError> {
+    let (added, _) = kv_service::update_typed(store, key, DataType::Set, None, &Precondition::None, |doc| {
+        let set = as_array_mut(doc);
+        let mut added = 0;
+
+        for m in members {
+            if let Err(i) = find_member(set, &m) {
+                set.insert(i, Value::String(m));
+                added += 1;
+            }
+        }
+
+        Ok((added, added > 0))
+    })?;
+
+    Ok(added)
+}
+
+/// Remove members from a set. Returns how many were members.
+pub fn set_remove(store: &KvStore, key: &str, members: Vec<String>) -> Result<usize, DodoError> {
+    let (removed, _) = kv_service::update_typed(store, key, DataType::Set, None, &Precondition::None, |doc| {
+        let set = as_array_mut(doc);
+        let mut removed = 0;
+
+        for m in members {
+            if let Ok(i) = find_member(set, &m) {
+                set.remove(i);
+                removed += 1;
+            }
+        }
+
+        Ok((removed, removed > 0))
+    })?;
+
+    Ok(removed)
+}
+
+/// All members of a set, sorted.
+pub fn set_members(store: &KvStore, key: &str) -> Result<Vec<Value>, DodoError> {
+    kv_service::view(store, key, DataType::Set, |doc| as_array(doc).clone())
+}
+
+/// Whether `member` is in the set.
+pub fn set_is_member(store: &KvStore, key: &str, member: &str) -> Result<bool, DodoError> {
+    kv_service::view(store, key, DataType::Set, |doc| {
+        find_member(as_array(doc), member).is_ok()
+    })
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// Hashes (stored as a JSON object)
+// ─────────────────────────────────────────────────────────────
+//
+
+fn field_path(field: &str) -> String {
+    json_ops::format_pointer(&[field.to_string()])
+}
+
+/// Value of one field of a hash. `NotFound` if the field is missing.
+pub fn hash_get(store: &KvStore, key: &str, field: &str) -> Result<Value, DodoError> {
+    kv_service::view(store, key, DataType::Hash, |doc| as_object(doc).get(field).cloned())?
+        .ok_or(DodoError::NotFound)
+}
+
+/// Set one field of a hash. Returns whether the field is new.
+pub fn hash_set(store: &KvStore, key: &str, field: &str, value: Value) -> Result<bool, DodoError> {
+    let path = field_path(field);
+
+    let (created, _) = kv_service::update_typed(
+        store,
+        key,
+        DataType::Hash,
+        Some(&path),
+        &Precondition::None,
+        |doc| {
+            let created = as_object_mut(doc).insert(field.to_string(), value).is_none();
+            Ok((created, true))
+        },
+    )?;
+
+    Ok(created)
+}
+
+/// Delete one field of a hash. Returns whether it existed.
+pub fn hash_del(store: &KvStore, key: &str, field: &str) -> Result<bool, DodoError> {
+    let path = field_path(field);
+
+    let (deleted, _) = kv_service::update_typed(
+        store,
+        key,
+        DataType::Hash,
+        Some(&path),
+        &Precondition::None,
+        |doc| {
+            let deleted = as_object_mut(doc).remove(field).is_some();
+            Ok((deleted, deleted))
+        },
+    )?;
+
+    Ok(deleted)
+}
+
+/// Field names of a hash, sorted.
+pub fn hash_fields(store: &KvStore, key: &str) -> Result<Vec<String>, DodoError> {
+    kv_service::view(store, key, DataType::Hash, |doc| {
+        let mut fields: Vec<String> = as_object(doc).keys().cloned().collect();
+        fields.sort();
+        fields
+    })
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// Sorted sets (stored as a JSON array of { member, score },
+// sorted by score then member)
+// ─────────────────────────────────────────────────────────────
+//
+
+/// A sorted set member with its score.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoredMember {
+    pub member: String,
+    pub score: f64,
+}
+
+impl ScoredMember {
+    fn from_value(v: &Value) -> Self {
+        ScoredMember {
+            member: v["member"].as_str().unwrap_or_default().to_string(),
+            score: v["score"].as_f64().unwrap_or_default(),
+        }
+    }
+
+    fn to_value(&self) -> Result<Value, DodoError> {
+        let score = Number::from_f64(self.score).ok_or_else(|| {
+            DodoError::InvalidRequest(format!("score of '{}' is not a finite number", self.member))
+        })?;
+        Ok(json!({ "member": self.member, "score": score }))
+    }
+}
+
+/// Order of `v` (an element of the stored array) relative to the member
+/// `member` with `score`: by score, then by member.
+fn cmp_scored(v: &Value, score: f64, member: &str) -> Ordering {
+    let v_score = v["score"].as_f64().unwrap_or_default();
+    let v_member = v["member"].as_str().unwrap_or_default();
+
+    v_score.total_cmp(&score).then_with(|| v_member.cmp(member))
+}
+
+/// The member index of `zset`, built from the array if it is missing.
+fn scores_of<'a>(zset: &[Value], scores: &'a mut Option<ZScores>) -> &'a mut ZScores {
+    scores.get_or_insert_with(|| {
+        zset.iter()
+            .map(|v| {
+                let m = ScoredMember::from_value(v);
+                (m.member, m.score)
+            })
+            .collect()
+    })
+}
+
+/// Index of `member` in the array: a binary search on its score from the
+/// member index, or a scan when there is no index yet.
+fn zset_position(zset: &[Value], scores: Option<&ZScores>, member: &str) -> Option<usize> {
+    match scores {
+        Some(scores) => {
+            let score = *scores.get(member)?;
+            zset.binary_search_by(|v| cmp_scored(v, score, member)).ok()
+        }
+        None => zset.iter().position(|v| v["member"].as_str() == Some(member)),
+    }
+}
+
+/// Insert `m` (whose array element is `value`) at its sorted position,
+/// replacing the member's previous score if any. Returns whether the
+/// member is new.
+fn zset_insert(zset: &mut Vec<Value>, scores: &mut ZScores, m: ScoredMember, value: Value) -> bool {
+    let previous = scores.insert(m.member.clone(), m.score);
+
+    if let Some(old) = previous {
+        if let Ok(i) = zset.binary_search_by(|v| cmp_scored(v, old, &m.member)) {
+            zset.remove(i);
+        }
+    }
+
+    let i = zset.partition_point(|v| cmp_scored(v, m.score, &m.member) == Ordering::Less);
+    zset.insert(i, value);
+
+    previous.is_none()
+}
+
+/// Indices of the members with `min <= score <= max` (a contiguous range).
+fn zset_score_range(zset: &[Value], min: f64, max: f64) -> (usize, usize) {
+    let score = |v: &Value| v["score"].as_f64().unwrap_or_default();
+    let lo = zset.partition_point(|v| score(v) < min);
+    let hi = zset.partition_point(|v| score(v) <= max);
+    (lo, hi.max(lo))
+}
+
+/// Add members or update their scores. Returns how many were new.
+///
+/// Every score is checked before the set is changed, so a non-finite one
+/// rejects the whole call.
+pub fn zset_add(store: &KvStore, key: &str, members: Vec<ScoredMember>) -> Result<usize, DodoError> {
+    let changed = !members.is_empty();
+    let members = members
+        .into_iter()
+        .map(|m| m.to_value().map(|value| (m, value)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (added, _) = kv_service::update_zset(store, key, |doc, scores| {
+        let zset = as_array_mut(doc);
+        let scores = scores_of(zset, scores);
+
+        let mut added = 0;
+
+        for (m, value) in members {
+            if zset_insert(zset, scores, m, value) {
+                added += 1;
+            }
+        }
+
+        Ok((added, changed))
+    })?;
+
+    Ok(added)
+}
+
+/// Add `by` to the score of `member` (a new member starts at 0).
+/// Returns the new score.
+pub fn zset_incr(store: &KvStore, key: &str, member: &str, by: f64) -> Result<f64, DodoError> {
+    let (score, _) = kv_service::update_zset(store, key, |doc, scores| {
+        let zset = as_array_mut(doc);
+        let scores = scores_of(zset, scores);
+
+        let current = scores.get(member).copied().unwrap_or(0.0);
+        let m = ScoredMember { member: member.to_string(), score: current + by };
+        let value = m.to_value()?;
+
+        let score = m.score;
+        zset_insert(zset, scores, m, value);
+        Ok((score, true))
+    })?;
+
+    Ok(score)
+}
+
+/// Rank (0-based, lowest score first, or highest first with `rev`) and
+/// score of `member`. `NotFound` if it is not in the set.
+pub fn zset_rank(store: &KvStore, key: &str, member: &str, rev: bool) -> Result<(usize, f64), DodoError> {
+    kv_service::view_zset(store, key, |doc, scores| {
+        let zset = as_array(doc);
+        zset_position(zset, scores, member).map(|i| {
+            let rank = if rev { zset.len() - 1 - i } else { i };
+            (rank, ScoredMember::from_value(&zset[i]).score)
+        })
+    })?
+    .ok_or(DodoError::NotFound)
+}
+
+/// Members between ranks `start` and `stop` (inclusive, negative ranks
+/// count from the end), lowest score first or highest first with `rev`.
+pub fn zset_range(
+    store: &KvStore,
+    key: &str,
+    start: i64,
+    stop: i64,
+    rev: bool,
+) -> Result<Vec<ScoredMember>, DodoError> {
+    kv_service::view(store, key, DataType::ZSet, |doc| {
+        let zset = as_array(doc);
+        let Some((lo, hi)) = resolve_range(zset.len(), start, stop) else {
+            return Vec::new();
+        };
+
+        let members = (lo..hi).map(|rank| {
+            let i = if rev { zset.len() - 1 - rank } else { rank };
+            ScoredMember::from_value(&zset[i])
+        });
+        members.collect()
+    })
+}
+
+/// Members with `min <= score <= max`, lowest score first.
+pub fn zset_range_by_score(store: &KvStore, key: &str, min: f64, max: f64) -> Result<Vec<ScoredMember>, DodoError> {
+    kv_service::view(store, key, DataType::ZSet, |doc| {
+        let zset = as_array(doc);
+        let (lo, hi) = zset_score_range(zset, min, max);
+        zset[lo..hi].iter().map(ScoredMember::from_value).collect()
+    })
+}
+
+/// Remove members. Returns how many were in the set.
+pub fn zset_remove(store: &KvStore, key: &str, members: Vec<String>) -> Result<usize, DodoError> {
+    let (removed, _) = kv_service::update_zset(store, key, |doc, scores| {
+        let zset = as_array_mut(doc);
+        let scores = scores_of(zset, scores);
+        let mut removed = 0;
+
+        for m in members {
+            let Some(score) = scores.remove(&m) else { continue };
+            if let Ok(i) = zset.binary_search_by(|v| cmp_scored(v, score, &m)) {
+                zset.remove(i);
+                removed += 1;
+            }
+        }
+
+        Ok((removed, removed > 0))
+    })?;
+
+    Ok(removed)
+}
+
+/// Remove the members with `min <= score <= max`. Returns how many.
+pub fn zset_remove_by_score(store: &KvStore, key: &str, min: f64, max: f64) -> Result<usize, DodoError> {
+    let (removed, _) = kv_service::update_zset(store, key, |doc, scores| {
+        let zset = as_array_mut(doc);
+        let (lo, hi) = zset_score_range(zset, min, max);
+
+        for v in zset.drain(lo..hi) {
+            if let (Some(scores), Some(member)) = (scores.as_mut(), v["member"].as_str()) {
+                scores.remove(member);
+            }
+        }
+
+        Ok((hi - lo, hi > lo))
+    })?;
+
+    Ok(removed)
+}
+
+/// Number of members (0 if the key is missing).
+pub fn zset_len(store: &KvStore, key: &str) -> Result<usize, DodoError> {
+    kv_service::view(store, key, DataType::ZSet, |doc| as_array(doc).len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence;
+    use crate::services::kv_service;
+    use crate::state::kv::new_store;
+
+    fn zm(member: &str, score: f64) -> ScoredMember {
+        ScoredMember { member: member.to_string(), score }
+    }
+
+    fn names(members: Vec<ScoredMember>) -> Vec<String> {
+        members.into_iter().map(|m| m.member).collect()
+    }
+
+    fn leaderboard(store: &KvStore) {
+        zset_add(store, "z", vec![zm("c", 3.0), zm("a", 1.0), zm("d", 4.0), zm("b", 2.0)]).unwrap();
+    }
+
+    #[test]
+    fn resolve_range_counts_negative_indices_from_the_end() {
+        assert_eq!(resolve_range(5, 0, -1), Some((0, 5)));
+        assert_eq!(resolve_range(5, -2, -1), Some((3, 5)));
+        assert_eq!(resolve_range(5, 1, 2), Some((1, 3)));
+        assert_eq!(resolve_range(5, -10, 1), Some((0, 2)));
+        assert_eq!(resolve_range(5, 2, 100), Some((2, 5)));
+        assert_eq!(resolve_range(5, 3, 1), None);
+        assert_eq!(resolve_range(5, 5, -1), None);
+        assert_eq!(resolve_range(0, 0, -1), None);
+        assert_eq!(resolve_range(5, i64::MIN, i64::MIN), None);
+        assert_eq!(resolve_range(5, i64::MIN, i64::MAX), Some((0, 5)));
+    }
+
+    #[tokio::test]
+    async fn list_push_to_the_front_reverses_the_values() {
+        let store = new_store("list-push", 4);
+        list_push(&store, "l", End::Back, vec![json!(1), json!(2)]).unwrap();
+        let len = list_push(&store, "l", End::Front, vec![json!("a"), json!("b")]).unwrap();
+
+        assert_eq!(len, 4);
+        assert_eq!(list_range(&store, "l", 0, -1).unwrap(), [json!("b"), json!("a"), json!(1), json!(2)]);
+    }
+
+    #[tokio::test]
+    async fn list_range_and_trim_with_negative_indices() {
+        let store = new_store("list-trim", 4);
+        list_push(&store, "l", End::Back, (0..6).map(|i| json!(i)).collect()).unwrap();
+
+        assert_eq!(list_range(&store, "l", -3, -2).unwrap(), [json!(3), json!(4)]);
+        assert_eq!(list_range(&store, "l", 4, 2).unwrap(), Vec::<Value>::new());
+
+        assert_eq!(list_trim(&store, "l", 1, -2).unwrap(), 4);
+        assert_eq!(list_range(&store, "l", 0, -1).unwrap(), [json!(1), json!(2), json!(3), json!(4)]);
+
+        // An empty range empties the list, which stays a list.
+        assert_eq!(list_trim(&store, "l", -1, 0).unwrap(), 0);
+        assert_eq!(list_len(&store, "l").unwrap(), 0);
+        assert!(kv_service::get(&store, "l").is_some());
+    }
+
+    #[tokio::test]
+    async fn list_pop_more_than_the_length() {
+        let store = new_store("list-pop", 4);
+        list_push(&store, "l", End::Back, vec![json!(1), json!(2), json!(3)]).unwrap();
+
+        assert_eq!(list_pop(&store, "l", End::Back, 2).unwrap(), [json!(3), json!(2)]);
+        assert_eq!(list_pop(&store, "l", End::Front, 10).unwrap(), [json!(1)]);
+        assert_eq!(list_pop(&store, "l", End::Front, 10).unwrap(), Vec::<Value>::new());
+        assert_eq!(list_pop(&store, "missing", End::Back, 1).unwrap(), Vec::<Value>::new());
+        assert!(kv_service::get(&store, "missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn operations_on_another_type_are_wrong_type() {
+        let store = new_store("wrong-type", 4);
+        list_push(&store, "l", End::Back, vec![json!(1)]).unwrap();
+
+        assert!(matches!(set_add(&store, "l", vec!["a".to_string()]), Err(DodoError::WrongType(_))));
+        assert!(matches!(zset_len(&store, "l"), Err(DodoError::WrongType(_))));
+    }
+
+    #[tokio::test]
+    async fn zset_range_and_rank_in_both_directions() {
+        let store = new_store("zset-range", 4);
+        leaderboard(&store);
+
+        assert_eq!(names(zset_range(&store, "z", 0, -1, false).unwrap()), ["a", "b", "c", "d"]);
+        assert_eq!(names(zset_range(&store, "z", 0, 1, true).unwrap()), ["d", "c"]);
+        assert_eq!(names(zset_range(&store, "z", -2, -1, true).unwrap()), ["b", "a"]);
+
+        assert_eq!(zset_rank(&store, "z", "b", false).unwrap(), (1, 2.0));
+        assert_eq!(zset_rank(&store, "z", "b", true).unwrap(), (2, 2.0));
+        assert!(matches!(zset_rank(&store, "z", "x", false), Err(DodoError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn zset_score_updates_move_members() {
+        let store = new_store("zset-update", 4);
+        leaderboard(&store);
+        let before = kv_service::get(&store, "z").unwrap().value;
+
+        assert_eq!(zset_incr(&store, "z", "a", 10.0).unwrap(), 11.0);
+        assert_eq!(zset_add(&store, "z", vec![zm("d", 0.5), zm("e", 2.0)]).unwrap(), 1);
+        assert_eq!(names(zset_range(&store, "z", 0, -1, false).unwrap()), ["d", "b", "e", "c", "a"]);
+        assert_eq!(zset_rank(&store, "z", "a", true).unwrap(), (0, 11.0));
+
+        // Earlier reads keep the value they saw.
+        assert_eq!(before.as_array().unwrap().len(), 4);
+        assert_eq!(before[0], json!({"member": "a", "score": 1.0}));
+
+        assert_eq!(zset_remove(&store, "z", vec!["b".to_string(), "b".to_string(), "x".to_string()]).unwrap(), 1);
+        assert_eq!(zset_incr(&store, "z", "b", 1.0).unwrap(), 1.0);
+        assert_eq!(zset_len(&store, "z").unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn zset_score_ranges_are_inclusive() {
+        let store = new_store("zset-scores", 4);
+        leaderboard(&store);
+
+        assert_eq!(names(zset_range_by_score(&store, "z", 2.0, 3.0).unwrap()), ["b", "c"]);
+        assert_eq!(names(zset_range_by_score(&store, "z", f64::NEG_INFINITY, 1.5).unwrap()), ["a"]);
+        assert!(zset_range_by_score(&store, "z", 3.0, 2.0).unwrap().is_empty());
+        assert!(zset_range_by_score(&store, "z", 5.0, 9.0).unwrap().is_empty());
+
+        assert_eq!(zset_remove_by_score(&store, "z", 3.0, 2.0).unwrap(), 0);
+        assert_eq!(zset_remove_by_score(&store, "z", 2.0, 3.0).unwrap(), 2);
+        assert_eq!(names(zset_range(&store, "z", 0, -1, false).unwrap()), ["a", "d"]);
+        assert!(matches!(zset_rank(&store, "z", "c", false), Err(DodoError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn zset_rejects_non_finite_scores() {
+        let store = new_store("zset-finite", 4);
+        leaderboard(&store);
+        let version = kv_service::get(&store, "z").unwrap().version;
+
+        let err = zset_add(&store, "z", vec![zm("e", 5.0), zm("f", f64::INFINITY)]);
+        assert!(matches!(err, Err(DodoError::InvalidRequest(_))));
+        assert!(matches!(zset_incr(&store, "z", "a", f64::NAN), Err(DodoError::InvalidRequest(_))));
+        assert_eq!(kv_service::get(&store, "z").unwrap().version, version);
+
+        assert_eq!(zset_incr(&store, "z", "d", f64::MAX).unwrap(), f64::MAX);
+        assert!(matches!(zset_incr(&store, "z", "d", f64::MAX), Err(DodoError::InvalidRequest(_))));
+        assert_eq!(zset_rank(&store, "z", "d", false).unwrap(), (3, f64::MAX));
+        assert_eq!(zset_len(&store, "z").unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn every_type_survives_a_snapshot() {
+        let dir = std::env::temp_dir().join(format!("dodo-collections-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot.json").to_string_lossy().into_owned();
+
+        let store = new_store("collections", 4);
+        list_push(&store, "list", End::Back, vec![json!(1), json!({"a": [2]}), json!(null)]).unwrap();
+        set_add(&store, "set", vec!["b".to_string(), "a".to_string()]).unwrap();
+        hash_set(&store, "hash", "f", json!([1, 2])).unwrap();
+        hash_set(&store, "hash", "g", json!("x")).unwrap();
+        zset_add(&store, "zset", vec![zm("x", 1.5), zm("y", -2.0), zm("w", 1.5)]).unwrap();
+        persistence::save_snapshot(&path, &store).await;
+
+        let restored = new_store("collections", 4);
+        persistence::load_snapshot(&path, &restored, None).await;
+
+        for (key, data_type) in [
+            ("list", DataType::List),
+            ("set", DataType::Set),
+            ("hash", DataType::Hash),
+            ("zset", DataType::ZSet),
+        ] {
+            let saved = kv_service::get(&store, key).unwrap();
+            let loaded = kv_service::get(&restored, key).unwrap();
+            assert_eq!(loaded.data_type, data_type);
+            assert_eq!(loaded.value, saved.value);
+            assert_eq!(loaded.version, saved.version);
+        }
+
+        // The loaded sorted set has no member index until it is written.
+        assert_eq!(zset_rank(&restored, "zset", "x", false).unwrap(), (2, 1.5));
+        assert_eq!(zset_incr(&restored, "zset", "y", 4.0).unwrap(), 2.0);
+        assert_eq!(names(zset_range(&restored, "zset", 0, -1, false).unwrap()), ["w", "x", "y"]);
+        assert!(set_is_member(&restored, "set", "a").unwrap());
+        assert_eq!(hash_get(&restored, "hash", "f").unwrap(), json!([1, 2]));
+        assert_eq!(list_pop(&restored, "list", End::Front, 1).unwrap(), [json!(1)]);
+    }
+}
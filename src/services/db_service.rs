@@ -0,0 +1,314 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tokio::task;
+
+use crate::app;
+use crate::config::AppConfig;
+use crate::errors::DodoError;
+use crate::persistence::{
+    autosave_loop, cleanup_loop, load_locks, load_queues, load_snapshot, locks_path, queues_path, replay_wal,
+    save_snapshot, wal_path, wal_sync_loop,
+};
+use crate::services::{index_service, kv_service, pubsub_service, queue_service, schema_service};
+use crate::state::db::{Database, DbRegistry, DbSpec};
+use crate::state::kv::{new_store, HistoryPolicy, KvStore, SlidingPolicy, DEFAULT_DB, DEFAULT_SHARDS};
+use crate::state::wal::{FsyncPolicy, Wal};
+
+/// Default catalog file listing the named databases.
+const DEFAULT_CATALOG_PATH: &str = "databases.json";
+
+/// Default seconds between passes of the queue requeue loop.
+const DEFAULT_REQUEUE_INTERVAL: u64 = 1;
+
+/// Names whose snapshot file would be one of the main snapshot's side
+/// files (`snapshot.schemas.json`, `snapshot.queues.json`,
+/// `snapshot.locks.json`).
+const RESERVED_NAMES: [&str; 3] = ["schemas", "queues", "locks"];
+
+/// Database names are used in URLs and file names, so keep them simple.
+fn validate_name(name: &str) -> Result<(), DodoError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if !valid {
+        return Err(DodoError::InvalidRequest(format!(
+            "invalid database name '{}' (use 1-64 of [A-Za-z0-9_-])",
+            name
+        )));
+    }
+    if RESERVED_NAMES.contains(&name) {
+        return Err(DodoError::InvalidRequest(format!("database name '{}' is reserved", name)));
+    }
+    Ok(())
+}
+
+/// Snapshot path for a named database: `snapshot.json` -> `snapshot.<name>.json`
+/// next to the main snapshot. Names are validated, so the file always
+/// stays in the main snapshot's directory and belongs to one database.
+fn default_snapshot_path(cfg: &AppConfig, name: &str) -> String {
+    let main = Path::new(&cfg.snapshot_path);
+    let stem = main.file_stem().and_then(|s| s.to_str()).unwrap_or("snapshot");
+    // Keep an extension even when the main snapshot has none, so the
+    // WAL path (`with_extension("wal")`) stays distinct from the main one.
+    let ext = main.extension().and_then(|e| e.to_str()).unwrap_or("json");
+    let file = format!("{}.{}.{}", stem, name, ext);
+
+    main.with_file_name(file).to_string_lossy().into_owned()
+}
+
+/// Open a database: create its store, indexes and schemas, load its
+/// snapshot and start its autosave / cleanup loops.
+pub async fn open(cfg: &AppConfig, spec: DbSpec) -> Arc<Database> {
+    let snapshot_path = if spec.name == DEFAULT_DB {
+        cfg.snapshot_path.clone()
+    } else {
+        default_snapshot_path(cfg, &spec.name)
+    };
+    let retention_seconds = spec.retention_seconds.or(cfg.retention_seconds);
+
+    let store = new_store(&spec.name, cfg.store_shards.unwrap_or(DEFAULT_SHARDS))
+        .with_memory_limit(cfg.max_memory_bytes, cfg.eviction_policy)
+        .with_history(HistoryPolicy {
+            default: cfg.history_depth.unwrap_or(0),
+            prefixes: cfg.history_prefixes.clone().unwrap_or_default().into_iter().collect(),
+        })
+        .with_sliding(SlidingPolicy {
+            prefixes: cfg.sliding_prefixes.clone().unwrap_or_default().into_iter().collect(),
+        });
+    index_service::load_defs(cfg, &store);
+    schema_service::load(&spec.name, &snapshot_path);
+    load_snapshot(&snapshot_path, &store, retention_seconds).await;
+    load_queues(&snapshot_path, &store);
+    load_locks(&snapshot_path, &store);
+    let store = if cfg.wal_enabled.unwrap_or(false) {
+        attach_wal(cfg, &snapshot_path, store)
+    } else {
+        store
+    };
+
+    let mut tasks = Vec::new();
+
+    //
+    // WAL fsync loop (wal_fsync = "everysec")
+    //
+    if let Some(wal) = store.wal().filter(|w| w.policy() == FsyncPolicy::EverySec).cloned() {
+        tasks.push(task::spawn(async move {
+            wal_sync_loop(wal).await;
+        }));
+    }
+
+    //
+    // Autosave loop
+    //
+    {
+        let store_clone = store.clone();
+        let path = snapshot_path.clone();
+        let interval = cfg.snapshot_interval;
+
+        tasks.push(task::spawn(async move {
+            autosave_loop(path, store_clone, interval).await;
+        }));
+    }
+
+    //
+    // Cleanup loop (optional)
+    //
+    // Runs whenever `cleanup_interval` is set: it purges keys past their
+    // per-key expiry and, if a retention is set, keys older than the
+    // retention window.
+    //
+    if let Some(clean_interval) = cfg.cleanup_interval {
+        let store_clone = store.clone();
+        tracing::info!(
+            "Starting cleanup loop for '{}': retention={:?}s, interval={}s",
+            spec.name,
+            retention_seconds,
+            clean_interval
+        );
+
+        tasks.push(task::spawn(async move {
+            cleanup_loop(store_clone, retention_seconds, clean_interval).await;
+        }));
+    }
+
+    //
+    // Queue requeue loop
+    //
+    // Returns in-flight messages whose visibility timeout passed to their
+    // queue (dequeue and ack also do it for the queue they touch).
+    //
+    {
+        let store_clone = store.clone();
+        let interval = cfg.queue_requeue_interval.unwrap_or(DEFAULT_REQUEUE_INTERVAL).max(1);
+
+        tasks.push(task::spawn(async move {
+            queue_service::requeue_loop(store_clone, interval).await;
+        }));
+    }
+
+    let router = app::database_router(store.clone());
+
+    Arc::new(Database {
+        spec,
+        store,
+        snapshot_path,
+        retention_seconds,
+        router,
+        tasks,
+    })
+}
+
+/// Replay the write-ahead log of a freshly loaded store, then log its
+/// writes from now on. If the log cannot be opened the store runs
+/// without one (snapshots only).
+fn attach_wal(cfg: &AppConfig, snapshot_path: &str, store: KvStore) -> KvStore {
+    let path = wal_path(snapshot_path);
+    replay_wal(&path, &store);
+
+    match Wal::open(&path, cfg.wal_fsync) {
+        Ok(wal) => store.with_wal(Arc::new(wal)),
+        Err(e) => {
+            tracing::error!("Failed to open WAL {}, continuing without it: {e}", path);
+            store
+        }
+    }
+}
+
+/// Create a new named database at runtime and record it in the catalog.
+pub async fn create(registry: &DbRegistry, cfg: &AppConfig, spec: DbSpec) -> Result<Value, DodoError> {
+    validate_name(&spec.name)?;
+
+    if registry.get(&spec.name).is_some() {
+        return Err(DodoError::Conflict(format!(
+            "database '{}' already exists",
+            spec.name
+        )));
+    }
+
+    let db = open(cfg, spec).await;
+    let info = describe(&db);
+
+    if !registry.insert(db) {
+        return Err(DodoError::Conflict("database already exists".to_string()));
+    }
+
+    save_catalog(registry, cfg);
+    Ok(info)
+}
+
+/// Drop a named database: stop its loops, forget its subscriptions and
+/// schemas and delete its snapshot, queues, locks and WAL files. The default database
+/// cannot be dropped.
+pub fn drop_database(registry: &DbRegistry, cfg: &AppConfig, name: &str) -> Result<(), DodoError> {
+    if name == DEFAULT_DB {
+        return Err(DodoError::InvalidRequest(
+            "the default database cannot be dropped".to_string(),
+        ));
+    }
+
+    let db = registry
+        .remove(name)
+        .ok_or_else(|| DodoError::DatabaseNotFound(name.to_string()))?;
+
+    // Stop the autosave loop first so it cannot re-create the file.
+    for t in &db.tasks {
+        t.abort();
+    }
+
+    let subs = pubsub_service::remove_database(name);
+    schema_service::remove_database(name, &db.snapshot_path);
+
+    for path in [
+        db.snapshot_path.clone(),
+        queues_path(&db.snapshot_path),
+        locks_path(&db.snapshot_path),
+        wal_path(&db.snapshot_path),
+    ] {
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove {}: {e}", path);
+            }
+        }
+    }
+
+    save_catalog(registry, cfg);
+    index_service::save_defs(registry, cfg);
+    tracing::info!("Dropped database '{}' ({} subscriptions removed)", name, subs);
+
+    Ok(())
+}
+
+/// Summary of a database for /system/databases.
+pub fn describe(db: &Database) -> Value {
+    json!({
+        "name": db.store.name(),
+        "keys": kv_service::count(&db.store, ""),
+        "snapshot_path": db.snapshot_path,
+        "retention_seconds": db.retention_seconds,
+        "subscriptions": pubsub_service::subscription_count(db.store.name()),
+        "memory": {
+            "used_bytes": db.store.used_bytes(),
+            "max_bytes": db.store.max_bytes(),
+            "eviction_policy": db.store.policy(),
+            "evicted_keys": db.store.evicted(),
+        },
+    })
+}
+
+/// Describe every open database.
+pub fn list(registry: &DbRegistry) -> Vec<Value> {
+    registry.all().iter().map(|db| describe(db)).collect()
+}
+
+fn catalog_path(cfg: &AppConfig) -> &str {
+    cfg.databases_path.as_deref().unwrap_or(DEFAULT_CATALOG_PATH)
+}
+
+/// Write the specs of all named (non-default) databases to the catalog.
+fn save_catalog(registry: &DbRegistry, cfg: &AppConfig) {
+    let specs: Vec<DbSpec> = registry
+        .all()
+        .iter()
+        .filter(|db| db.spec.name != DEFAULT_DB)
+        .map(|db| db.spec.clone())
+        .collect();
+
+    let json = serde_json::to_string_pretty(&specs).unwrap();
+    if let Err(e) = fs::write(catalog_path(cfg), json) {
+        tracing::warn!("Failed to write database catalog: {e}");
+    }
+}
+
+/// Re-open every named database listed in the catalog (startup).
+pub async fn load_catalog(registry: &DbRegistry, cfg: &AppConfig) {
+    let specs: Vec<DbSpec> = match fs::read_to_string(catalog_path(cfg)) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            tracing::warn!("Failed to parse database catalog: {e}");
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+
+    for spec in specs {
+        if validate_name(&spec.name).is_err() || spec.name == DEFAULT_DB {
+            tracing::warn!("Skipping invalid database '{}' in catalog", spec.name);
+            continue;
+        }
+
+        let db = open(cfg, spec).await;
+        registry.insert(db);
+    }
+}
+
+/// Save the snapshot of every open database (used on shutdown).
+pub async fn save_all(registry: &DbRegistry) {
+    for db in registry.all() {
+        save_snapshot(&db.snapshot_path, &db.store).await;
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::AppConfig;
+use crate::errors::DodoError;
+use crate::state::db::DbRegistry;
+use crate::state::index::{Index, IndexDef, IndexKey};
+use crate::state::kv::KvStore;
+
+/// Default file holding the index definitions of every database.
+const DEFAULT_INDEXES_PATH: &str = "indexes.json";
+
+/// Index names are used in URLs, so keep them simple.
+fn validate(def: &IndexDef) -> Result<(), DodoError> {
+    let valid_name = !def.name.is_empty()
+        && def.name.len() <= 64
+        && def
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if !valid_name {
+        return Err(DodoError::InvalidRequest(format!(
+            "invalid index name '{}' (use 1-64 of [A-Za-z0-9_-])",
+            def.name
+        )));
+    }
+
+    if !def.field.starts_with('/') {
+        return Err(DodoError::InvalidRequest(format!(
+            "index field '{}' must be a JSON Pointer starting with '/'",
+            def.field
+        )));
+    }
+
+    Ok(())
+}
+
+/// Register `def` on `store` and index the keys it already holds.
+fn install(store: &KvStore, def: IndexDef) -> bool {
+    let index = Arc::new(Index::new(def));
+
+    // Register first so writes racing with the backfill are indexed too;
+    // the backfill re-reads each shard under its lock.
+    if !store.indexes().add(index.clone()) {
+        return false;
+    }
+
+    let now = Utc::now().timestamp();
+    for shard in store.shards().iter() {
+        let map = shard.read().unwrap();
+        for (key, entry) in map.range(index.def.prefix.clone()..) {
+            if !index.covers(key) {
+                break;
+            }
+            if !entry.is_expired(now) {
+                index.update(key, Some(&entry.value));
+            }
+        }
+    }
+
+    true
+}
+
+/// Summary of an index for /system/indexes.
+#[derive(Debug, Serialize)]
+pub struct IndexInfo {
+    pub db: String,
+    #[serde(flatten)]
+    pub def: IndexDef,
+    pub keys: usize,
+}
+
+fn describe(db: &str, index: &Index) -> IndexInfo {
+    IndexInfo {
+        db: db.to_string(),
+        def: index.def.clone(),
+        keys: index.len(),
+    }
+}
+
+/// Create an index on database `db`, build it from the existing keys and
+/// record it in the definitions file.
+pub fn create(registry: &DbRegistry, cfg: &AppConfig, db: &str, def: IndexDef) -> Result<IndexInfo, DodoError> {
+    validate(&def)?;
+
+    let database = registry
+        .get(db)
+        .ok_or_else(|| DodoError::DatabaseNotFound(db.to_string()))?;
+
+    let name = def.name.clone();
+    if !install(&database.store, def) {
+        return Err(DodoError::Conflict(format!(
+            "index '{}' already exists in database '{}'",
+            name, db
+        )));
+    }
+
+    save_defs(registry, cfg);
+
+    let index = database.store.indexes().get(&name).unwrap();
+    tracing::info!("Created index '{}' on '{}' ({} keys)", name, db, index.len());
+    Ok(describe(db, &index))
+}
+
+/// Drop index `name` of database `db`.
+pub fn drop_index(registry: &DbRegistry, cfg: &AppConfig, db: &str, name: &str) -> Result<(), DodoError> {
+    let database = registry
+        .get(db)
+        .ok_or_else(|| DodoError::DatabaseNotFound(db.to_string()))?;
+
+    if database.store.indexes().remove(name).is_none() {
+        return Err(DodoError::NotFound);
+    }
+
+    save_defs(registry, cfg);
+    Ok(())
+}
+
+/// Every index of every database.
+pub fn list(registry: &DbRegistry) -> Vec<IndexInfo> {
+    registry
+        .all()
+        .iter()
+        .flat_map(|db| {
+            db.store
+                .indexes()
+                .all()
+                .iter()
+                .map(|index| describe(&db.spec.name, index))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// A key found through an index.
+#[derive(Debug, Serialize)]
+pub struct IndexHit {
+    pub key: String,
+    pub value: Arc<Value>,
+}
+
+/// Keys of `store` whose indexed field lies within `(lower, upper)`,
+/// ordered by field value then key.
+///
+/// Expired keys are skipped, so fewer than `limit` hits may be returned.
+pub fn query(
+    store: &KvStore,
+    name: &str,
+    lower: Bound<&Value>,
+    upper: Bound<&Value>,
+    limit: usize,
+) -> Result<Vec<IndexHit>, DodoError> {
+    let index = store.indexes().get(name).ok_or(DodoError::NotFound)?;
+
+    let bound = |b: Bound<&Value>| -> Result<Bound<IndexKey>, DodoError> {
+        let key = |v: &Value| {
+            IndexKey::from_value(v).ok_or_else(|| {
+                DodoError::InvalidRequest("index bounds must be scalar values".to_string())
+            })
+        };
+        Ok(match b {
+            Bound::Included(v) => Bound::Included(key(v)?),
+            Bound::Excluded(v) => Bound::Excluded(key(v)?),
+            Bound::Unbounded => Bound::Unbounded,
+        })
+    };
+    let lower = bound(lower)?;
+    let upper = bound(upper)?;
+
+    let keys = index.lookup(lower.as_ref(), upper.as_ref(), limit);
+    let now = Utc::now().timestamp();
+
+    Ok(keys
+        .into_iter()
+        .filter_map(|key| {
+            let map = store.shard(&key).read().unwrap();
+            let entry = map.get(&key).filter(|e| !e.is_expired(now))?;
+            entry.access.touch();
+            let value = entry.value.clone();
+            drop(map);
+            Some(IndexHit { key, value })
+        })
+        .collect())
+}
+
+//
+// Definitions file: { "<db>": [ { "name", "prefix", "field" }, ... ] }
+//
+
+fn indexes_path(cfg: &AppConfig) -> &str {
+    cfg.indexes_path.as_deref().unwrap_or(DEFAULT_INDEXES_PATH)
+}
+
+fn read_defs(cfg: &AppConfig) -> BTreeMap<String, Vec<IndexDef>> {
+    match fs::read_to_string(indexes_path(cfg)) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            tracing::warn!("Failed to parse index definitions: {e}");
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// Write the index definitions of all open databases.
+pub fn save_defs(registry: &DbRegistry, cfg: &AppConfig) {
+    let defs: BTreeMap<String, Vec<IndexDef>> = registry
+        .all()
+        .iter()
+        .map(|db| {
+            let defs = db.store.indexes().all().iter().map(|i| i.def.clone()).collect();
+            (db.spec.name.clone(), defs)
+        })
+        .filter(|(_, defs): &(String, Vec<IndexDef>)| !defs.is_empty())
+        .collect();
+
+    let json = serde_json::to_string_pretty(&defs).unwrap();
+    if let Err(e) = fs::write(indexes_path(cfg), json) {
+        tracing::warn!("Failed to write index definitions: {e}");
+    }
+}
+
+/// Re-create the indexes recorded for `store`'s database (startup, before
+/// its snapshot is loaded so loading fills them).
+pub fn load_defs(cfg: &AppConfig, store: &KvStore) {
+    let defs = read_defs(cfg).remove(store.name()).unwrap_or_default();
+
+    for def in defs {
+        if let Err(e) = validate(&def) {
+            tracing::warn!("Skipping index '{}' of '{}': {e}", def.name, store.name());
+            continue;
+        }
+        install(store, def);
+    }
+}
@@ -0,0 +1,431 @@
+use serde_json::{Map, Number, Value};
+
+use crate::errors::DodoError;
+use crate::state::index::number_cmp;
+
+/// Split an RFC 6901 JSON Pointer into unescaped reference tokens.
+///
+/// `""` addresses the whole document, every other pointer must start
+/// with `/`.
+pub fn parse_pointer(pointer: &str) -> Result<Vec<String>, DodoError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let rest = pointer.strip_prefix('/').ok_or_else(|| {
+        DodoError::InvalidRequest(format!("JSON Pointer must start with '/': {}", pointer))
+    })?;
+
+    Ok(rest
+        .split('/')
+        .map(|t| t.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+/// Resolve `tokens` inside `doc`, creating missing object members on the
+/// way (a `null` parent becomes an empty object).
+///
+/// Array indices must already exist, except `-` which appends a `null`.
+pub fn pointer_mut_or_create<'a>(
+    doc: &'a mut Value,
+    tokens: &[String],
+) -> Result<&'a mut Value, DodoError> {
+    let mut current = doc;
+
+    for token in tokens {
+        if current.is_null() {
+            *current = Value::Object(Map::new());
+        }
+
+        current = match current {
+            Value::Object(obj) => obj.entry(token.clone()).or_insert(Value::Null),
+            Value::Array(arr) => {
+                let idx = if token == "-" {
+                    arr.push(Value::Null);
+                    arr.len() - 1
+                } else {
+                    array_index(token, arr.len())?
+                };
+                &mut arr[idx]
+            }
+            _ => {
+                return Err(DodoError::WrongType(format!(
+                    "cannot descend into a scalar at '{}'",
+                    token
+                )))
+            }
+        };
+    }
+
+    Ok(current)
+}
+
+/// Parse an existing array index (no leading zeros, in range).
+fn array_index(token: &str, len: usize) -> Result<usize, DodoError> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+
+    match token.parse::<usize>() {
+        Ok(i) if valid && i < len => Ok(i),
+        _ => Err(DodoError::InvalidRequest(format!(
+            "array index '{}' out of range",
+            token
+        ))),
+    }
+}
+
+/// Add `by` to the number in `target` (a `null` target counts as 0).
+///
+/// Integer + integer stays an integer over the whole `i64` and `u64`
+/// range (a result outside both is an error), anything involving a float
+/// produces a float. Overflow and non-finite results are
+/// `InvalidRequest`: the stored value is fine, the increment is not.
+pub fn increment(target: &mut Value, by: &Number) -> Result<Number, DodoError> {
+    let current = match target {
+        Value::Null => Number::from(0),
+        Value::Number(n) => n.clone(),
+        other => {
+            return Err(DodoError::WrongType(format!(
+                "value is not a number: {}",
+                other
+            )))
+        }
+    };
+
+    let result = match (integer(&current), integer(by)) {
+        (Some(a), Some(b)) => {
+            let sum = a + b;
+            if let Ok(i) = i64::try_from(sum) {
+                Number::from(i)
+            } else if let Ok(u) = u64::try_from(sum) {
+                Number::from(u)
+            } else {
+                return Err(DodoError::InvalidRequest("integer overflow".to_string()));
+            }
+        }
+        _ => {
+            let sum = current.as_f64().unwrap_or(0.0) + by.as_f64().unwrap_or(0.0);
+            Number::from_f64(sum).ok_or_else(|| {
+                DodoError::InvalidRequest("result is not a finite number".to_string())
+            })?
+        }
+    };
+
+    *target = Value::Number(result.clone());
+    Ok(result)
+}
+
+/// A JSON integer (signed or unsigned), widened so any two can be added.
+fn integer(n: &Number) -> Option<i128> {
+    n.as_i64()
+        .map(i128::from)
+        .or_else(|| n.as_u64().map(i128::from))
+}
+
+/// Resolve `tokens` inside `doc` without creating anything.
+pub fn pointer_get<'a>(doc: &'a Value, tokens: &[String]) -> Option<&'a Value> {
+    tokens.iter().try_fold(doc, |current, token| match current {
+        Value::Object(obj) => obj.get(token),
+        Value::Array(arr) => array_index(token, arr.len()).ok().map(|i| &arr[i]),
+        _ => None,
+    })
+}
+
+/// Mutable counterpart of `pointer_get`.
+pub fn pointer_get_mut<'a>(doc: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    tokens.iter().try_fold(doc, |current, token| match current {
+        Value::Object(obj) => obj.get_mut(token),
+        Value::Array(arr) => {
+            let len = arr.len();
+            array_index(token, len).ok().map(move |i| &mut arr[i])
+        }
+        _ => None,
+    })
+}
+
+/// JSON equality with numbers compared by value (`1 == 1.0`, see
+/// `number_cmp`).
+pub fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => number_cmp(x, y).is_eq(),
+        (Value::Array(x), Value::Array(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(a, b)| json_eq(a, b))
+        }
+        (Value::Object(x), Value::Object(y)) => {
+            x.len() == y.len() && x.iter().all(|(k, a)| y.get(k).is_some_and(|b| json_eq(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+/// Apply an RFC 7396 JSON Merge Patch to `target`.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch_obj) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+
+    if let Value::Object(target_obj) = target {
+        for (k, v) in patch_obj {
+            if v.is_null() {
+                target_obj.remove(k);
+            } else {
+                merge_patch(target_obj.entry(k.clone()).or_insert(Value::Null), v);
+            }
+        }
+    }
+}
+
+/// Apply an RFC 6902 JSON Patch (array of operations) to `doc`.
+///
+/// Operations are applied in order; on the first error `doc` may be
+/// partially modified, so callers should work on a copy.
+///
+/// - Malformed operations are `InvalidRequest`.
+/// - A failed `test` or a path that does not exist is a `Conflict`.
+///   `test` compares numbers by value (`1` equals `1.0`).
+pub fn apply_json_patch(doc: &mut Value, patch: &Value) -> Result<(), DodoError> {
+    let ops = patch
+        .as_array()
+        .ok_or_else(|| DodoError::InvalidRequest("JSON Patch must be an array".to_string()))?;
+
+    for (i, op) in ops.iter().enumerate() {
+        let field = |name: &str| {
+            op.get(name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| {
+                    DodoError::InvalidRequest(format!("patch op {} is missing '{}'", i, name))
+                })
+        };
+        let value = || {
+            op.get("value").cloned().ok_or_else(|| {
+                DodoError::InvalidRequest(format!("patch op {} is missing 'value'", i))
+            })
+        };
+
+        let path = parse_pointer(field("path")?)?;
+
+        match field("op")? {
+            "add" => patch_add(doc, &path, value()?)?,
+            "remove" => {
+                patch_remove(doc, &path)?;
+            }
+            "replace" => {
+                let target = pointer_get_mut(doc, &path).ok_or_else(|| missing_path(&path))?;
+                *target = value()?;
+            }
+            "move" => {
+                let from = parse_pointer(field("from")?)?;
+                if path.len() > from.len() && path[..from.len()] == from[..] {
+                    return Err(DodoError::InvalidRequest(
+                        "cannot move a value into one of its children".to_string(),
+                    ));
+                }
+                let moved = patch_remove(doc, &from)?;
+                patch_add(doc, &path, moved)?;
+            }
+            "copy" => {
+                let from = parse_pointer(field("from")?)?;
+                let copied = pointer_get(doc, &from)
+                    .cloned()
+                    .ok_or_else(|| missing_path(&from))?;
+                patch_add(doc, &path, copied)?;
+            }
+            "test" => {
+                let expected = value()?;
+                if !pointer_get(doc, &path).is_some_and(|v| json_eq(v, &expected)) {
+                    return Err(DodoError::Conflict(format!(
+                        "test failed at '{}'",
+                        format_pointer(&path)
+                    )));
+                }
+            }
+            other => {
+                return Err(DodoError::InvalidRequest(format!(
+                    "unknown patch op '{}'",
+                    other
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-escape reference tokens into a JSON Pointer string.
+pub fn format_pointer(tokens: &[String]) -> String {
+    tokens
+        .iter()
+        .map(|t| format!("/{}", t.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+fn missing_path(tokens: &[String]) -> DodoError {
+    DodoError::Conflict(format!("path '{}' does not exist", format_pointer(tokens)))
+}
+
+/// RFC 6902 "add": insert into an object or array, or replace the root.
+pub fn patch_add(doc: &mut Value, path: &[String], value: Value) -> Result<(), DodoError> {
+    let Some((last, parent_path)) = path.split_last() else {
+        *doc = value;
+        return Ok(());
+    };
+
+    let parent = pointer_get_mut(doc, parent_path).ok_or_else(|| missing_path(parent_path))?;
+
+    match parent {
+        Value::Object(obj) => {
+            obj.insert(last.clone(), value);
+        }
+        Value::Array(arr) => {
+            if last == "-" {
+                arr.push(value);
+            } else {
+                // Inserting at `len` (append) is allowed for add.
+                let idx = array_index(last, arr.len() + 1)?;
+                arr.insert(idx, value);
+            }
+        }
+        _ => return Err(missing_path(path)),
+    }
+
+    Ok(())
+}
+
+/// RFC 6902 "remove": take the value out of its parent and return it.
+pub fn patch_remove(doc: &mut Value, path: &[String]) -> Result<Value, DodoError> {
+    let Some((last, parent_path)) = path.split_last() else {
+        return Err(DodoError::InvalidRequest(
+            "cannot remove the whole document".to_string(),
+        ));
+    };
+
+    let parent = pointer_get_mut(doc, parent_path).ok_or_else(|| missing_path(parent_path))?;
+
+    match parent {
+        Value::Object(obj) => obj.remove(last).ok_or_else(|| missing_path(path)),
+        Value::Array(arr) => {
+            let idx = array_index(last, arr.len()).map_err(|_| missing_path(path))?;
+            Ok(arr.remove(idx))
+        }
+        _ => Err(missing_path(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn incr(target: Value, by: Value) -> Result<Value, DodoError> {
+        let mut target = target;
+        let Value::Number(by) = by else { panic!("not a number") };
+        increment(&mut target, &by)?;
+        Ok(target)
+    }
+
+    #[test]
+    fn increment_keeps_integers() {
+        assert_eq!(incr(Value::Null, json!(5)).unwrap(), json!(5));
+        assert_eq!(incr(json!(-3), json!(1)).unwrap(), json!(-2));
+        assert_eq!(incr(json!(1), json!(0.5)).unwrap(), json!(1.5));
+    }
+
+    #[test]
+    fn increment_covers_the_u64_range() {
+        assert_eq!(incr(json!(i64::MAX), json!(1)).unwrap(), json!(i64::MAX as u64 + 1));
+        assert_eq!(incr(json!(u64::MAX), json!(-1)).unwrap(), json!(u64::MAX - 1));
+        assert_eq!(incr(json!(u64::MAX), json!(i64::MIN)).unwrap(), json!(i64::MAX as u64));
+    }
+
+    #[test]
+    fn increment_overflow_is_invalid_request() {
+        assert!(matches!(
+            incr(json!(u64::MAX), json!(1)),
+            Err(DodoError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            incr(json!(i64::MIN), json!(-1)),
+            Err(DodoError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            incr(json!(f64::MAX), json!(f64::MAX)),
+            Err(DodoError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            incr(json!("a"), json!(1)),
+            Err(DodoError::WrongType(_))
+        ));
+    }
+
+    fn patched(doc: Value, patch: Value) -> Result<Value, DodoError> {
+        let mut doc = doc;
+        apply_json_patch(&mut doc, &patch)?;
+        Ok(doc)
+    }
+
+    #[test]
+    fn patch_test_compares_by_value() {
+        let doc = json!({"a": {"n": 1, "list": [1, 2.0]}});
+        assert!(patched(doc.clone(), json!([{"op": "test", "path": "/a/n", "value": 1.0}])).is_ok());
+        assert!(patched(doc.clone(), json!([{"op": "test", "path": "/a", "value": {"list": [1.0, 2], "n": 1}}])).is_ok());
+        assert!(matches!(
+            patched(doc.clone(), json!([{"op": "test", "path": "/a/n", "value": "1"}])),
+            Err(DodoError::Conflict(_))
+        ));
+        assert!(matches!(
+            patched(doc, json!([{"op": "test", "path": "/missing", "value": null}])),
+            Err(DodoError::Conflict(_))
+        ));
+
+        // Integers past 2^53 are not rounded to the same f64.
+        let doc = json!({"id": 9007199254740992u64});
+        assert!(patched(doc.clone(), json!([{"op": "test", "path": "/id", "value": 9007199254740992u64}])).is_ok());
+        assert!(matches!(
+            patched(doc, json!([{"op": "test", "path": "/id", "value": 9007199254740993u64}])),
+            Err(DodoError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn patch_move_and_copy() {
+        let doc = json!({"a": {"x": 1}, "list": [1, 2, 3]});
+
+        assert_eq!(
+            patched(doc.clone(), json!([{"op": "move", "from": "/a/x", "path": "/b"}])).unwrap(),
+            json!({"a": {}, "b": 1, "list": [1, 2, 3]})
+        );
+        assert_eq!(
+            patched(doc.clone(), json!([{"op": "move", "from": "/list/0", "path": "/list/-"}])).unwrap(),
+            json!({"a": {"x": 1}, "list": [2, 3, 1]})
+        );
+        assert_eq!(
+            patched(doc.clone(), json!([{"op": "copy", "from": "/a", "path": "/list/1"}])).unwrap(),
+            json!({"a": {"x": 1}, "list": [1, {"x": 1}, 2, 3]})
+        );
+        assert!(matches!(
+            patched(doc.clone(), json!([{"op": "move", "from": "/a", "path": "/a/y"}])),
+            Err(DodoError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            patched(doc, json!([{"op": "copy", "from": "/nope", "path": "/b"}])),
+            Err(DodoError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn patch_stops_at_a_failed_test() {
+        let doc = json!({"n": 1});
+        let patch = json!([
+            {"op": "replace", "path": "/n", "value": 2},
+            {"op": "test", "path": "/n", "value": 1},
+            {"op": "add", "path": "/m", "value": 3}
+        ]);
+        assert!(matches!(patched(doc, patch), Err(DodoError::Conflict(_))));
+    }
+}
@@ -0,0 +1,768 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use regex_automata::meta::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::services::json_ops::{format_pointer, json_eq, parse_pointer, pointer_get};
+
+/// One reason a value does not match its schema.
+///
+/// `path` is the JSON Pointer of the offending part of the value and
+/// `keyword` the schema keyword it failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub key: String,
+    pub path: String,
+    pub keyword: &'static str,
+    pub message: String,
+}
+
+/// Keywords that only annotate a schema and never fail validation.
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "format",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+];
+
+/// Keywords whose value is a schema.
+const SUBSCHEMA: &[&str] = &["items", "not"];
+
+/// Keywords holding named schemas for `$ref` (`#/$defs/<name>`).
+const DEFINITIONS: &[&str] = &["$defs", "definitions"];
+
+/// Keywords whose value is an array of schemas.
+const SUBSCHEMA_LISTS: &[&str] = &["allOf", "anyOf", "oneOf"];
+
+const VALIDATORS: &[&str] = &[
+    "type",
+    "enum",
+    "const",
+    "properties",
+    "required",
+    "additionalProperties",
+    "minProperties",
+    "maxProperties",
+    "items",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "$ref",
+];
+
+const TYPES: &[&str] = &["null", "boolean", "object", "array", "number", "integer", "string"];
+
+/// Subschemas a value may be checked against, per JSON node of the value
+/// (plus `BASE_STEPS`). Combinators that reach the same subschemas many
+/// times over (e.g. a chain of `{"anyOf": [{"$ref": "#/$defs/next"},
+/// {"$ref": "#/$defs/next"}]}`) would otherwise take exponential time.
+const STEPS_PER_NODE: usize = 100;
+const BASE_STEPS: usize = 10_000;
+
+lazy_static! {
+    /// Compiled `pattern`s, by source. Only registered schemas reach
+    /// validation, so this holds the patterns of those schemas.
+    static ref PATTERNS: Mutex<HashMap<String, Arc<Regex>>> = Mutex::new(HashMap::new());
+}
+
+/// Check that `schema` only uses the supported subset of JSON Schema:
+/// - the draft 2020-12 validation keywords listed in `VALIDATORS`
+/// - `pattern` in Rust regex syntax (close to ECMA-262, without
+///   look-around and backreferences), matched anywhere in the string
+/// - `$ref` to a JSON Pointer inside the same schema (`#`,
+///   `#/$defs/<name>`, `#/definitions/<name>`, ...); no remote references
+///   and no `$anchor`s
+/// - no cycle of `$ref`s and combinators that does not descend into the
+///   value (see `check_cycles`)
+///
+/// Unknown keywords are rejected instead of ignored, so a schema never
+/// silently checks less than its author expects.
+pub fn check_schema(schema: &Value) -> Result<(), String> {
+    check_at(schema, schema, &mut Vec::new())?;
+    check_cycles(schema)
+}
+
+/// Reject a schema in which `$ref`, `allOf`, `anyOf`, `oneOf` and `not`
+/// lead back to a subschema without going through `properties`,
+/// `additionalProperties` or `items` (e.g. `{"anyOf": [{"$ref": "#"}]}`):
+/// it would check the same part of the value against itself forever.
+fn check_cycles(root: &Value) -> Result<(), String> {
+    let mut done = HashSet::new();
+    let mut pending = vec![Vec::new()];
+
+    while let Some(at) = pending.pop() {
+        visit(root, at, &mut Vec::new(), &mut done, &mut pending)?;
+    }
+    Ok(())
+}
+
+/// Depth-first walk of the subschemas that apply to the same part of the
+/// value as the one `at`. `path` holds the ones being walked; subschemas
+/// for a part of the value are left in `pending` for a walk of their own.
+fn visit(
+    root: &Value,
+    at: Vec<String>,
+    path: &mut Vec<Vec<String>>,
+    done: &mut HashSet<Vec<String>>,
+    pending: &mut Vec<Vec<String>>,
+) -> Result<(), String> {
+    if done.contains(&at) {
+        return Ok(());
+    }
+    if path.contains(&at) {
+        return Err(format!(
+            "schema at '{}' refers back to itself without descending into the value",
+            format_pointer(&at)
+        ));
+    }
+
+    let child = |keys: &[&str]| -> Vec<String> {
+        let mut p = at.clone();
+        p.extend(keys.iter().map(|k| k.to_string()));
+        p
+    };
+
+    let mut same_value = Vec::new();
+    if let Some(Value::Object(obj)) = pointer_get(root, &at) {
+        for (keyword, arg) in obj {
+            match keyword.as_str() {
+                "$ref" => same_value.extend(
+                    arg.as_str()
+                        .and_then(|r| r.strip_prefix('#'))
+                        .and_then(|p| parse_pointer(p).ok()),
+                ),
+                "not" => same_value.push(child(&["not"])),
+                k if SUBSCHEMA_LISTS.contains(&k) => {
+                    let len = arg.as_array().map_or(0, Vec::len);
+                    same_value.extend((0..len).map(|i| child(&[k, &i.to_string()])));
+                }
+                k @ ("items" | "additionalProperties") => pending.push(child(&[k])),
+                k if k == "properties" || DEFINITIONS.contains(&k) => {
+                    let names = arg.as_object().into_iter().flat_map(|o| o.keys());
+                    pending.extend(names.map(|name| child(&[k, name])));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    path.push(at.clone());
+    for next in same_value {
+        visit(root, next, path, done, pending)?;
+    }
+    path.pop();
+
+    done.insert(at);
+    Ok(())
+}
+
+/// Resolve a `$ref` against the root schema.
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    let tokens = parse_pointer(pointer).ok()?;
+    pointer_get(root, &tokens)
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| e.to_string())
+}
+
+fn check_at(root: &Value, schema: &Value, at: &mut Vec<String>) -> Result<(), String> {
+    let obj = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(obj) => obj,
+        _ => return Err(format!("schema at '{}' must be an object or a boolean", format_pointer(at))),
+    };
+
+    let invalid = |at: &[String], keyword: &str, expected: &str| {
+        Err(format!(
+            "'{}' at '{}' must be {}",
+            keyword,
+            format_pointer(at),
+            expected
+        ))
+    };
+
+    for (keyword, arg) in obj {
+        if ANNOTATIONS.contains(&keyword.as_str()) {
+            continue;
+        }
+        if !VALIDATORS.contains(&keyword.as_str()) && !DEFINITIONS.contains(&keyword.as_str()) {
+            return Err(format!(
+                "unsupported schema keyword '{}' at '{}'",
+                keyword,
+                format_pointer(at)
+            ));
+        }
+
+        at.push(keyword.clone());
+        match keyword.as_str() {
+            "type" => {
+                let names: Vec<&Value> = match arg {
+                    Value::Array(names) => names.iter().collect(),
+                    name => vec![name],
+                };
+                if !names.iter().all(|n| n.as_str().is_some_and(|n| TYPES.contains(&n))) {
+                    return invalid(at, keyword, "a type name or an array of type names");
+                }
+            }
+            "enum" if !arg.is_array() => return invalid(at, keyword, "an array"),
+            "required" if !arg.as_array().is_some_and(|a| a.iter().all(Value::is_string)) => {
+                return invalid(at, keyword, "an array of strings");
+            }
+            "properties" => {
+                let Some(props) = arg.as_object() else {
+                    return invalid(at, keyword, "an object");
+                };
+                for (name, sub) in props {
+                    at.push(name.clone());
+                    check_at(root, sub, at)?;
+                    at.pop();
+                }
+            }
+            k if DEFINITIONS.contains(&k) => {
+                let Some(defs) = arg.as_object() else {
+                    return invalid(at, keyword, "an object");
+                };
+                for (name, sub) in defs {
+                    at.push(name.clone());
+                    check_at(root, sub, at)?;
+                    at.pop();
+                }
+            }
+            "$ref" => {
+                let target = arg.as_str().and_then(|r| resolve(root, r));
+                if !target.is_some_and(|t| t.is_object() || t.is_boolean()) {
+                    return invalid(at, keyword, "a JSON Pointer to a schema in this schema (e.g. \"#/$defs/name\")");
+                }
+            }
+            "pattern" => {
+                let Some(pattern) = arg.as_str() else {
+                    return invalid(at, keyword, "a string");
+                };
+                if let Err(e) = compile(pattern) {
+                    return Err(format!("invalid 'pattern' at '{}': {}", format_pointer(at), e));
+                }
+            }
+            "additionalProperties" => check_at(root, arg, at)?,
+            k if SUBSCHEMA.contains(&k) => check_at(root, arg, at)?,
+            k if SUBSCHEMA_LISTS.contains(&k) => {
+                let Some(subs) = arg.as_array().filter(|a| !a.is_empty()) else {
+                    return invalid(at, keyword, "a non-empty array of schemas");
+                };
+                for (i, sub) in subs.iter().enumerate() {
+                    at.push(i.to_string());
+                    check_at(root, sub, at)?;
+                    at.pop();
+                }
+            }
+            "minProperties" | "maxProperties" | "minItems" | "maxItems" | "minLength" | "maxLength"
+                if arg.as_u64().is_none() =>
+            {
+                return invalid(at, keyword, "a non-negative integer");
+            }
+            "minimum" | "maximum" | "exclusiveMinimum" | "exclusiveMaximum" if !arg.is_number() => {
+                return invalid(at, keyword, "a number");
+            }
+            "multipleOf" if !arg.as_f64().is_some_and(|m| m > 0.0) => {
+                return invalid(at, keyword, "a number greater than 0");
+            }
+            "uniqueItems" if !arg.is_boolean() => return invalid(at, keyword, "a boolean"),
+            _ => {}
+        }
+        at.pop();
+    }
+
+    Ok(())
+}
+
+/// Validate `value` against a schema accepted by `check_schema`.
+///
+/// Gives up with a violation once the value was checked against too many
+/// subschemas (see `STEPS_PER_NODE`).
+pub fn validate(schema: &Value, value: &Value) -> Vec<Violation> {
+    let mut out = Vec::new();
+    let budget = BASE_STEPS.saturating_add(node_count(value).saturating_mul(STEPS_PER_NODE));
+    let steps = Cell::new(budget);
+    let scope = Scope { root: schema, steps: &steps };
+    validate_at(scope, schema, value, &mut Vec::new(), &mut out);
+
+    if steps.get() == 0 {
+        out.push(violation(
+            &[],
+            "schema",
+            "the schema takes too many steps to check this value".to_string(),
+        ));
+    }
+    out
+}
+
+/// The schema being validated against, for `$ref`, and the steps left.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    root: &'a Value,
+    steps: &'a Cell<usize>,
+}
+
+/// Number of JSON nodes in `value`.
+fn node_count(value: &Value) -> usize {
+    match value {
+        Value::Array(items) => 1 + items.iter().map(node_count).sum::<usize>(),
+        Value::Object(fields) => 1 + fields.values().map(node_count).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        name => type_name(value) == name,
+    }
+}
+
+fn is_valid(scope: Scope, schema: &Value, value: &Value) -> bool {
+    let mut out = Vec::new();
+    validate_at(scope, schema, value, &mut Vec::new(), &mut out);
+    out.is_empty()
+}
+
+/// Whether `s` matches `pattern` (anywhere, like ECMA-262 `test`).
+///
+/// The cache is only locked to look the regex up, not while it runs.
+fn matches_pattern(pattern: &str, s: &str) -> bool {
+    let cached = PATTERNS.lock().unwrap().get(pattern).cloned();
+    let re = match cached {
+        Some(re) => re,
+        None => match compile(pattern) {
+            Ok(re) => PATTERNS
+                .lock()
+                .unwrap()
+                .entry(pattern.to_string())
+                .or_insert_with(|| Arc::new(re))
+                .clone(),
+            // Rejected by `check_schema`, so never registered.
+            Err(_) => return true,
+        },
+    };
+    re.is_match(s)
+}
+
+fn validate_at(scope: Scope, schema: &Value, value: &Value, path: &mut Vec<String>, out: &mut Vec<Violation>) {
+    // Out of steps: stop here, `validate` reports it.
+    let Some(left) = scope.steps.get().checked_sub(1) else {
+        return;
+    };
+    scope.steps.set(left);
+
+    let obj = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            out.push(violation(path, "false", "no value is allowed here".to_string()));
+            return;
+        }
+        Value::Object(obj) => obj,
+        _ => return,
+    };
+
+    if let Some(target) = obj.get("$ref").and_then(Value::as_str).and_then(|r| resolve(scope.root, r)) {
+        validate_at(scope, target, value, path, out);
+    }
+
+    let mut fail = |path: &[String], keyword: &'static str, message: String| {
+        out.push(violation(path, keyword, message));
+    };
+
+    if let Some(types) = obj.get("type") {
+        let names: Vec<&str> = match types {
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            name => name.as_str().into_iter().collect(),
+        };
+        if !names.iter().any(|n| has_type(value, n)) {
+            fail(path, "type", format!("expected {}, got {}", names.join(" or "), type_name(value)));
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = obj.get("enum") {
+        if !allowed.iter().any(|a| json_eq(a, value)) {
+            fail(path, "enum", format!("must be one of {}", Value::Array(allowed.clone())));
+        }
+    }
+
+    if let Some(expected) = obj.get("const") {
+        if !json_eq(expected, value) {
+            fail(path, "const", format!("must be {}", expected));
+        }
+    }
+
+    match value {
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(f64::NAN);
+            let bound = |k: &str| obj.get(k).and_then(Value::as_f64);
+
+            if let Some(min) = bound("minimum").filter(|&min| n < min) {
+                fail(path, "minimum", format!("must be >= {}", min));
+            }
+            if let Some(max) = bound("maximum").filter(|&max| n > max) {
+                fail(path, "maximum", format!("must be <= {}", max));
+            }
+            if let Some(min) = bound("exclusiveMinimum").filter(|&min| n <= min) {
+                fail(path, "exclusiveMinimum", format!("must be > {}", min));
+            }
+            if let Some(max) = bound("exclusiveMaximum").filter(|&max| n >= max) {
+                fail(path, "exclusiveMaximum", format!("must be < {}", max));
+            }
+            if let Some(m) = bound("multipleOf") {
+                let q = n / m;
+                if (q - q.round()).abs() > 1e-9 {
+                    fail(path, "multipleOf", format!("must be a multiple of {}", m));
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = obj.get("minLength").and_then(Value::as_u64).filter(|&m| len < m) {
+                fail(path, "minLength", format!("must be at least {} characters long", min));
+            }
+            if let Some(max) = obj.get("maxLength").and_then(Value::as_u64).filter(|&m| len > m) {
+                fail(path, "maxLength", format!("must be at most {} characters long", max));
+            }
+            if let Some(pattern) = obj.get("pattern").and_then(Value::as_str) {
+                if !matches_pattern(pattern, s) {
+                    fail(path, "pattern", format!("must match the pattern '{}'", pattern));
+                }
+            }
+        }
+        Value::Array(items) => {
+            let len = items.len() as u64;
+            if let Some(min) = obj.get("minItems").and_then(Value::as_u64).filter(|&m| len < m) {
+                fail(path, "minItems", format!("must have at least {} items", min));
+            }
+            if let Some(max) = obj.get("maxItems").and_then(Value::as_u64).filter(|&m| len > m) {
+                fail(path, "maxItems", format!("must have at most {} items", max));
+            }
+            if obj.get("uniqueItems") == Some(&Value::Bool(true)) {
+                let duplicate = items
+                    .iter()
+                    .enumerate()
+                    .any(|(i, a)| items[..i].iter().any(|b| json_eq(a, b)));
+                if duplicate {
+                    fail(path, "uniqueItems", "items must be unique".to_string());
+                }
+            }
+            if let Some(item_schema) = obj.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    path.push(i.to_string());
+                    validate_at(scope, item_schema, item, path, out);
+                    path.pop();
+                }
+            }
+        }
+        Value::Object(fields) => validate_object(scope, obj, fields, path, out),
+        _ => {}
+    }
+
+    validate_combinators(scope, obj, value, path, out);
+}
+
+fn validate_object(
+    scope: Scope,
+    obj: &Map<String, Value>,
+    fields: &Map<String, Value>,
+    path: &mut Vec<String>,
+    out: &mut Vec<Violation>,
+) {
+    let len = fields.len() as u64;
+    if let Some(min) = obj.get("minProperties").and_then(Value::as_u64).filter(|&m| len < m) {
+        out.push(violation(path, "minProperties", format!("must have at least {} properties", min)));
+    }
+    if let Some(max) = obj.get("maxProperties").and_then(Value::as_u64).filter(|&m| len > m) {
+        out.push(violation(path, "maxProperties", format!("must have at most {} properties", max)));
+    }
+
+    if let Some(Value::Array(required)) = obj.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !fields.contains_key(name) {
+                out.push(violation(path, "required", format!("missing required property '{}'", name)));
+            }
+        }
+    }
+
+    let properties = obj.get("properties").and_then(Value::as_object);
+    for (name, field) in fields {
+        let sub = match properties.and_then(|p| p.get(name)) {
+            Some(sub) => sub,
+            None => match obj.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    path.push(name.clone());
+                    out.push(violation(
+                        path,
+                        "additionalProperties",
+                        format!("property '{}' is not allowed", name),
+                    ));
+                    path.pop();
+                    continue;
+                }
+                Some(sub) => sub,
+                None => continue,
+            },
+        };
+
+        path.push(name.clone());
+        validate_at(scope, sub, field, path, out);
+        path.pop();
+    }
+}
+
+fn validate_combinators(
+    scope: Scope,
+    obj: &Map<String, Value>,
+    value: &Value,
+    path: &mut Vec<String>,
+    out: &mut Vec<Violation>,
+) {
+    if let Some(Value::Array(subs)) = obj.get("allOf") {
+        for sub in subs {
+            validate_at(scope, sub, value, path, out);
+        }
+    }
+
+    if let Some(Value::Array(subs)) = obj.get("anyOf") {
+        if !subs.iter().any(|sub| is_valid(scope, sub, value)) {
+            out.push(violation(path, "anyOf", "must match at least one schema of anyOf".to_string()));
+        }
+    }
+
+    if let Some(Value::Array(subs)) = obj.get("oneOf") {
+        let matched = subs.iter().filter(|sub| is_valid(scope, sub, value)).count();
+        if matched != 1 {
+            out.push(violation(
+                path,
+                "oneOf",
+                format!("must match exactly one schema of oneOf (matched {})", matched),
+            ));
+        }
+    }
+
+    if let Some(sub) = obj.get("not") {
+        if is_valid(scope, sub, value) {
+            out.push(violation(path, "not", "must not match the schema of not".to_string()));
+        }
+    }
+}
+
+fn violation(path: &[String], keyword: &'static str, message: String) -> Violation {
+    Violation {
+        key: String::new(),
+        path: format_pointer(path),
+        keyword,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Keywords of the violations of `value`, after checking the schema.
+    fn failed(schema: Value, value: Value) -> Vec<&'static str> {
+        check_schema(&schema).expect("schema is supported");
+        validate(&schema, &value).into_iter().map(|v| v.keyword).collect()
+    }
+
+    #[test]
+    fn type_and_required() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": { "age": { "type": "integer" } }
+        });
+
+        assert!(failed(schema.clone(), json!({ "name": "a", "age": 3 })).is_empty());
+        assert_eq!(failed(schema.clone(), json!({ "age": 3.5 })), ["required", "type"]);
+        assert_eq!(failed(schema, json!([])), ["type"]);
+        assert!(failed(json!({ "type": ["string", "null"] }), json!(null)).is_empty());
+    }
+
+    #[test]
+    fn additional_properties() {
+        let schema = json!({
+            "properties": { "a": true },
+            "additionalProperties": false
+        });
+        assert!(failed(schema.clone(), json!({ "a": 1 })).is_empty());
+
+        let violations = validate(&schema, &json!({ "a": 1, "b": 2 }));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].keyword, "additionalProperties");
+        assert_eq!(violations[0].path, "/b");
+    }
+
+    #[test]
+    fn enum_and_const_compare_numbers_by_value() {
+        assert!(failed(json!({ "enum": [1, "a"] }), json!(1.0)).is_empty());
+        assert_eq!(failed(json!({ "enum": [1, "a"] }), json!("b")), ["enum"]);
+        assert_eq!(failed(json!({ "const": { "x": 1 } }), json!({ "x": 2 })), ["const"]);
+
+        // Exactly, for integers past 2^53.
+        let big = 9007199254740992u64;
+        assert!(failed(json!({ "const": big }), json!(big)).is_empty());
+        assert_eq!(failed(json!({ "const": big }), json!(big + 1)), ["const"]);
+        assert_eq!(failed(json!({ "enum": [big, i64::MIN] }), json!(i64::MIN + 1)), ["enum"]);
+    }
+
+    #[test]
+    fn numeric_bounds() {
+        let schema = json!({ "minimum": 0, "exclusiveMaximum": 10, "multipleOf": 0.5 });
+        assert!(failed(schema.clone(), json!(9.5)).is_empty());
+        assert_eq!(failed(schema.clone(), json!(-1)), ["minimum"]);
+        assert_eq!(failed(schema.clone(), json!(10)), ["exclusiveMaximum"]);
+        assert_eq!(failed(schema, json!(0.3)), ["multipleOf"]);
+    }
+
+    #[test]
+    fn strings_and_patterns() {
+        let schema = json!({ "minLength": 2, "maxLength": 3, "pattern": "^[a-z]+$" });
+        assert!(failed(schema.clone(), json!("abc")).is_empty());
+        assert_eq!(failed(schema.clone(), json!("a")), ["minLength"]);
+        assert_eq!(failed(schema.clone(), json!("ab1")), ["pattern"]);
+        // Lengths count characters, not bytes.
+        assert!(failed(json!({ "maxLength": 2 }), json!("éé")).is_empty());
+        // An unanchored pattern matches anywhere.
+        assert!(failed(json!({ "pattern": "b" }), json!("abc")).is_empty());
+    }
+
+    #[test]
+    fn arrays() {
+        let schema = json!({ "items": { "type": "number" }, "maxItems": 2, "uniqueItems": true });
+        assert!(failed(schema.clone(), json!([1, 2])).is_empty());
+        assert_eq!(failed(schema.clone(), json!([1, 1])), ["uniqueItems"]);
+        assert!(failed(schema.clone(), json!([9007199254740992u64, 9007199254740993u64])).is_empty());
+        assert_eq!(failed(schema.clone(), json!([1, 2, 3])), ["maxItems"]);
+
+        let violations = validate(&schema, &json!([1, "x"]));
+        assert_eq!(violations[0].path, "/1");
+    }
+
+    #[test]
+    fn combinators() {
+        let one_of = json!({ "oneOf": [{ "type": "integer" }, { "minimum": 0 }] });
+        assert!(failed(one_of.clone(), json!(-1)).is_empty());
+        assert_eq!(failed(one_of, json!(1)), ["oneOf"]);
+
+        assert_eq!(failed(json!({ "anyOf": [{ "type": "string" }, { "type": "null" }] }), json!(1)), ["anyOf"]);
+        assert_eq!(failed(json!({ "not": { "type": "string" } }), json!("a")), ["not"]);
+        assert_eq!(
+            failed(json!({ "allOf": [{ "minimum": 1 }, { "maximum": 0 }] }), json!(2)),
+            ["maximum"]
+        );
+    }
+
+    #[test]
+    fn refs_to_definitions() {
+        let schema = json!({
+            "$defs": { "zip": { "type": "string", "pattern": "^[0-9]{5}$" } },
+            "properties": {
+                "home": { "$ref": "#/$defs/zip" },
+                "work": { "$ref": "#/$defs/zip", "maxLength": 4 }
+            }
+        });
+        assert!(failed(schema.clone(), json!({ "home": "12345" })).is_empty());
+        assert_eq!(failed(schema.clone(), json!({ "home": 12345 })), ["type"]);
+        // Keywords next to a $ref apply too.
+        assert_eq!(failed(schema, json!({ "work": "12345" })), ["maxLength"]);
+    }
+
+    #[test]
+    fn recursive_refs() {
+        let tree = json!({
+            "type": "object",
+            "properties": { "children": { "type": "array", "items": { "$ref": "#" } } }
+        });
+        let value = json!({ "children": [{ "children": [] }, { "children": [{ "children": 1 }] }] });
+
+        let violations = validate(&tree, &value);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].path, "/children/1/children/0/children");
+    }
+
+    #[test]
+    fn refs_that_never_descend_are_rejected() {
+        assert!(check_schema(&json!({ "anyOf": [{ "$ref": "#" }, { "$ref": "#" }] })).is_err());
+        assert!(check_schema(&json!({ "allOf": [{ "$ref": "#" }] })).is_err());
+        assert!(check_schema(&json!({
+            "$defs": { "a": { "not": { "$ref": "#/$defs/b" } }, "b": { "oneOf": [{ "$ref": "#/$defs/a" }] } },
+            "$ref": "#/$defs/a"
+        }))
+        .is_err());
+
+        // Through properties or items the value gets smaller each time.
+        assert!(check_schema(&json!({ "anyOf": [{ "items": { "$ref": "#" } }, { "type": "null" }] })).is_ok());
+        // The same subschema reached twice is not a cycle.
+        assert!(check_schema(&json!({
+            "$defs": { "n": { "type": "number" } },
+            "anyOf": [{ "$ref": "#/$defs/n" }, { "not": { "$ref": "#/$defs/n" } }]
+        }))
+        .is_ok());
+    }
+
+    #[test]
+    fn combinator_blowup_runs_out_of_steps() {
+        // 2^40 paths through the definitions, none of them a cycle.
+        let mut defs = serde_json::Map::new();
+        for i in 0..40 {
+            let next = json!({ "$ref": format!("#/$defs/d{}", i + 1) });
+            defs.insert(format!("d{}", i), json!({ "anyOf": [next.clone(), next] }));
+        }
+        defs.insert("d40".to_string(), json!(false));
+        let schema = json!({ "$defs": defs, "$ref": "#/$defs/d0" });
+
+        assert_eq!(failed(schema, json!(1)), ["schema"]);
+    }
+
+    #[test]
+    fn unsupported_schemas_are_rejected() {
+        assert!(check_schema(&json!({ "patternProperties": {} })).is_err());
+        assert!(check_schema(&json!({ "pattern": "(" })).is_err());
+        assert!(check_schema(&json!({ "$ref": "#/$defs/missing" })).is_err());
+        assert!(check_schema(&json!({ "$ref": "https://example.com/schema.json" })).is_err());
+        assert!(check_schema(&json!({ "minLength": -1 })).is_err());
+        assert!(check_schema(&json!({ "type": "float" })).is_err());
+        assert!(check_schema(&json!({ "$defs": { "a": 1 } })).is_err());
+    }
+}
@@ -1,104 +1,2002 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+
 use chrono::Utc;
-use serde_json::{Map, Value};
+use tokio::time::{timeout_at, Duration, Instant};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use crate::errors::DodoError;
+use crate::services::pubsub_service::{self, KeyEvent};
+use crate::services::schema_service;
+use crate::services::json_ops;
+use crate::state::kv::{
+    entry_bytes, json_size, next_version, DataType, Entry, EvictionPolicy, KvStore, Revision, Shard,
+    ShardsRead, ZScores, ENTRY_OVERHEAD,
+};
+
+/// Condition on the current state of a key that must hold for a write
+/// to be applied. Checked under the same lock as the write itself.
+#[derive(Debug, Clone, Default)]
+pub enum Precondition {
+    /// Unconditional write.
+    #[default]
+    None,
+    /// The key must exist (`If-Match: *`).
+    Exists,
+    /// The key must exist with one of these versions (`If-Match: "<v>"`).
+    VersionIn(Vec<u64>),
+    /// The key must not exist (`If-None-Match: *`).
+    Absent,
+}
+
+impl Precondition {
+    /// Check the condition against the current (non-expired) entry, if any.
+    pub fn check(&self, current: Option<&Entry>) -> Result<(), DodoError> {
+        let ok = match self {
+            Precondition::None => true,
+            Precondition::Exists => current.is_some(),
+            Precondition::VersionIn(versions) => {
+                current.is_some_and(|e| versions.contains(&e.version))
+            }
+            Precondition::Absent => current.is_none(),
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(DodoError::PreconditionFailed)
+        }
+    }
+}
+
+/// Expiry given to a key by a write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Expiry {
+    /// No expiry of its own (a prefix's sliding expiry may still apply).
+    #[default]
+    None,
+    /// Absolute Unix timestamp.
+    At(i64),
+    /// Idle timeout in seconds: the key expires once it has not been read
+    /// or written for that long.
+    Sliding(u64),
+}
+
+impl Expiry {
+    fn entry(self, value: Arc<Value>, now: i64, version: u64) -> Entry {
+        let expires_at = match self {
+            Expiry::At(ts) => Some(ts),
+            _ => None,
+        };
+
+        let mut entry = Entry::new(value, now, expires_at, version);
+        if let Expiry::Sliding(secs) = self {
+            entry.sliding = Some(secs);
+        }
+        entry
+    }
+}
+
+/// Turn the optional `ttl` (seconds from now) / `expire_at` (Unix timestamp)
+/// pair accepted by write endpoints into an expiry. With `sliding`, `ttl`
+/// is an idle timeout instead (see `Expiry::Sliding`).
+///
+/// At most one of the two may be given, and the resulting expiry must be
+/// in the future.
+pub fn resolve_expiry(ttl: Option<u64>, expire_at: Option<i64>, sliding: bool) -> Result<Expiry, DodoError> {
+    let now = Utc::now().timestamp();
+
+    match (ttl, expire_at) {
+        (Some(ttl), None) if ttl > 0 && sliding => Ok(Expiry::Sliding(ttl)),
+        _ if sliding => Err(DodoError::InvalidRequest(
+            "sliding expiry needs a positive ttl (and no expire_at)".to_string(),
+        )),
+        (None, None) => Ok(Expiry::None),
+        (Some(ttl), None) if ttl > 0 => Ok(Expiry::At(now.saturating_add(ttl as i64))),
+        (None, Some(ts)) if ts > now => Ok(Expiry::At(ts)),
+        _ => Err(DodoError::InvalidRequest(
+            "use either a positive ttl or a future expire_at".to_string(),
+        )),
+    }
+}
+
+/// Spawn the Pub/Sub notification for a key change.
+///
+/// Write functions are sync (like the rest of this API), so we don't
+/// `await` here. Deep copies for the payload happen in the task, outside
+/// the store lock.
+fn spawn_notification(
+    store: &KvStore,
+    event: &'static str,
+    key: String,
+    path: Option<String>,
+    old_json: Option<Arc<Value>>,
+    new_json: Option<Arc<Value>>,
+) {
+    let db = store.name().to_string();
+
+    tokio::spawn(async move {
+        let ev = KeyEvent {
+            db,
+            key,
+            event,
+            path,
+            old_value: old_json.map_or(Value::Null, |v| (*v).clone()),
+            new_value: new_json.map_or(Value::Null, |v| (*v).clone()),
+        };
 
-use crate::services::pubsub_service;
-use crate::state::kv::{Entry, KvStore};
+        pubsub_service::notify_key_event(ev).await;
+    });
+}
+
+/// Shorthand for a whole-value "update" notification.
+fn spawn_update_notification(
+    store: &KvStore,
+    key: String,
+    old_json: Option<Arc<Value>>,
+    new_json: Arc<Value>,
+) {
+    spawn_notification(store, "update", key, None, old_json, Some(new_json));
+}
+
+/// Shorthand for a "delete" notification of a removed key.
+fn spawn_delete_notification(store: &KvStore, key: String, old_json: Arc<Value>) {
+    spawn_notification(store, "delete", key, None, Some(old_json), None);
+}
 
 /// Set a key to a JSON value and trigger Pub/Sub notifications.
-pub fn set(store: &KvStore, key: String, value: Value) {
+///
+/// `expiry` replaces any expiry previously attached to the key, so
+/// `Expiry::None` makes the key persistent (unless its prefix has a
+/// sliding expiry).
+///
+/// Returns the new version of the key, or `PreconditionFailed` if
+/// `cond` does not hold (nothing is written in that case).
+/// `InsufficientStorage` if the write does not fit in the memory limit
+/// (see `make_room`), `SchemaViolation` if the value does not match the
+/// schema registered for the key.
+pub fn set(
+    store: &KvStore,
+    key: String,
+    value: Value,
+    expiry: Expiry,
+    cond: &Precondition,
+) -> Result<u64, DodoError> {
     // We keep all locking / map mutation strictly synchronous.
     // Capture:
-    //   - old_json: Option<Value> (if there was a previous value)
-    //   - new_json: Value (clone for pubsub)
-    //   - key_for_pubsub: String (owned key for the async task)
-    let (old_json, new_json, key_for_pubsub) = {
-        let mut map = store.write().unwrap();
-
-        // Previous entry, if any
-        let old_entry = map.get(&key).cloned();
-
-        // Store JSON as string in the KV store
-        let entry = Entry {
-            value: value.to_string(),
-            created_at: Utc::now().timestamp(),
-        };
-        map.insert(key.clone(), entry);
+    //   - old_json: Option<Arc<Value>> (if there was a previous value)
+    //   - new_json: Arc<Value> (shared with the stored entry)
+    schema_service::check(store.name(), [(key.as_str(), &value)])?;
+    let value = Arc::new(value);
+    let now = Utc::now().timestamp();
 
-        // Try to parse the previous value as JSON
-        let old_json = old_entry
-            .and_then(|e| serde_json::from_str::<Value>(&e.value).ok());
+    // Build the entry up front so its size is known before evicting.
+    let mut entry = expiry.entry(value.clone(), now, 0);
+    let growth = entry_bytes(&key, &entry).saturating_sub(stored_bytes(store, &key));
+    make_room(store, &[&key], growth)?;
 
-        // Clone for the pubsub notification
-        (old_json, value.clone(), key.clone())
+    let (old_json, new_json, version) = {
+        let mut map = store.shard(&key).write().unwrap();
+
+        // Previous entry, if any (an expired one counts as absent)
+        let current = map.get(&key).filter(|e| !e.is_expired(now));
+        cond.check(current)?;
+
+        if let Some(previous) = current {
+            entry.succeed(previous);
+        }
+        let version = next_version();
+        entry.version = version;
+
+        let old_json = map
+            .insert(key.clone(), entry)
+            .filter(|e| !e.is_expired(now))
+            .map(|e| e.value);
+
+        (old_json, value, version)
     };
 
     // 2. Spawn async task to send webhooks.
-    //
-    // `set` is sync (like the rest of this API), so we don't `await` here.
-    tokio::spawn(async move {
-        let old_val: Value = old_json.unwrap_or(Value::Null);
+    spawn_update_notification(store, key, old_json, new_json);
 
-        // FIX: passiamo Some(old_val) invece di old_val
-        pubsub_service::notify_key_update(&key, Some(old_val), new_json)
-            .await;
-    });
+    Ok(version)
+}
+
+/// Atomically read-modify-write a key under the write lock.
+///
+/// `f` receives a copy of the current document (`Value::Null` if the key
+/// is missing) and edits it in place. If it returns an error nothing is
+/// written. The per-key expiry is preserved.
+///
+/// `path` is the JSON Pointer of the part `f` changes, if it only touches
+/// a sub-document; it is reported in the Pub/Sub event.
+///
+/// Returns whatever `f` returned together with the key's new version.
+/// Keys holding a native collection are `WrongType` (see `update_typed`).
+pub fn update<R>(
+    store: &KvStore,
+    key: &str,
+    path: Option<&str>,
+    cond: &Precondition,
+    f: impl FnOnce(&mut Value) -> Result<R, DodoError>,
+) -> Result<(R, u64), DodoError> {
+    update_typed(store, key, DataType::Json, path, cond, |doc| {
+        f(doc).map(|out| (out, true))
+    })
+}
+
+/// `WrongType` unless the existing entry (if any) holds `data_type`.
+pub fn check_type(key: &str, current: Option<&Entry>, data_type: DataType) -> Result<(), DodoError> {
+    match current {
+        Some(e) if e.data_type != data_type => Err(DodoError::WrongType(format!(
+            "key '{}' is a {}, expected {}",
+            key,
+            e.data_type.name(),
+            data_type.name()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Like `update`, for a key holding a value of `data_type`.
+///
+/// A missing key starts as the type's empty value. `f` returns its result
+/// and whether it changed the document; unchanged documents are not
+/// written (no new version, no event, and a missing key stays missing),
+/// in which case the current version (0 if missing) is returned.
+///
+/// The new size is only known once `f` has run, so under a memory limit
+/// an evicting policy makes room after the write, while `noeviction`
+/// rejects a write that grows the store past the limit.
+///
+/// A changed JSON document must match the schema registered for the key;
+/// collections are not checked against schemas.
+///
+/// Collections are edited in place (copy-on-write) unless the old value
+/// is still needed by the key's history or a subscription, so `f` must
+/// leave a collection untouched when it fails or reports no change.
+pub fn update_typed<R>(
+    store: &KvStore,
+    key: &str,
+    data_type: DataType,
+    path: Option<&str>,
+    cond: &Precondition,
+    f: impl FnOnce(&mut Value) -> Result<(R, bool), DodoError>,
+) -> Result<(R, u64), DodoError> {
+    update_entry(store, key, data_type, path, cond, |doc, scores| {
+        *scores = None;
+        f(doc)
+    })
+}
+
+/// Like `update_typed`, for a sorted set: `f` also gets the set's member
+/// index (see `Entry::scores`), `None` if it has not been built yet.
+/// Whatever `f` leaves there is stored with the new value.
+pub fn update_zset<R>(
+    store: &KvStore,
+    key: &str,
+    f: impl FnOnce(&mut Value, &mut Option<ZScores>) -> Result<(R, bool), DodoError>,
+) -> Result<(R, u64), DodoError> {
+    update_entry(store, key, DataType::ZSet, None, &Precondition::None, f)
+}
+
+fn update_entry<R>(
+    store: &KvStore,
+    key: &str,
+    data_type: DataType,
+    path: Option<&str>,
+    cond: &Precondition,
+    f: impl FnOnce(&mut Value, &mut Option<ZScores>) -> Result<(R, bool), DodoError>,
+) -> Result<(R, u64), DodoError> {
+    make_room(store, &[key], 0)?;
+
+    let (out, old_json, new_json, version) = {
+        let mut map = store.shard(key).write().unwrap();
+        let now = Utc::now().timestamp();
+
+        let current = map.get(key).filter(|e| !e.is_expired(now));
+        cond.check(current)?;
+        check_type(key, current, data_type)?;
+
+        let current_version = current.map(|e| e.version);
+        let expires_at = current.and_then(|e| e.expires_at);
+        let sliding = current.and_then(|e| e.sliding);
+        let old_bytes = map.get(key).map_or(0, |e| entry_bytes(key, e));
+
+        let in_place = current.is_some()
+            && data_type != DataType::Json
+            && !map.keeps_history(key)
+            && !pubsub_service::is_watched(store.name(), key);
+
+        // In place, the value is moved out of the entry (which keeps a
+        // placeholder until it is replaced or the value is put back).
+        let (old_json, old_scores) = match current {
+            Some(e) if !in_place => (Some(e.value.clone()), e.scores.clone()),
+            Some(_) => {
+                let entry = map.entry_mut(key).expect("current entry exists");
+                let value = std::mem::replace(&mut entry.value, Arc::new(Value::Null));
+                (Some(value), entry.scores.take())
+            }
+            None => (None, None),
+        };
+
+        let put_back = |map: &mut Shard, value: Arc<Value>, scores: Option<Arc<ZScores>>| {
+            if let Some(entry) = map.entry_mut(key).filter(|_| in_place) {
+                entry.value = value;
+                entry.scores = scores;
+            }
+        };
+
+        let (mut doc, old_json) = match old_json {
+            Some(v) if in_place => (Arc::unwrap_or_clone(v), None),
+            Some(v) => ((*v).clone(), Some(v)),
+            None => (data_type.empty(), None),
+        };
+        let mut scores = old_scores.map(Arc::unwrap_or_clone);
+
+        let out = match f(&mut doc, &mut scores) {
+            Ok((out, true)) => out,
+            Ok((out, false)) => {
+                put_back(&mut map, Arc::new(doc), scores.map(Arc::new));
+                return Ok((out, current_version.unwrap_or(0)));
+            }
+            Err(e) => {
+                put_back(&mut map, Arc::new(doc), scores.map(Arc::new));
+                return Err(e);
+            }
+        };
+        if data_type == DataType::Json {
+            schema_service::check(store.name(), [(key, &doc)])?;
+        }
+
+        let mut entry = Entry::new(Arc::new(doc), now, expires_at, 0);
+        entry.data_type = data_type;
+        entry.sliding = sliding;
+        entry.scores = scores.map(Arc::new);
+
+        if store.policy() == EvictionPolicy::NoEviction {
+            let growth = entry_bytes(key, &entry).saturating_sub(old_bytes);
+            if let Err(e) = check_limit(store, growth) {
+                put_back(&mut map, entry.value, entry.scores);
+                return Err(e);
+            }
+        }
+
+        if let Some(previous) = map.get(key).filter(|_| current_version.is_some()) {
+            entry.succeed(previous);
+        }
+
+        let version = next_version();
+        entry.version = version;
+        let new_json = entry.value.clone();
+        map.insert(key.to_string(), entry);
+
+        (out, old_json, new_json, version)
+    };
+
+    // Best effort: if nothing can be evicted the next write is rejected.
+    let _ = make_room(store, &[key], 0);
+
+    spawn_notification(
+        store,
+        "update",
+        key.to_string(),
+        path.map(str::to_string),
+        old_json,
+        Some(new_json),
+    );
+
+    Ok((out, version))
+}
+
+/// Run `f` on the value of a key holding `data_type` (the type's empty
+/// value if the key is missing). Counts as a read.
+pub fn view<R>(
+    store: &KvStore,
+    key: &str,
+    data_type: DataType,
+    f: impl FnOnce(&Value) -> R,
+) -> Result<R, DodoError> {
+    let map = store.shard(key).read().unwrap();
+    let now = Utc::now().timestamp();
+
+    let current = map.get(key).filter(|e| !e.is_expired(now));
+    check_type(key, current, data_type)?;
+
+    match current {
+        Some(entry) => {
+            entry.access.touch();
+            Ok(f(&entry.value))
+        }
+        None => Ok(f(&data_type.empty())),
+    }
+}
+
+/// Like `view`, for a sorted set: `f` also gets the set's member index
+/// (see `Entry::scores`), `None` if it has not been built yet.
+pub fn view_zset<R>(
+    store: &KvStore,
+    key: &str,
+    f: impl FnOnce(&Value, Option<&ZScores>) -> R,
+) -> Result<R, DodoError> {
+    let map = store.shard(key).read().unwrap();
+    let now = Utc::now().timestamp();
+
+    let current = map.get(key).filter(|e| !e.is_expired(now));
+    check_type(key, current, DataType::ZSet)?;
+
+    match current {
+        Some(entry) => {
+            entry.access.touch();
+            Ok(f(&entry.value, entry.scores.as_deref()))
+        }
+        None => Ok(f(&DataType::ZSet.empty(), None)),
+    }
+}
+
+/// Atomically add `by` to the number at `pointer` inside `key`
+/// (`""` = the whole value).
+///
+/// Missing keys and missing fields start at 0. Returns the new number
+/// and the key's new version.
+pub fn incr(store: &KvStore, key: &str, pointer: &str, by: &Number) -> Result<(Number, u64), DodoError> {
+    let tokens = json_ops::parse_pointer(pointer)?;
+
+    let path = (!pointer.is_empty()).then_some(pointer);
+
+    update(store, key, path, &Precondition::None, |doc| {
+        let target = json_ops::pointer_mut_or_create(doc, &tokens)?;
+        json_ops::increment(target, by)
+    })
+}
+
+/// Kind of patch document accepted by `patch`.
+#[derive(Debug, Clone, Copy)]
+pub enum PatchKind {
+    /// RFC 7396 (`application/merge-patch+json`)
+    Merge,
+    /// RFC 6902 (`application/json-patch+json`)
+    Json,
+}
+
+/// Atomically apply a patch document to the value of `key`.
+///
+/// A missing key is patched as `null`. Returns the new value and version.
+/// A failed JSON Patch `test` op is reported as `Conflict` and leaves the
+/// key untouched.
+pub fn patch(
+    store: &KvStore,
+    key: &str,
+    kind: PatchKind,
+    patch: &Value,
+    cond: &Precondition,
+) -> Result<(Value, u64), DodoError> {
+    update(store, key, None, cond, |doc| {
+        match kind {
+            PatchKind::Merge => json_ops::merge_patch(doc, patch),
+            PatchKind::Json => json_ops::apply_json_patch(doc, patch)?,
+        }
+        Ok(doc.clone())
+    })
+}
+
+/// Return the part of `key`'s value addressed by a JSON Pointer.
+///
+/// `NotFound` if the key or the path does not exist.
+pub fn get_at(store: &KvStore, key: &str, pointer: &str) -> Result<Value, DodoError> {
+    let tokens = json_ops::parse_pointer(pointer)?;
+    let entry = get(store, key).ok_or(DodoError::NotFound)?;
+
+    json_ops::pointer_get(&entry.value, &tokens)
+        .cloned()
+        .ok_or(DodoError::NotFound)
+}
+
+/// Atomically replace the part of `key`'s value addressed by a JSON
+/// Pointer, creating missing object members on the way (a missing key
+/// starts as an empty document). Returns the new version.
+pub fn set_at(
+    store: &KvStore,
+    key: &str,
+    pointer: &str,
+    value: Value,
+    cond: &Precondition,
+) -> Result<u64, DodoError> {
+    let tokens = json_ops::parse_pointer(pointer)?;
+
+    let ((), version) = update(store, key, Some(pointer), cond, |doc| {
+        *json_ops::pointer_mut_or_create(doc, &tokens)? = value;
+        Ok(())
+    })?;
+
+    Ok(version)
 }
 
-/// Retrieve a JSON value from a key.
-pub fn get(store: &KvStore, key: &str) -> Option<Value> {
-    let map = store.read().unwrap();
+/// Atomically remove the part of `key`'s value addressed by a JSON
+/// Pointer. `NotFound` if the key or the path does not exist.
+/// Returns the new version.
+pub fn delete_at(
+    store: &KvStore,
+    key: &str,
+    pointer: &str,
+    cond: &Precondition,
+) -> Result<u64, DodoError> {
+    let tokens = json_ops::parse_pointer(pointer)?;
+
+    let (_, version) = update(store, key, Some(pointer), cond, |doc| {
+        if json_ops::pointer_get(doc, &tokens).is_none() {
+            return Err(DodoError::NotFound);
+        }
+        json_ops::patch_remove(doc, &tokens)
+    })?;
+
+    Ok(version)
+}
+
+/// Retrieve the entry (shared JSON value plus metadata such as the
+/// version) for a key.
+///
+/// Keys whose per-key expiry has passed are reported as missing even if
+/// the cleanup loop has not removed them yet.
+pub fn get(store: &KvStore, key: &str) -> Option<Entry> {
+    let map = store.shard(key).read().unwrap();
+    let now = Utc::now().timestamp();
+
+    let entry = map.get(key).filter(|entry| !entry.is_expired(now))?;
+    entry.access.touch();
+    Some(entry.clone())
+}
+
+/// Long-poll read: wait up to `wait` for `key` to change, then return
+/// its entry (`None` if it does not exist) and whether it changed.
+///
+/// With `since_version`, a key already newer than that version counts as
+/// changed right away. Otherwise any write or delete of the key after the
+/// call counts. Waiters park on the store's `Watchers` and are woken by
+/// the write itself, so the store is never polled.
+pub async fn get_changed(
+    store: &KvStore,
+    key: &str,
+    since_version: Option<u64>,
+    wait: Duration,
+) -> (Option<Entry>, bool) {
+    let deadline = Instant::now() + wait;
+    let watch = store.watchers().watch(key);
+    let mut seen: Option<Option<u64>> = None;
+
+    loop {
+        // Register for a wake-up before reading, so a write landing in
+        // between is not missed.
+        let notified = watch.notify().notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let entry = get(store, key);
+        let version = entry.as_ref().map(|e| e.version);
+
+        match seen {
+            None if since_version.is_some_and(|n| version.is_some_and(|v| v > n)) => return (entry, true),
+            None => seen = Some(version),
+            Some(before) if before != version => return (entry, true),
+            Some(_) => {}
+        }
+
+        if timeout_at(deadline, notified).await.is_err() {
+            return (entry, false);
+        }
+    }
+}
+
+/// Metadata of a key, as returned by GET /kv/:key/meta.
+///
+/// Timestamps are Unix seconds. `size` is the length of the value's JSON
+/// text.
+#[derive(Debug, Serialize)]
+pub struct KeyMeta {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub data_type: DataType,
+    pub version: u64,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub last_accessed_at: i64,
+    pub expires_at: Option<i64>,
+    /// Idle timeout (seconds) of a sliding key; `expires_at` moves with
+    /// every read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sliding: Option<u64>,
+    pub read_count: u64,
+    pub write_count: u64,
+    pub size: usize,
+}
+
+impl KeyMeta {
+    fn new(key: &str, entry: &Entry) -> Self {
+        KeyMeta {
+            key: key.to_string(),
+            data_type: entry.data_type,
+            version: entry.version,
+            created_at: entry.created_at,
+            updated_at: entry.updated_at,
+            last_accessed_at: entry.access.last_millis().div_euclid(1000),
+            expires_at: entry.deadline(),
+            sliding: entry.sliding,
+            read_count: entry.access.reads(),
+            write_count: entry.write_count,
+            size: entry.size,
+        }
+    }
+}
+
+/// Metadata of a key. Does not count as a read.
+pub fn meta(store: &KvStore, key: &str) -> Option<KeyMeta> {
+    let map = store.shard(key).read().unwrap();
+    let now = Utc::now().timestamp();
+
+    map.get(key)
+        .filter(|entry| !entry.is_expired(now))
+        .map(|entry| KeyMeta::new(key, entry))
+}
+
+/// Metadata of several keys, in the given order. Keys that are missing
+/// (e.g. deleted since they were listed) are skipped.
+pub fn meta_many(store: &KvStore, keys: &[String]) -> Vec<KeyMeta> {
+    let map = store.read_all();
+    let now = Utc::now().timestamp();
+
+    keys.iter()
+        .filter_map(|k| {
+            map.get(k)
+                .filter(|e| !e.is_expired(now))
+                .map(|e| KeyMeta::new(k, e))
+        })
+        .collect()
+}
+
+/// Absolute per-key expiry (Unix timestamp) of a key.
+///
+/// - `None`: the key does not exist (or has already expired)
+/// - `Some(None)`: the key exists and has no per-key expiry
+/// - `Some(Some(ts))`: the key expires at `ts` (for a sliding key, unless
+///   it is read or written before)
+pub fn expiry(store: &KvStore, key: &str) -> Option<Option<i64>> {
+    let map = store.shard(key).read().unwrap();
+    let now = Utc::now().timestamp();
 
     map.get(key)
-        .and_then(|entry| serde_json::from_str::<Value>(&entry.value).ok())
+        .filter(|entry| !entry.is_expired(now))
+        .map(|entry| entry.deadline())
+}
+
+/// Remove the per-key expiry (absolute or sliding), making the key
+/// persistent again. A prefix's sliding expiry comes back with the
+/// next write of the key.
+///
+/// Returns `None` if the key does not exist, otherwise whether an
+/// expiry was actually removed.
+pub fn persist(store: &KvStore, key: &str) -> Option<bool> {
+    let mut map = store.shard(key).write().unwrap();
+    let now = Utc::now().timestamp();
+
+    if map.get(key).is_none_or(|entry| entry.is_expired(now)) {
+        return None;
+    }
+
+    map.modify(key, |entry| {
+        let had_ttl = entry.expires_at.take().is_some();
+        entry.sliding.take().is_some() || had_ttl
+    })
+}
+
+/// Delete a key if `cond` holds, and send a "delete" notification.
+///
+/// Deleting a missing key is not an error unless `cond` requires it to
+/// exist. Returns whether a key was removed.
+pub fn delete(store: &KvStore, key: &str, cond: &Precondition) -> Result<bool, DodoError> {
+    let removed = {
+        let mut map = store.shard(key).write().unwrap();
+        let now = Utc::now().timestamp();
+
+        let current = map.get(key).filter(|e| !e.is_expired(now));
+        cond.check(current)?;
+
+        map.remove(key).filter(|e| !e.is_expired(now))
+    };
+
+    let deleted = removed.is_some();
+    if let Some(e) = removed {
+        spawn_delete_notification(store, key.to_string(), e.value);
+    }
+    Ok(deleted)
 }
 
-/// Delete a key.
-pub fn delete(store: &KvStore, key: &str) {
-    let mut map = store.write().unwrap();
-    map.remove(key);
+/// Previous versions of a key, newest first (see `HistoryPolicy`).
+///
+/// Deleted keys keep their history. `None` if the key neither exists nor
+/// has a history.
+pub fn history(store: &KvStore, key: &str) -> Option<Vec<Revision>> {
+    let map = store.shard(key).read().unwrap();
+    let now = Utc::now().timestamp();
+
+    match map.history(key) {
+        Some(revisions) => Some(revisions.iter().cloned().collect()),
+        None => map
+            .get(key)
+            .filter(|e| !e.is_expired(now))
+            .map(|_| Vec::new()),
+    }
 }
 
-/// List all keys.
-pub fn list(store: &KvStore) -> Vec<String> {
-    let map = store.read().unwrap();
-    map.keys().cloned().collect()
+/// Restore the value (and type) a key had at `version`, as a new write.
+///
+/// The current value goes to the history like on any other write, so a
+/// rollback can itself be rolled back. The per-key expiry is preserved.
+/// Returns the new version; rolling back to the current version is a
+/// no-op that returns it unchanged.
+///
+/// Like `update_typed`, `noeviction` rejects a rollback that grows the
+/// store past its memory limit with `InsufficientStorage`.
+pub fn rollback(store: &KvStore, key: &str, version: u64, cond: &Precondition) -> Result<u64, DodoError> {
+    make_room(store, &[key], 0)?;
+
+    let (old_json, new_json, version) = {
+        let mut map = store.shard(key).write().unwrap();
+        let now = Utc::now().timestamp();
+
+        let current = map.get(key).filter(|e| !e.is_expired(now));
+        cond.check(current)?;
+
+        if let Some(e) = current.filter(|e| e.version == version) {
+            return Ok(e.version);
+        }
+
+        let revision = map
+            .history(key)
+            .and_then(|revs| revs.iter().find(|r| r.version == version))
+            .cloned()
+            .ok_or_else(|| {
+                DodoError::InvalidRequest(format!(
+                    "version {} of '{}' is not in its history",
+                    version, key
+                ))
+            })?;
+        if revision.data_type == DataType::Json {
+            schema_service::check(store.name(), [(key, &*revision.value)])?;
+        }
+
+        let mut entry = Entry::new(
+            revision.value.clone(),
+            now,
+            current.and_then(|e| e.expires_at),
+            0,
+        );
+        entry.data_type = revision.data_type;
+        entry.sliding = current.and_then(|e| e.sliding);
+
+        if store.policy() == EvictionPolicy::NoEviction {
+            let old_bytes = map.get(key).map_or(0, |e| entry_bytes(key, e));
+            check_limit(store, entry_bytes(key, &entry).saturating_sub(old_bytes))?;
+        }
+        entry.version = next_version();
+
+        if let Some(previous) = current {
+            entry.succeed(previous);
+        }
+
+        let old_json = current.map(|e| e.value.clone());
+        let version = entry.version;
+        map.insert(key.to_string(), entry);
+
+        (old_json, revision.value, version)
+    };
+
+    let _ = make_room(store, &[key], 0);
+    spawn_update_notification(store, key.to_string(), old_json, new_json);
+
+    Ok(version)
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// Batch operations (one lock acquisition per call)
+// ─────────────────────────────────────────────────────────────
+//
+
+/// A key of `get_many` with its value (`None` if missing).
+#[derive(Debug, Serialize)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: Option<Arc<Value>>,
+}
+
+/// A key of `set_many` with its new version.
+#[derive(Debug, Serialize)]
+pub struct KeyVersion {
+    pub key: String,
+    pub version: u64,
+}
+
+/// A key of `delete_many` and whether it existed.
+#[derive(Debug, Serialize)]
+pub struct KeyDeleted {
+    pub key: String,
+    pub deleted: bool,
+}
+
+/// Get several keys at once, in the order of `keys` (duplicates
+/// included). Missing (or expired) keys have no value.
+pub fn get_many(store: &KvStore, keys: &[String]) -> Vec<KeyValue> {
+    let map = store.read_all();
+    let now = Utc::now().timestamp();
+
+    keys.iter()
+        .map(|k| {
+            let value = map.get(k).filter(|e| !e.is_expired(now)).map(|e| {
+                e.access.touch();
+                e.value.clone()
+            });
+            KeyValue { key: k.clone(), value }
+        })
+        .collect()
+}
+
+/// Set several keys at once and return the new version of each, in
+/// request order. A key given twice is written twice (the last wins).
+///
+/// Like `set`, any per-key expiry is cleared. Pub/Sub notifications are
+/// sent after the lock is released. Either all keys fit in the memory
+/// limit and match their schemas or nothing is written.
+pub fn set_many(store: &KvStore, entries: Vec<(String, Value)>) -> Result<Vec<KeyVersion>, DodoError> {
+    schema_service::check(store.name(), entries.iter().map(|(k, v)| (k.as_str(), v)))?;
+
+    let now = Utc::now().timestamp();
+    let entries: Vec<(String, Entry)> = entries
+        .into_iter()
+        .map(|(key, value)| (key, Entry::new(Arc::new(value), now, None, 0)))
+        .collect();
+
+    let keys: Vec<&str> = entries.iter().map(|(k, _)| k.as_str()).collect();
+    let growth = entries
+        .iter()
+        .map(|(k, e)| entry_bytes(k, e).saturating_sub(stored_bytes(store, k)))
+        .sum();
+    make_room(store, &keys, growth)?;
+
+    let mut versions = Vec::with_capacity(entries.len());
+    let mut notifications = Vec::with_capacity(entries.len());
+
+    {
+        let mut map = store.write_all();
+
+        map.batch(|map| {
+            for (key, mut entry) in entries {
+                let value = entry.value.clone();
+                if let Some(previous) = map.get(&key).filter(|e| !e.is_expired(now)) {
+                    entry.succeed(previous);
+                }
+                let version = next_version();
+                entry.version = version;
+
+                let old_json = map
+                    .insert(key.clone(), entry)
+                    .filter(|e| !e.is_expired(now))
+                    .map(|e| e.value);
+
+                versions.push(KeyVersion { key: key.clone(), version });
+                notifications.push((key, old_json, value));
+            }
+        });
+    }
+
+    for (key, old_json, new_json) in notifications {
+        spawn_update_notification(store, key, old_json, new_json);
+    }
+
+    Ok(versions)
+}
+
+/// Delete several keys at once and report, per key and in request order,
+/// whether it existed.
+///
+/// A "delete" notification is sent for each removed key after the lock
+/// is released.
+pub fn delete_many(store: &KvStore, keys: &[String]) -> Vec<KeyDeleted> {
+    let now = Utc::now().timestamp();
+    let mut notifications = Vec::new();
+
+    let results = {
+        let mut map = store.write_all();
+
+        map.batch(|map| {
+            keys.iter()
+                .map(|k| {
+                    let removed = map.remove(k).filter(|e| !e.is_expired(now));
+                    let deleted = removed.is_some();
+                    if let Some(e) = removed {
+                        notifications.push((k.clone(), e.value));
+                    }
+                    KeyDeleted { key: k.clone(), deleted }
+                })
+                .collect()
+        })
+    };
+
+    for (key, old_json) in notifications {
+        spawn_delete_notification(store, key, old_json);
+    }
+
+    results
+}
+
+/// Keys selected by a bulk delete.
+#[derive(Debug, Clone)]
+pub enum KeyPattern {
+    /// Keys starting with this (non-empty) prefix.
+    Prefix(String),
+    /// Keys matching a glob: `*` (any run), `?` (one character),
+    /// `[abc]` / `[a-z]` / `[!a-z]` (character classes), `\` escapes.
+    Glob(String),
+}
+
+impl KeyPattern {
+    /// Literal start shared by every matching key, to narrow the scan.
+    fn prefix(&self) -> &str {
+        match self {
+            KeyPattern::Prefix(p) => p,
+            KeyPattern::Glob(g) => {
+                let end = g.find(['*', '?', '[', '\\']).unwrap_or(g.len());
+                &g[..end]
+            }
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            KeyPattern::Prefix(p) => key.starts_with(p.as_str()),
+            KeyPattern::Glob(g) => {
+                let pattern: Vec<char> = g.chars().collect();
+                let key: Vec<char> = key.chars().collect();
+                glob_match(&pattern, &key)
+            }
+        }
+    }
+}
+
+/// Match `key` against a glob (see `KeyPattern::Glob`).
+///
+/// Backtracks only to the last `*`, so matching stays linear in
+/// practice.
+fn glob_match(pattern: &[char], key: &[char]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, k));
+                p += 1;
+                continue;
+            }
+            Some(&c) => {
+                if let Some(next) = glob_step(pattern, p, c, key[k]) {
+                    p = next;
+                    k += 1;
+                    continue;
+                }
+            }
+            None => {}
+        }
+
+        // Mismatch: let the last `*` swallow one more character.
+        match star {
+            Some((sp, sk)) => {
+                star = Some((sp, sk + 1));
+                p = sp + 1;
+                k = sk + 1;
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
-/// Return all key–value pairs as a JSON object.
-pub fn get_all(store: &KvStore) -> Value {
-    let map = store.read().unwrap();
+/// Match one key character against the pattern token starting at `p`
+/// (`c` is `pattern[p]`, not `*`). Returns the index after the token.
+fn glob_step(pattern: &[char], p: usize, c: char, ch: char) -> Option<usize> {
+    match c {
+        '?' => Some(p + 1),
+        '\\' => match pattern.get(p + 1) {
+            Some(&lit) if lit == ch => Some(p + 2),
+            Some(_) => None,
+            None => (ch == '\\').then_some(p + 1),
+        },
+        '[' => {
+            let mut i = p + 1;
+            let negated = matches!(pattern.get(i), Some('!' | '^'));
+            if negated {
+                i += 1;
+            }
+
+            let mut matched = false;
+            let mut first = true;
+            while let Some(&lo) = pattern.get(i) {
+                if lo == ']' && !first {
+                    return (matched != negated).then_some(i + 1);
+                }
+                first = false;
 
-    let mut out = Map::new();
+                if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&hi| hi != ']') {
+                    matched |= (lo..=pattern[i + 2]).contains(&ch);
+                    i += 3;
+                } else {
+                    matched |= lo == ch;
+                    i += 1;
+                }
+            }
 
-    for (k, entry) in map.iter() {
-        if let Ok(json_val) = serde_json::from_str::<Value>(&entry.value) {
-            out.insert(k.clone(), json_val);
+            // No closing bracket: treat `[` as a literal.
+            (ch == '[').then_some(p + 1)
         }
+        _ => (c == ch).then_some(p + 1),
+    }
+}
+
+/// Outcome of `delete_matching`: the affected keys, in key order.
+#[derive(Debug, Serialize)]
+pub struct BulkDelete {
+    pub dry_run: bool,
+    pub count: usize,
+    pub keys: Vec<String>,
+}
+
+/// Delete every key matching `pattern`, under one lock acquisition over
+/// all shards, and send a "delete" event per removed key.
+///
+/// With `dry_run` nothing is removed; the keys that would be are
+/// reported.
+pub fn delete_matching(store: &KvStore, pattern: &KeyPattern, dry_run: bool) -> BulkDelete {
+    let now = Utc::now().timestamp();
+    let prefix = pattern.prefix();
+
+    if dry_run {
+        let map = store.read_all();
+        let keys: Vec<String> = scan_prefix(&map, prefix, now)
+            .filter(|(k, _)| pattern.matches(k))
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        return BulkDelete { dry_run, count: keys.len(), keys };
     }
 
-    Value::Object(out)
+    let removed: Vec<(String, Arc<Value>)> = {
+        let mut map = store.write_all();
+
+        let keys: Vec<String> = map
+            .range(Bound::Included(prefix))
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .filter(|(k, e)| !e.is_expired(now) && pattern.matches(k))
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        map.batch(|map| {
+            keys.into_iter()
+                .filter_map(|k| map.remove(&k).map(|e| (k, e.value)))
+                .collect()
+        })
+    };
+
+    let keys: Vec<String> = removed.iter().map(|(k, _)| k.clone()).collect();
+    for (key, old_json) in removed {
+        spawn_delete_notification(store, key, old_json);
+    }
+
+    BulkDelete { dry_run, count: keys.len(), keys }
+}
+
+/// Iterate, in key order, over the non-expired entries whose key starts
+/// with `prefix` (`""` = all keys).
+pub fn scan_prefix<'a>(
+    map: &'a ShardsRead<'_>,
+    prefix: &'a str,
+    now: i64,
+) -> impl Iterator<Item = (&'a String, &'a Entry)> + 'a {
+    map.range(Bound::Included(prefix))
+        .take_while(move |(k, _)| k.starts_with(prefix))
+        .filter(move |(_, e)| !e.is_expired(now))
+}
+
+/// List all keys starting with `prefix`, in lexicographic order.
+pub fn list(store: &KvStore, prefix: &str) -> Vec<String> {
+    let map = store.read_all();
+    let now = Utc::now().timestamp();
+
+    scan_prefix(&map, prefix, now)
+        .map(|(k, _)| k.clone())
+        .collect()
+}
+
+/// One page of a key listing.
+#[derive(Debug, Serialize)]
+pub struct KeyPage {
+    pub keys: Vec<String>,
+    /// Pass as `start_after` to get the next page; `None` on the last page.
+    pub next: Option<String>,
 }
 
-/// Return pretty JSON representation of all data.
-pub fn get_all_pretty(store: &KvStore) -> String {
-    let value = get_all(store);
+/// List up to `limit` keys starting with `prefix` that sort strictly after
+/// `start_after`, in lexicographic order.
+pub fn list_page(store: &KvStore, prefix: &str, start_after: Option<&str>, limit: usize) -> KeyPage {
+    let map = store.read_all();
+    let now = Utc::now().timestamp();
+
+    let lower = match start_after {
+        Some(after) if after >= prefix => Bound::Excluded(after),
+        _ => Bound::Included(prefix),
+    };
+
+    let mut keys: Vec<String> = map
+        .range(lower)
+        .take_while(|(k, _)| k.starts_with(prefix))
+        .filter(|(_, e)| !e.is_expired(now))
+        .take(limit.saturating_add(1))
+        .map(|(k, _)| k.clone())
+        .collect();
+
+    let next = if keys.len() > limit {
+        keys.truncate(limit);
+        keys.last().cloned()
+    } else {
+        None
+    };
+
+    KeyPage { keys, next }
+}
+
+/// Return all key–value pairs whose key starts with `prefix`
+/// (serializes as a JSON object, in key order).
+///
+/// Values are shared with the store, so this only clones `Arc`s.
+pub fn get_all(store: &KvStore, prefix: &str) -> BTreeMap<String, Arc<Value>> {
+    let map = store.read_all();
+    let now = Utc::now().timestamp();
+
+    scan_prefix(&map, prefix, now)
+        .map(|(k, e)| (k.clone(), e.value.clone()))
+        .collect()
+}
+
+/// Return pretty JSON representation of all data under `prefix`.
+pub fn get_all_pretty(store: &KvStore, prefix: &str) -> String {
+    let value = get_all(store, prefix);
     serde_json::to_string_pretty(&value).unwrap()
 }
 
 /// Check if a key exists.
 pub fn exists(store: &KvStore, key: &str) -> bool {
-    let map = store.read().unwrap();
-    map.contains_key(key)
+    let map = store.shard(key).read().unwrap();
+    let now = Utc::now().timestamp();
+
+    map.get(key).is_some_and(|entry| !entry.is_expired(now))
 }
 
 /// Clear all keys.
 pub fn clear(store: &KvStore) {
-    let mut map = store.write().unwrap();
+    let mut map = store.write_all();
     map.clear();
 }
 
-/// Return number of stored keys.
-pub fn count(store: &KvStore) -> usize {
-    let map = store.read().unwrap();
-    map.len()
-}
\ No newline at end of file
+/// Return number of stored keys starting with `prefix`.
+pub fn count(store: &KvStore, prefix: &str) -> usize {
+    let map = store.read_all();
+    let now = Utc::now().timestamp();
+
+    scan_prefix(&map, prefix, now).count()
+}
+//
+// ─────────────────────────────────────────────────────────────
+// Transactions
+// ─────────────────────────────────────────────────────────────
+//
+
+/// A single operation of a transaction, as sent to POST /kv/tx.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TxOp {
+    /// Set `key` to `value`, with optional `ttl` / `expire_at` /
+    /// `sliding` like PUT.
+    Set {
+        key: String,
+        value: Value,
+        ttl: Option<u64>,
+        expire_at: Option<i64>,
+        #[serde(default)]
+        sliding: bool,
+    },
+    /// Delete `key` (no-op if it does not exist).
+    Delete { key: String },
+    /// Abort unless `key` exists.
+    CheckExists { key: String },
+    /// Abort if `key` exists.
+    CheckMissing { key: String },
+    /// Abort unless `key` exists with exactly this version.
+    CheckVersion { key: String, version: u64 },
+}
+
+impl TxOp {
+    fn key(&self) -> &str {
+        match self {
+            TxOp::Set { key, .. }
+            | TxOp::Delete { key }
+            | TxOp::CheckExists { key }
+            | TxOp::CheckMissing { key }
+            | TxOp::CheckVersion { key, .. } => key,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TxOp::Set { .. } => "set",
+            TxOp::Delete { .. } => "delete",
+            TxOp::CheckExists { .. } => "check_exists",
+            TxOp::CheckMissing { .. } => "check_missing",
+            TxOp::CheckVersion { .. } => "check_version",
+        }
+    }
+}
+
+/// Per-operation result of a transaction.
+#[derive(Debug, Serialize)]
+pub struct TxOpResult {
+    pub op: &'static str,
+    pub key: String,
+    pub ok: bool,
+    /// New version for `set`, current version for successful checks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    /// For `delete`: whether the key existed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of a transaction.
+///
+/// When `committed` is false nothing was written and `results` stops at
+/// the operation that failed.
+#[derive(Debug, Serialize)]
+pub struct TxOutcome {
+    pub committed: bool,
+    pub results: Vec<TxOpResult>,
+}
+
+/// Run `ops` in order under a single write lock, all-or-nothing.
+///
+/// Operations see the effects of earlier operations in the same
+/// transaction. If any check fails the store is left untouched; values
+/// not matching their schemas reject the whole request up front.
+/// Writes are committed, logged and notified in the order their keys
+/// first appear in `ops`. Pub/Sub notifications are sent only after a
+/// successful commit.
+pub fn transaction(store: &KvStore, ops: Vec<TxOp>) -> Result<TxOutcome, DodoError> {
+    // Resolve expiries up front so a bad request never takes the lock.
+    let expiries = ops
+        .iter()
+        .map(|op| match op {
+            TxOp::Set { ttl, expire_at, sliding, .. } => resolve_expiry(*ttl, *expire_at, *sliding),
+            _ => Ok(Expiry::None),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    schema_service::check(
+        store.name(),
+        ops.iter().filter_map(|op| match op {
+            TxOp::Set { key, value, .. } => Some((key.as_str(), value)),
+            _ => None,
+        }),
+    )?;
+
+    // Make room for every set (deletes are not counted).
+    let keys: Vec<&str> = ops.iter().map(TxOp::key).collect();
+    let growth = ops
+        .iter()
+        .filter_map(|op| match op {
+            TxOp::Set { key, value, .. } => Some(
+                (key.len() + json_size(value) + ENTRY_OVERHEAD)
+                    .saturating_sub(stored_bytes(store, key)),
+            ),
+            _ => None,
+        })
+        .sum();
+    make_room(store, &keys, growth)?;
+
+    let mut notifications = Vec::new();
+
+    let outcome = {
+        let mut map = store.write_all();
+        let now = Utc::now().timestamp();
+
+        // Staged writes, in the order their keys were first written
+        // (`slots` maps a key to its position): `None` means "deleted in
+        // this transaction".
+        let mut staged: Vec<(String, Option<Entry>)> = Vec::new();
+        let mut slots: HashMap<String, usize> = HashMap::new();
+        let mut results = Vec::with_capacity(ops.len());
+
+        for (op, expiry) in ops.into_iter().zip(expiries) {
+            let current: Option<&Entry> = match slots.get(op.key()) {
+                Some(&slot) => staged[slot].1.as_ref(),
+                None => map.get(op.key()).filter(|e| !e.is_expired(now)),
+            };
+            let current_version = current.map(|e| e.version);
+
+            let mut result = TxOpResult {
+                op: op.name(),
+                key: op.key().to_string(),
+                ok: true,
+                version: None,
+                deleted: None,
+                error: None,
+            };
+
+            match op {
+                TxOp::Set { key, value, .. } => {
+                    let version = next_version();
+                    let mut entry = expiry.entry(Arc::new(value), now, version);
+                    if let Some(previous) = current {
+                        entry.succeed(previous);
+                    }
+                    stage(&mut staged, &mut slots, key, Some(entry));
+                    result.version = Some(version);
+                }
+                TxOp::Delete { key } => {
+                    result.deleted = Some(current_version.is_some());
+                    stage(&mut staged, &mut slots, key, None);
+                }
+                TxOp::CheckExists { .. } => {
+                    result.ok = current_version.is_some();
+                    result.version = current_version;
+                }
+                TxOp::CheckMissing { .. } => {
+                    result.ok = current_version.is_none();
+                }
+                TxOp::CheckVersion { version, .. } => {
+                    result.ok = current_version == Some(version);
+                    result.version = current_version;
+                }
+            }
+
+            let failed = !result.ok;
+            if failed {
+                result.error = Some("check failed".to_string());
+            }
+            results.push(result);
+
+            if failed {
+                return Ok(TxOutcome {
+                    committed: false,
+                    results,
+                });
+            }
+        }
+
+        // Commit, logged as one batch
+        map.batch(|map| {
+            for (key, staged_entry) in staged {
+                match staged_entry {
+                    Some(entry) => {
+                        let new_json = entry.value.clone();
+                        let old_json = map
+                            .insert(key.clone(), entry)
+                            .filter(|e| !e.is_expired(now))
+                            .map(|e| e.value);
+                        notifications.push(("update", key, old_json, Some(new_json)));
+                    }
+                    None => {
+                        if let Some(e) = map.remove(&key).filter(|e| !e.is_expired(now)) {
+                            notifications.push(("delete", key, Some(e.value), None));
+                        }
+                    }
+                }
+            }
+        });
+
+        TxOutcome {
+            committed: true,
+            results,
+        }
+    };
+
+    for (event, key, old_json, new_json) in notifications {
+        spawn_notification(store, event, key, None, old_json, new_json);
+    }
+
+    Ok(outcome)
+}
+
+/// Stage the write of `key` in a transaction, in the slot of its first
+/// write if it already has one.
+fn stage(
+    staged: &mut Vec<(String, Option<Entry>)>,
+    slots: &mut HashMap<String, usize>,
+    key: String,
+    entry: Option<Entry>,
+) {
+    match slots.get(&key) {
+        Some(&slot) => staged[slot].1 = entry,
+        None => {
+            slots.insert(key.clone(), staged.len());
+            staged.push((key, entry));
+        }
+    }
+}
+
+//
+// ─────────────────────────────────────────────────────────────
+// Memory limit / eviction
+// ─────────────────────────────────────────────────────────────
+//
+
+/// Bytes currently accounted for `key` (0 if missing).
+fn stored_bytes(store: &KvStore, key: &str) -> usize {
+    let map = store.shard(key).read().unwrap();
+    map.get(key).map_or(0, |e| entry_bytes(key, e))
+}
+
+fn out_of_memory(store: &KvStore) -> DodoError {
+    DodoError::InsufficientStorage(format!(
+        "database '{}' reached its memory limit of {} bytes",
+        store.name(),
+        store.max_bytes().unwrap_or(0)
+    ))
+}
+
+/// `InsufficientStorage` unless `growth` more bytes fit in the limit.
+fn check_limit(store: &KvStore, growth: usize) -> Result<(), DodoError> {
+    match store.max_bytes() {
+        Some(max) if store.used_bytes() + growth > max => Err(out_of_memory(store)),
+        _ => Ok(()),
+    }
+}
+
+/// Keys sampled per eviction round (see `make_room`).
+const EVICTION_SAMPLES: usize = 16;
+
+/// Best candidates `make_room` remembers across its rounds.
+const EVICTION_POOL: usize = 16;
+
+/// Rounds in a row without anything to evict after which `make_room`
+/// gives up.
+const EVICTION_MAX_IDLE_ROUNDS: usize = 64;
+
+/// Make sure a write growing the store by `growth` bytes fits in its
+/// memory limit.
+///
+/// With `noeviction` (or no key found to evict) the write is rejected
+/// with `InsufficientStorage`. Other policies evict keys, never one of
+/// `protect` (queued messages are not keys and are never evicted
+/// either), best candidate first:
+/// - `allkeys-lru`: least recently read or written
+/// - `allkeys-lfu`: fewest reads, then LRU
+/// - `volatile-ttl`: keys with a per-key expiry, soonest first
+///
+/// Eviction is approximate, so its cost does not grow with the store:
+/// each round samples `EVICTION_SAMPLES` keys (see `KvStore::sample`)
+/// into a pool of the `EVICTION_POOL` best candidates seen so far and
+/// evicts the best one.
+///
+/// Runs before the write takes its lock and locks one shard at a time,
+/// so concurrent writers can overshoot the limit slightly.
+pub fn make_room(store: &KvStore, protect: &[&str], growth: usize) -> Result<(), DodoError> {
+    let policy = store.policy();
+    if check_limit(store, growth).is_ok() {
+        return Ok(());
+    }
+    if policy == EvictionPolicy::NoEviction {
+        return Err(out_of_memory(store));
+    }
+
+    // Lowest score is evicted first.
+    let mut pool: Vec<((i64, i64), String)> = Vec::with_capacity(EVICTION_POOL + EVICTION_SAMPLES);
+    let now = Utc::now().timestamp();
+    let mut evicted = 0;
+    let mut idle_rounds = 0;
+
+    while check_limit(store, growth).is_err() && idle_rounds < EVICTION_MAX_IDLE_ROUNDS {
+        let sampled = store.sample(EVICTION_SAMPLES, |k, e| {
+            if protect.contains(&k.as_str()) {
+                return None;
+            }
+            let last = e.access.last_millis();
+            let score = match policy {
+                EvictionPolicy::AllKeysLru => Some((last, 0)),
+                EvictionPolicy::AllKeysLfu => Some((e.access.reads() as i64, last)),
+                EvictionPolicy::VolatileTtl => e.deadline().map(|t| (t, last)),
+                EvictionPolicy::NoEviction => None,
+            };
+            score.map(|score| (score, k.clone()))
+        });
+
+        for candidate in sampled {
+            if !pool.iter().any(|(_, k)| *k == candidate.1) {
+                pool.push(candidate);
+            }
+        }
+        pool.sort_unstable();
+        pool.truncate(EVICTION_POOL);
+
+        // Pooled keys may have been deleted since they were sampled.
+        let mut victim = None;
+        while victim.is_none() && !pool.is_empty() {
+            let (_, key) = pool.remove(0);
+            let mut shard = store.shard(&key).write().unwrap();
+            victim = shard.evict(&key).map(|entry| (key, entry));
+        }
+
+        let Some((key, entry)) = victim else {
+            idle_rounds += 1;
+            continue;
+        };
+        idle_rounds = 0;
+        evicted += 1;
+        if !entry.is_expired(now) {
+            spawn_notification(store, "evicted", key, None, Some(entry.value), None);
+        }
+    }
+
+    store.record_evictions(evicted);
+    if evicted > 0 {
+        tracing::info!("Evicted {} keys from '{}' ({:?})", evicted, store.name(), policy);
+    }
+
+    check_limit(store, growth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::kv::{new_store, HistoryPolicy};
+    use crate::state::wal::{self, FsyncPolicy, Record, Wal};
+    use serde_json::json;
+
+    fn tx_ops(ops: Value) -> Vec<TxOp> {
+        serde_json::from_value(ops).unwrap()
+    }
+
+    fn value(store: &KvStore, key: &str) -> Option<Value> {
+        get(store, key).map(|e| (*e.value).clone())
+    }
+
+    #[tokio::test]
+    async fn transaction_with_a_failed_check_writes_nothing() {
+        let store = new_store("tx-rollback", 4);
+        let v1 = set(&store, "a".to_string(), json!(1), Expiry::None, &Precondition::None).unwrap();
+        set(&store, "b".to_string(), json!(2), Expiry::None, &Precondition::None).unwrap();
+
+        let outcome = transaction(
+            &store,
+            tx_ops(json!([
+                {"op": "set", "key": "a", "value": 10},
+                {"op": "delete", "key": "b"},
+                {"op": "set", "key": "c", "value": 3},
+                {"op": "check_version", "key": "a", "version": v1},
+                {"op": "set", "key": "d", "value": 4}
+            ])),
+        )
+        .unwrap();
+
+        // The check sees the staged write of "a", whose version moved on.
+        assert!(!outcome.committed);
+        assert_eq!(outcome.results.len(), 4);
+        assert!(!outcome.results[3].ok);
+
+        assert_eq!(value(&store, "a"), Some(json!(1)));
+        assert_eq!(get(&store, "a").unwrap().version, v1);
+        assert_eq!(value(&store, "b"), Some(json!(2)));
+        assert_eq!(value(&store, "c"), None);
+        assert_eq!(value(&store, "d"), None);
+    }
+
+    #[tokio::test]
+    async fn transaction_commits_when_every_check_holds() {
+        let store = new_store("tx-commit", 4);
+        set(&store, "a".to_string(), json!(1), Expiry::None, &Precondition::None).unwrap();
+
+        let outcome = transaction(
+            &store,
+            tx_ops(json!([
+                {"op": "check_exists", "key": "a"},
+                {"op": "check_missing", "key": "b"},
+                {"op": "set", "key": "b", "value": 2},
+                {"op": "check_exists", "key": "b"},
+                {"op": "delete", "key": "a"}
+            ])),
+        )
+        .unwrap();
+
+        assert!(outcome.committed);
+        assert_eq!(value(&store, "a"), None);
+        assert_eq!(value(&store, "b"), Some(json!(2)));
+    }
+
+    #[tokio::test]
+    async fn transaction_commits_in_request_order() {
+        let dir = std::env::temp_dir().join(format!("dodo-tx-order-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot.wal");
+        let wal = Arc::new(Wal::open(&path, FsyncPolicy::Never).unwrap());
+        let store = new_store("tx-order", 4).with_wal(wal);
+        set(&store, "m".to_string(), json!(0), Expiry::None, &Precondition::None).unwrap();
+        let before = wal::read_records(&path).unwrap().len();
+
+        let outcome = transaction(
+            &store,
+            tx_ops(json!([
+                {"op": "set", "key": "z", "value": 1},
+                {"op": "delete", "key": "m"},
+                {"op": "set", "key": "a", "value": 2},
+                {"op": "set", "key": "z", "value": 3},
+                {"op": "set", "key": "k", "value": 4}
+            ])),
+        )
+        .unwrap();
+        assert!(outcome.committed);
+
+        // "z" keeps the slot of its first write, with its last value.
+        let logged: Vec<(String, Option<Value>)> = wal::read_records(&path)
+            .unwrap()
+            .into_iter()
+            .skip(before)
+            .flat_map(|r| match r {
+                Record::Batch { records } => records,
+                other => panic!("unexpected record {:?}", other),
+            })
+            .map(|r| match r {
+                Record::Set { key, entry } => (key, Some((*entry.value).clone())),
+                Record::Delete { key } => (key, None),
+                other => panic!("unexpected record {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                ("z".to_string(), Some(json!(3))),
+                ("m".to_string(), None),
+                ("a".to_string(), Some(json!(2))),
+                ("k".to_string(), Some(json!(4))),
+            ]
+        );
+    }
+
+    /// A store holding at most three of the values of `put`.
+    fn limited_store(name: &str, policy: EvictionPolicy) -> KvStore {
+        let one = entry_bytes("k0", &Entry::new(Arc::new(payload()), 0, None, 0));
+        new_store(name, 4).with_memory_limit(Some(3 * one + one / 2), policy)
+    }
+
+    fn payload() -> Value {
+        json!("x".repeat(100))
+    }
+
+    fn put(store: &KvStore, key: &str, expiry: Expiry) -> Result<u64, DodoError> {
+        let version = set(store, key.to_string(), payload(), expiry, &Precondition::None);
+        // Access times have millisecond resolution.
+        std::thread::sleep(std::time::Duration::from_millis(3));
+        version
+    }
+
+    fn keys(store: &KvStore) -> Vec<String> {
+        list(store, "")
+    }
+
+    #[tokio::test]
+    async fn noeviction_rejects_writes_past_the_limit() {
+        let store = limited_store("evict-none", EvictionPolicy::NoEviction);
+        for key in ["k0", "k1", "k2"] {
+            put(&store, key, Expiry::None).unwrap();
+        }
+
+        assert!(matches!(put(&store, "k3", Expiry::None), Err(DodoError::InsufficientStorage(_))));
+        // Overwriting with a value of the same size does not grow the store.
+        put(&store, "k1", Expiry::None).unwrap();
+        assert_eq!(keys(&store), ["k0", "k1", "k2"]);
+        assert_eq!(store.evicted(), 0);
+    }
+
+    #[tokio::test]
+    async fn lru_evicts_the_least_recently_used_key() {
+        let store = limited_store("evict-lru", EvictionPolicy::AllKeysLru);
+        for key in ["k0", "k1", "k2"] {
+            put(&store, key, Expiry::None).unwrap();
+        }
+        get(&store, "k0");
+
+        put(&store, "k3", Expiry::None).unwrap();
+        assert_eq!(keys(&store), ["k0", "k2", "k3"]);
+        assert_eq!(store.evicted(), 1);
+    }
+
+    #[tokio::test]
+    async fn lfu_evicts_the_least_read_key() {
+        let store = limited_store("evict-lfu", EvictionPolicy::AllKeysLfu);
+        for key in ["k0", "k1", "k2"] {
+            put(&store, key, Expiry::None).unwrap();
+        }
+        get(&store, "k0");
+        get(&store, "k0");
+        get(&store, "k1");
+        get(&store, "k2");
+        get(&store, "k2");
+
+        put(&store, "k3", Expiry::None).unwrap();
+        assert_eq!(keys(&store), ["k0", "k2", "k3"]);
+    }
+
+    #[tokio::test]
+    async fn volatile_ttl_evicts_keys_expiring_soonest_and_only_those() {
+        let store = limited_store("evict-ttl", EvictionPolicy::VolatileTtl);
+        let now = Utc::now().timestamp();
+        put(&store, "k0", Expiry::At(now + 200)).unwrap();
+        put(&store, "k1", Expiry::None).unwrap();
+        put(&store, "k2", Expiry::At(now + 100)).unwrap();
+
+        put(&store, "k3", Expiry::None).unwrap();
+        assert_eq!(keys(&store), ["k0", "k1", "k3"]);
+        put(&store, "k4", Expiry::None).unwrap();
+        assert_eq!(keys(&store), ["k1", "k3", "k4"]);
+
+        // Nothing left with a TTL.
+        assert!(matches!(put(&store, "k5", Expiry::None), Err(DodoError::InsufficientStorage(_))));
+        assert_eq!(store.evicted(), 2);
+    }
+
+    #[tokio::test]
+    async fn eviction_spares_protected_keys() {
+        let store = limited_store("evict-protect", EvictionPolicy::AllKeysLru);
+        for key in ["k0", "k1", "k2"] {
+            put(&store, key, Expiry::None).unwrap();
+        }
+
+        // k0 and k1 are the oldest, but the write is about them.
+        let bigger = Entry::new(Arc::new(payload()), 0, None, 0).size;
+        make_room(&store, &["k0", "k1"], bigger).unwrap();
+        assert_eq!(keys(&store), ["k0", "k1"]);
+
+        // With every key protected there is nothing to evict.
+        assert!(matches!(
+            make_room(&store, &["k0", "k1"], 4 * bigger),
+            Err(DodoError::InsufficientStorage(_))
+        ));
+        assert_eq!(keys(&store), ["k0", "k1"]);
+    }
+
+    fn history_store(name: &str) -> KvStore {
+        new_store(name, 4).with_history(HistoryPolicy {
+            default: 2,
+            prefixes: vec![("deep:".to_string(), 4), ("none:".to_string(), 0)],
+        })
+    }
+
+    fn history_values(store: &KvStore, key: &str) -> Vec<Value> {
+        history(store, key)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| (*r.value).clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn history_keeps_the_configured_depth_newest_first() {
+        let store = history_store("history-depth");
+        for key in ["k", "deep:k", "none:k"] {
+            for n in 1..=5 {
+                set(&store, key.to_string(), json!(n), Expiry::None, &Precondition::None).unwrap();
+            }
+        }
+
+        assert_eq!(history_values(&store, "k"), [json!(4), json!(3)]);
+        assert_eq!(history_values(&store, "deep:k"), [json!(4), json!(3), json!(2), json!(1)]);
+        assert_eq!(history(&store, "none:k").map(|h| h.len()), Some(0));
+        assert!(history(&store, "missing").is_none());
+
+        // Deleted keys keep theirs.
+        delete(&store, "k", &Precondition::None).unwrap();
+        assert_eq!(history_values(&store, "k"), [json!(5), json!(4)]);
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_a_revision_as_a_new_write() {
+        let store = history_store("history-rollback");
+        let v1 = set(&store, "k".to_string(), json!({"n": 1}), Expiry::None, &Precondition::None).unwrap();
+        let v2 = set(&store, "k".to_string(), json!({"n": 2}), Expiry::None, &Precondition::None).unwrap();
+
+        assert_eq!(rollback(&store, "k", v2, &Precondition::None).unwrap(), v2);
+
+        let v3 = rollback(&store, "k", v1, &Precondition::None).unwrap();
+        assert!(v3 > v2);
+        assert_eq!(value(&store, "k"), Some(json!({"n": 1})));
+        assert_eq!(get(&store, "k").unwrap().write_count, 3);
+        assert_eq!(history_values(&store, "k"), [json!({"n": 2}), json!({"n": 1})]);
+
+        // The rollback is itself in the history.
+        rollback(&store, "k", v2, &Precondition::None).unwrap();
+        assert_eq!(value(&store, "k"), Some(json!({"n": 2})));
+
+        assert!(matches!(
+            rollback(&store, "k", v1, &Precondition::VersionIn(vec![v1])),
+            Err(DodoError::PreconditionFailed)
+        ));
+        assert!(matches!(rollback(&store, "k", 0, &Precondition::None), Err(DodoError::InvalidRequest(_))));
+
+        // A deleted key comes back.
+        delete(&store, "k", &Precondition::None).unwrap();
+        rollback(&store, "k", v3, &Precondition::None).unwrap();
+        assert_eq!(value(&store, "k"), Some(json!({"n": 1})));
+    }
+
+    #[tokio::test]
+    async fn rollback_respects_the_memory_limit() {
+        let store = history_store("history-limit");
+        let big = set(&store, "k".to_string(), json!("x".repeat(500)), Expiry::None, &Precondition::None).unwrap();
+        set(&store, "k".to_string(), json!("small"), Expiry::None, &Precondition::None).unwrap();
+
+        let limit = store.used_bytes() + 100;
+        let store = store.with_memory_limit(Some(limit), EvictionPolicy::NoEviction);
+        assert!(matches!(
+            rollback(&store, "k", big, &Precondition::None),
+            Err(DodoError::InsufficientStorage(_))
+        ));
+        assert_eq!(value(&store, "k"), Some(json!("small")));
+        assert_eq!(history_values(&store, "k").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prefix_pages_are_stable_and_end_without_a_cursor() {
+        let store = new_store("scan-pages", 4);
+        for key in ["user:5", "user:1", "users", "user:3", "user:2", "other", "user:6", "user:4"] {
+            set(&store, key.to_string(), json!(key), Expiry::None, &Precondition::None).unwrap();
+        }
+
+        let first = list_page(&store, "user:", None, 3);
+        assert_eq!(first.keys, ["user:1", "user:2", "user:3"]);
+        assert_eq!(first.next.as_deref(), Some("user:3"));
+
+        // Writes around the cursor do not shift the next page.
+        delete(&store, "user:1", &Precondition::None).unwrap();
+        set(&store, "user:0".to_string(), json!(0), Expiry::None, &Precondition::None).unwrap();
+
+        let second = list_page(&store, "user:", first.next.as_deref(), 3);
+        assert_eq!(second.keys, ["user:4", "user:5", "user:6"]);
+        assert_eq!(second.next, None);
+        assert_eq!(list_page(&store, "user:", first.next.as_deref(), 3).keys, second.keys);
+
+        assert_eq!(list_page(&store, "user:", Some("user:6"), 3).keys, Vec::<String>::new());
+        assert_eq!(list_page(&store, "user:", Some("a"), 2).keys, ["user:0", "user:2"]);
+        assert_eq!(list_page(&store, "none:", None, 3).next, None);
+    }
+
+    #[tokio::test]
+    async fn meta_counts_reads_and_writes_across_updates() {
+        let store = new_store("meta-counts", 4);
+        set(&store, "k".to_string(), json!(1), Expiry::None, &Precondition::None).unwrap();
+        get(&store, "k").unwrap();
+        get(&store, "k").unwrap();
+
+        let before = meta(&store, "k").unwrap();
+        assert_eq!((before.read_count, before.write_count), (2, 1));
+        assert_eq!(before.created_at, before.updated_at);
+        // Reading the metadata is not a read.
+        assert_eq!(meta(&store, "k").unwrap().read_count, 2);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        set(&store, "k".to_string(), json!(2), Expiry::None, &Precondition::None).unwrap();
+
+        let after = meta(&store, "k").unwrap();
+        assert_eq!((after.read_count, after.write_count), (2, 2));
+        assert_eq!(after.created_at, before.created_at);
+        assert!(after.updated_at > before.updated_at);
+        assert!(after.version > before.version);
+
+        // A new key after a delete starts over.
+        delete(&store, "k", &Precondition::None).unwrap();
+        set(&store, "k".to_string(), json!(3), Expiry::None, &Precondition::None).unwrap();
+        let fresh = meta(&store, "k").unwrap();
+        assert_eq!((fresh.read_count, fresh.write_count), (0, 1));
+        assert!(fresh.created_at > before.created_at);
+    }
+
+    #[tokio::test]
+    async fn long_poll_wakes_on_a_write() {
+        let store = new_store("long-poll", 4);
+        let v1 = set(&store, "k".to_string(), json!(1), Expiry::None, &Precondition::None).unwrap();
+
+        let waiter = tokio::spawn({
+            let store = store.clone();
+            async move { get_changed(&store, "k", Some(v1), Duration::from_secs(10)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        let started = Instant::now();
+        let v2 = set(&store, "k".to_string(), json!(2), Expiry::None, &Precondition::None).unwrap();
+        let (entry, changed) = waiter.await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(changed);
+        let entry = entry.unwrap();
+        assert_eq!((entry.version, &*entry.value), (v2, &json!(2)));
+
+        // Already newer than `since_version`: no wait at all.
+        let (entry, changed) = get_changed(&store, "k", Some(v1), Duration::from_secs(10)).await;
+        assert!(changed && entry.is_some_and(|e| e.version == v2));
+
+        // Nothing happens: times out unchanged.
+        let (entry, changed) = get_changed(&store, "k", Some(v2), Duration::from_millis(50)).await;
+        assert!(!changed && entry.is_some_and(|e| e.version == v2));
+    }
+
+    #[tokio::test]
+    async fn sliding_key_survives_reads_past_its_ttl() {
+        let store = new_store("sliding", 4);
+        set(&store, "idle".to_string(), json!(1), Expiry::Sliding(2), &Precondition::None).unwrap();
+        let fixed = resolve_expiry(Some(2), None, false).unwrap();
+        set(&store, "fixed".to_string(), json!(1), fixed, &Precondition::None).unwrap();
+        let first_deadline = meta(&store, "idle").unwrap().expires_at.unwrap();
+
+        // Read every half second for well past the 2s timeout.
+        for _ in 0..7 {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            assert!(get(&store, "idle").is_some());
+        }
+
+        assert!(get(&store, "fixed").is_none());
+        let idle = meta(&store, "idle").unwrap();
+        assert_eq!(idle.sliding, Some(2));
+        assert!(idle.expires_at.unwrap() > first_deadline);
+    }
+
+    fn glob(pattern: &str, key: &str) -> bool {
+        KeyPattern::Glob(pattern.to_string()).matches(key)
+    }
+
+    #[test]
+    fn glob_star_matches_any_run() {
+        assert!(glob("user:*", "user:"));
+        assert!(glob("user:*", "user:42:name"));
+        assert!(glob("*:name", "user:42:name"));
+        assert!(glob("u*r*e", "user:42:name"));
+        assert!(!glob("user:*", "users"));
+        assert!(!glob("*:name", "user:42:email"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_character() {
+        assert!(glob("k?", "k1"));
+        assert!(glob("k?", "ké"));
+        assert!(!glob("k?", "k"));
+        assert!(!glob("k?", "k12"));
+    }
+
+    #[test]
+    fn glob_classes_ranges_negation_and_escapes() {
+        assert!(glob("k[abc]", "kb"));
+        assert!(!glob("k[abc]", "kd"));
+        assert!(glob("k[0-9]", "k7"));
+        assert!(!glob("k[0-9]", "kx"));
+        assert!(glob("k[!0-9]", "kx"));
+        assert!(!glob("k[!0-9]", "k7"));
+        assert!(glob(r"k\*", "k*"));
+        assert!(!glob(r"k\*", "kx"));
+        assert!(glob(r"k\?", "k?"));
+    }
+
+    #[test]
+    fn glob_prefix_stops_at_the_first_special_character() {
+        assert_eq!(KeyPattern::Glob("user:*:name".to_string()).prefix(), "user:");
+        assert_eq!(KeyPattern::Glob(r"a\*b".to_string()).prefix(), "a");
+        assert_eq!(KeyPattern::Glob("[ab]*".to_string()).prefix(), "");
+    }
+}
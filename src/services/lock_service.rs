@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+use tokio::time::{timeout_at, Instant};
+
+use crate::errors::DodoError;
+use crate::state::kv::KvStore;
+use crate::state::lock::{Grant, LockTable};
+
+/// A granted lease, as returned by the /locks routes.
+///
+/// `token` is the fencing token of the grant: it only changes when the
+/// lock changes hands, and every grant gets a larger one than any earlier
+/// grant of this database, across restarts too (see `LockTable::next_token`).
+#[derive(Debug, Serialize)]
+pub struct Lease {
+    pub name: String,
+    pub owner: String,
+    pub token: u64,
+    pub expires_at: i64,
+    /// Seconds left on the lease.
+    pub ttl: i64,
+}
+
+impl Lease {
+    fn of(name: &str, grant: &Grant, now: i64) -> Self {
+        Lease {
+            name: name.to_string(),
+            owner: grant.owner.clone(),
+            token: grant.token,
+            expires_at: grant.expires_at,
+            ttl: (grant.expires_at - now).max(0),
+        }
+    }
+}
+
+fn check_ttl(ttl: u64) -> Result<i64, DodoError> {
+    if ttl == 0 {
+        return Err(DodoError::InvalidRequest("ttl must be at least 1 second".to_string()));
+    }
+    i64::try_from(ttl).map_err(|_| DodoError::InvalidRequest("ttl is too large".to_string()))
+}
+
+fn held_by_other(name: &str, grant: &Grant) -> DodoError {
+    DodoError::Conflict(format!(
+        "lock '{}' is held by '{}' until {}",
+        name, grant.owner, grant.expires_at
+    ))
+}
+
+fn not_held(name: &str) -> DodoError {
+    DodoError::Conflict(format!("lock '{}' is not held", name))
+}
+
+/// Give `name` to `owner` until `now + ttl` if it is free, or extend it
+/// (same token) if `owner` already holds it. Otherwise returns the
+/// lease of the current holder.
+fn grant(locks: &mut LockTable, name: &str, owner: &str, ttl: i64, now: i64) -> Result<Grant, Grant> {
+    let token = match locks.get(name, now) {
+        Some(held) if held.owner != owner => return Err(held.clone()),
+        Some(held) => held.token,
+        None => locks.next_token(now),
+    };
+
+    let grant = Grant {
+        owner: owner.to_string(),
+        token,
+        expires_at: now.saturating_add(ttl),
+    };
+    locks.grant(name, grant.clone());
+    Ok(grant)
+}
+
+/// Acquire `name` for `owner` if it is free (never granted, released or
+/// expired).
+///
+/// Acquiring a lock the owner already holds extends it and keeps its
+/// token. `Conflict` if another owner holds it.
+pub fn try_acquire(store: &KvStore, name: &str, owner: &str, ttl: u64) -> Result<Lease, DodoError> {
+    let ttl = check_ttl(ttl)?;
+    let now = Utc::now().timestamp();
+
+    let mut locks = store.locks().lock();
+    grant(&mut locks, name, owner, ttl, now)
+        .map(|g| Lease::of(name, &g, now))
+        .map_err(|held| held_by_other(name, &held))
+}
+
+/// Like `try_acquire`, waiting up to `wait` while another owner holds
+/// the lock.
+///
+/// The wait ends as soon as the lock is released, or when the holder's
+/// lease runs out (renewals push that back).
+pub async fn acquire(
+    store: &KvStore,
+    name: &str,
+    owner: &str,
+    ttl: u64,
+    wait: Option<Duration>,
+) -> Result<Lease, DodoError> {
+    let Some(wait) = wait else {
+        return try_acquire(store, name, owner, ttl);
+    };
+    let ttl = check_ttl(ttl)?;
+    let deadline = Instant::now() + wait;
+    let watch = store.locks().watch(name);
+
+    loop {
+        // Enable the notification before looking at the lock, so a
+        // release right after the look still wakes us.
+        let notified = watch.notify().notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let now_millis = Utc::now().timestamp_millis();
+        let now = now_millis.div_euclid(1000);
+        let held = match grant(&mut store.locks().lock(), name, owner, ttl, now) {
+            Ok(g) => return Ok(Lease::of(name, &g, now)),
+            Err(held) => held,
+        };
+
+        if Instant::now() >= deadline {
+            return Err(held_by_other(name, &held));
+        }
+
+        let left = held.expires_at.saturating_mul(1000).saturating_sub(now_millis);
+        let expiry = Instant::now() + Duration::from_millis(left.max(0) as u64);
+        let _ = timeout_at(expiry.min(deadline), notified).await;
+    }
+}
+
+/// `Ok` if `owner` (and `token`, if given) holds `name`.
+fn check_holder(locks: &LockTable, name: &str, owner: &str, token: Option<u64>, now: i64) -> Result<Grant, DodoError> {
+    let held = locks.get(name, now).ok_or_else(|| not_held(name))?;
+    if held.owner != owner || token.is_some_and(|t| t != held.token) {
+        return Err(held_by_other(name, held));
+    }
+
+    Ok(held.clone())
+}
+
+/// Extend the lease of `owner` on `name` to `ttl` seconds from now.
+pub fn renew(store: &KvStore, name: &str, owner: &str, token: Option<u64>, ttl: u64) -> Result<Lease, DodoError> {
+    let ttl = check_ttl(ttl)?;
+    let now = Utc::now().timestamp();
+    let mut locks = store.locks().lock();
+
+    let mut grant = check_holder(&locks, name, owner, token, now)?;
+    grant.expires_at = now.saturating_add(ttl);
+    locks.grant(name, grant.clone());
+
+    Ok(Lease::of(name, &grant, now))
+}
+
+/// Release the lock `owner` holds on `name`.
+pub fn release(store: &KvStore, name: &str, owner: &str, token: Option<u64>) -> Result<(), DodoError> {
+    let now = Utc::now().timestamp();
+    let mut locks = store.locks().lock();
+
+    check_holder(&locks, name, owner, token, now)?;
+    locks.release(name);
+    Ok(())
+}
+
+/// Current lease on `name`, if it is held.
+pub fn inspect(store: &KvStore, name: &str) -> Option<Lease> {
+    let now = Utc::now().timestamp();
+    store
+        .locks()
+        .lock()
+        .get(name, now)
+        .map(|g| Lease::of(name, g, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::kv::new_store;
+
+    #[test]
+    fn acquire_renew_release() {
+        let store = new_store("locks", 4);
+
+        let lease = try_acquire(&store, "job", "a", 30).unwrap();
+        assert!(matches!(try_acquire(&store, "job", "b", 30), Err(DodoError::Conflict(_))));
+
+        // Re-acquiring or renewing keeps the token.
+        assert_eq!(try_acquire(&store, "job", "a", 60).unwrap().token, lease.token);
+        assert_eq!(renew(&store, "job", "a", Some(lease.token), 60).unwrap().token, lease.token);
+        assert!(renew(&store, "job", "a", Some(lease.token + 1), 60).is_err());
+        assert!(renew(&store, "job", "b", None, 60).is_err());
+
+        assert!(release(&store, "job", "b", None).is_err());
+        release(&store, "job", "a", Some(lease.token)).unwrap();
+        assert!(inspect(&store, "job").is_none());
+        assert!(release(&store, "job", "a", None).is_err());
+    }
+
+    #[test]
+    fn every_grant_gets_a_larger_token() {
+        let store = new_store("locks", 4);
+
+        let first = try_acquire(&store, "job", "a", 30).unwrap();
+        release(&store, "job", "a", None).unwrap();
+        let second = try_acquire(&store, "job", "b", 30).unwrap();
+        let other = try_acquire(&store, "other", "a", 30).unwrap();
+
+        assert!(second.token > first.token);
+        assert!(other.token > second.token);
+    }
+
+    #[test]
+    fn locks_are_not_keys() {
+        let store = new_store("locks", 4);
+        try_acquire(&store, "job", "a", 30).unwrap();
+
+        assert!(store.read_all().get("job").is_none());
+        crate::services::kv_service::clear(&store);
+        assert!(inspect(&store, "job").is_some());
+    }
+
+    #[tokio::test]
+    async fn blocking_acquire_wakes_on_release() {
+        let store = new_store("locks", 4);
+        let first = try_acquire(&store, "job", "a", 30).unwrap();
+
+        let waiter = {
+            let store = store.clone();
+            tokio::spawn(async move {
+                acquire(&store, "job", "b", 30, Some(Duration::from_secs(10))).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let released = std::time::Instant::now();
+        release(&store, "job", "a", None).unwrap();
+        let lease = waiter.await.unwrap().unwrap();
+
+        assert!(released.elapsed() < Duration::from_secs(1));
+        assert_eq!(lease.owner, "b");
+        assert!(lease.token > first.token);
+    }
+
+    #[tokio::test]
+    async fn blocking_acquire_times_out() {
+        let store = new_store("locks", 4);
+        try_acquire(&store, "job", "a", 30).unwrap();
+
+        let started = std::time::Instant::now();
+        let result = acquire(&store, "job", "b", 30, Some(Duration::from_millis(200))).await;
+
+        assert!(matches!(result, Err(DodoError::Conflict(_))));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(inspect(&store, "job").unwrap().owner, "a");
+    }
+
+    #[tokio::test]
+    async fn blocking_acquire_takes_over_an_expired_lease() {
+        let store = new_store("locks", 4);
+        let first = try_acquire(&store, "job", "a", 1).unwrap();
+
+        let lease = acquire(&store, "job", "b", 30, Some(Duration::from_secs(5))).await.unwrap();
+
+        assert_eq!(lease.owner, "b");
+        assert!(lease.token > first.token);
+        assert!(Utc::now().timestamp() >= first.expires_at);
+    }
+}
@@ -1,3 +1,12 @@
+pub mod collection_service;
+pub mod db_service;
+pub mod index_service;
+pub mod json_ops;
+pub mod json_schema;
 pub mod kv_service;
+pub mod lock_service;
 pub mod pubsub_service;
+pub mod query_service;
+pub mod queue_service;
+pub mod schema_service;
 
@@ -9,9 +9,13 @@ use serde_json::Value;
 use tracing::warn;
 
 /// Internal subscription stored in memory.
+///
+/// `db` is the database the watched key lives in, so every database
+/// has its own independent set of subscriptions. The id is the key of
+/// `SUBSCRIPTIONS`.
 #[derive(Debug, Clone)]
 pub struct Subscription {
-    pub id: u64,
+    pub db: String,
     pub key: String,
     pub callback: String,
 }
@@ -37,12 +41,12 @@ fn next_id() -> u64 {
     COUNTER.fetch_add(1, Ordering::Relaxed)
 }
 
-/// Create a subscription and return `{ "subscription_id": <id> }`.
-pub async fn subscribe(req: SubscribeRequest) -> Value {
+/// Create a subscription on database `db` and return `{ "subscription_id": <id> }`.
+pub async fn subscribe(db: &str, req: SubscribeRequest) -> Value {
     let id = next_id();
 
     let sub = Subscription {
-        id,
+        db: db.to_string(),
         key: req.key,
         callback: req.callback,
     };
@@ -55,10 +59,14 @@ pub async fn subscribe(req: SubscribeRequest) -> Value {
     serde_json::json!({ "subscription_id": id })
 }
 
-/// Remove a subscription by id and report if it existed.
-pub async fn unsubscribe(id: u64) -> Value {
+/// Remove a subscription of database `db` by id and report if it existed.
+pub async fn unsubscribe(db: &str, id: u64) -> Value {
     let mut map = SUBSCRIPTIONS.lock().unwrap();
-    let existed = map.remove(&id).is_some();
+
+    let existed = map.get(&id).is_some_and(|s| s.db == db);
+    if existed {
+        map.remove(&id);
+    }
 
     serde_json::json!({
         "subscription_id": id,
@@ -66,14 +74,52 @@ pub async fn unsubscribe(id: u64) -> Value {
     })
 }
 
-/// Called by `kv_service::set` when a key is updated.
-pub async fn notify_key_update(key: &str, old_value: Option<Value>, new_value: Value) {
+/// Drop every subscription of database `db` (used when the database is
+/// dropped). Returns how many were removed.
+pub fn remove_database(db: &str) -> usize {
+    let mut map = SUBSCRIPTIONS.lock().unwrap();
+    let before = map.len();
+    map.retain(|_, s| s.db != db);
+    before - map.len()
+}
+
+/// Number of subscriptions on database `db`.
+pub fn subscription_count(db: &str) -> usize {
+    let map = SUBSCRIPTIONS.lock().unwrap();
+    map.values().filter(|s| s.db == db).count()
+}
+
+/// Whether some subscription of database `db` watches `key`.
+pub fn is_watched(db: &str, key: &str) -> bool {
+    let map = SUBSCRIPTIONS.lock().unwrap();
+    map.values().any(|s| s.db == db && s.key == key)
+}
+
+/// A change to a key, as delivered to subscribers.
+#[derive(Debug, Clone)]
+pub struct KeyEvent {
+    /// Database the key lives in.
+    pub db: String,
+    pub key: String,
+    /// Event type, e.g. "update".
+    pub event: &'static str,
+    /// JSON Pointer of the changed part when only a sub-document was
+    /// written; `None` when the whole value changed.
+    pub path: Option<String>,
+    /// `null` if the key did not exist before.
+    pub old_value: Value,
+    /// `null` if the key no longer exists.
+    pub new_value: Value,
+}
+
+/// Called by `kv_service` whenever a key changes.
+pub async fn notify_key_event(ev: KeyEvent) {
     // Take a snapshot of matching subscriptions so we don’t hold the lock
     // while doing HTTP calls.
     let subs: Vec<Subscription> = {
         let map = SUBSCRIPTIONS.lock().unwrap();
         map.values()
-            .filter(|s| s.key == key)
+            .filter(|s| s.db == ev.db && s.key == ev.key)
             .cloned()
             .collect()
     };
@@ -82,14 +128,19 @@ pub async fn notify_key_update(key: &str, old_value: Option<Value>, new_value: V
         return;
     }
 
-    let body = serde_json::json!({
-        "key": key,
-        "event": "update",
-        "old_value": old_value.unwrap_or(Value::Null),
-        "new_value": new_value,
+    let mut body = serde_json::json!({
+        "db": ev.db,
+        "key": ev.key,
+        "event": ev.event,
+        "old_value": ev.old_value,
+        "new_value": ev.new_value,
         "timestamp": Utc::now().to_rfc3339(),
     });
 
+    if let Some(path) = ev.path {
+        body["path"] = Value::String(path);
+    }
+
     for sub in subs {
         let callback = sub.callback.clone();
         let body_clone = body.clone();
@@ -106,4 +157,4 @@ pub async fn notify_key_update(key: &str, old_value: Option<Value>, new_value: V
             }
         });
     }
-}
\ No newline at end of file
+}
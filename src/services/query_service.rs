@@ -0,0 +1,467 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::errors::DodoError;
+use crate::services::kv_service;
+use crate::state::index::{number_cmp, Index, IndexKey};
+use crate::state::kv::KvStore;
+
+/// Default and maximum number of results per query page.
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 10_000;
+
+/// A filter over stored values. `field`s are JSON Pointers into the value
+/// (`""` is the whole value).
+///
+/// Comparisons only hold between values of the same JSON type (numbers
+/// compare numerically) and are false when the field is missing.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Predicate {
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+    Eq(Comparison),
+    Ne(Comparison),
+    Gt(Comparison),
+    Gte(Comparison),
+    Lt(Comparison),
+    Lte(Comparison),
+    In(Membership),
+    Exists(String),
+    KeyPrefix(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Comparison {
+    pub field: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Membership {
+    pub field: String,
+    pub values: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SortKey {
+    pub field: String,
+    #[serde(default)]
+    pub desc: bool,
+}
+
+/// Body of POST /kv/query.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Query {
+    #[serde(rename = "where")]
+    pub filter: Option<Predicate>,
+    /// Fields to return instead of the whole value.
+    pub select: Option<Vec<String>>,
+    /// Result order (default: by key).
+    #[serde(default)]
+    pub sort: Vec<SortKey>,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryHit {
+    pub key: String,
+    pub value: Value,
+}
+
+/// One page of query results. `next` is the offset of the following
+/// page; `index` names the index used to find candidates, if any.
+#[derive(Debug, Serialize)]
+pub struct QueryPage {
+    pub results: Vec<QueryHit>,
+    pub next: Option<usize>,
+    pub index: Option<String>,
+}
+
+fn check_field(field: &str) -> Result<(), DodoError> {
+    if field.is_empty() || field.starts_with('/') {
+        Ok(())
+    } else {
+        Err(DodoError::InvalidRequest(format!(
+            "field '{}' must be a JSON Pointer starting with '/'",
+            field
+        )))
+    }
+}
+
+impl Predicate {
+    fn validate(&self) -> Result<(), DodoError> {
+        match self {
+            Predicate::And(ps) | Predicate::Or(ps) => ps.iter().try_for_each(Predicate::validate),
+            Predicate::Not(p) => p.validate(),
+            Predicate::Eq(c)
+            | Predicate::Ne(c)
+            | Predicate::Gt(c)
+            | Predicate::Gte(c)
+            | Predicate::Lt(c)
+            | Predicate::Lte(c) => check_field(&c.field),
+            Predicate::In(m) => check_field(&m.field),
+            Predicate::Exists(field) => check_field(field),
+            Predicate::KeyPrefix(_) => Ok(()),
+        }
+    }
+
+    fn matches(&self, key: &str, value: &Value) -> bool {
+        let ordered = |c: &Comparison, ok: fn(Ordering) -> bool| {
+            value
+                .pointer(&c.field)
+                .and_then(|v| compare(v, &c.value))
+                .is_some_and(ok)
+        };
+
+        match self {
+            Predicate::And(ps) => ps.iter().all(|p| p.matches(key, value)),
+            Predicate::Or(ps) => ps.iter().any(|p| p.matches(key, value)),
+            Predicate::Not(p) => !p.matches(key, value),
+            Predicate::Eq(c) => value.pointer(&c.field).is_some_and(|v| equals(v, &c.value)),
+            Predicate::Ne(c) => value.pointer(&c.field).is_some_and(|v| !equals(v, &c.value)),
+            Predicate::Gt(c) => ordered(c, Ordering::is_gt),
+            Predicate::Gte(c) => ordered(c, Ordering::is_ge),
+            Predicate::Lt(c) => ordered(c, Ordering::is_lt),
+            Predicate::Lte(c) => ordered(c, Ordering::is_le),
+            Predicate::In(m) => value
+                .pointer(&m.field)
+                .is_some_and(|v| m.values.iter().any(|x| equals(v, x))),
+            Predicate::Exists(field) => value.pointer(field).is_some(),
+            Predicate::KeyPrefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+
+    /// The predicates that must all hold for this one to hold.
+    fn conjuncts(&self) -> Vec<&Predicate> {
+        match self {
+            Predicate::And(ps) => ps.iter().flat_map(Predicate::conjuncts).collect(),
+            p => vec![p],
+        }
+    }
+}
+
+/// Order of two scalars of the same JSON type; `None` otherwise.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Number(a), Value::Number(b)) => Some(number_cmp(a, b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn equals(a: &Value, b: &Value) -> bool {
+    compare(a, b).map_or(a == b, Ordering::is_eq)
+}
+
+//
+// Index planning
+//
+// An index can answer a comparison on its field when every key the query
+// can match lies under the index prefix, i.e. the query's key prefix
+// starts with it. The index only narrows down candidates: the whole
+// predicate is still evaluated on each of them.
+//
+
+/// Bounds of the index range holding every value of `key`'s JSON type.
+fn type_range(key: &IndexKey) -> (Bound<IndexKey>, Bound<IndexKey>) {
+    match key {
+        IndexKey::Null => (Bound::Included(IndexKey::Null), Bound::Included(IndexKey::Null)),
+        IndexKey::Bool(_) => (
+            Bound::Included(IndexKey::Bool(false)),
+            Bound::Included(IndexKey::Bool(true)),
+        ),
+        IndexKey::Number(_) => (
+            Bound::Excluded(IndexKey::Bool(true)),
+            Bound::Excluded(IndexKey::String(String::new())),
+        ),
+        IndexKey::String(_) => (Bound::Included(IndexKey::String(String::new())), Bound::Unbounded),
+    }
+}
+
+/// Candidate keys for `p` from `index`, or `None` if it cannot help.
+fn index_candidates(index: &Index, p: &Predicate) -> Option<Vec<String>> {
+    let lookup = |lower: Bound<&IndexKey>, upper: Bound<&IndexKey>| index.lookup(lower, upper, usize::MAX);
+
+    match p {
+        Predicate::In(m) => {
+            let keys: Option<Vec<IndexKey>> = m.values.iter().map(IndexKey::from_value).collect();
+            Some(
+                keys?
+                    .iter()
+                    .flat_map(|k| lookup(Bound::Included(k), Bound::Included(k)))
+                    .collect(),
+            )
+        }
+        Predicate::Eq(c) | Predicate::Gt(c) | Predicate::Gte(c) | Predicate::Lt(c) | Predicate::Lte(c) => {
+            let k = IndexKey::from_value(&c.value)?;
+            let (low, high) = type_range(&k);
+            let (lower, upper) = match p {
+                Predicate::Eq(_) => (Bound::Included(&k), Bound::Included(&k)),
+                Predicate::Gt(_) => (Bound::Excluded(&k), high.as_ref()),
+                Predicate::Gte(_) => (Bound::Included(&k), high.as_ref()),
+                Predicate::Lt(_) => (low.as_ref(), Bound::Excluded(&k)),
+                _ => (low.as_ref(), Bound::Included(&k)),
+            };
+            Some(lookup(lower, upper))
+        }
+        _ => None,
+    }
+}
+
+fn indexed_field(p: &Predicate) -> Option<&str> {
+    match p {
+        Predicate::Eq(c) | Predicate::Gt(c) | Predicate::Gte(c) | Predicate::Lt(c) | Predicate::Lte(c) => {
+            Some(&c.field)
+        }
+        Predicate::In(m) => Some(&m.field),
+        _ => None,
+    }
+}
+
+/// Pick an index for the query, preferring equality lookups, and return
+/// its name with the sorted candidate keys.
+fn plan(store: &KvStore, conjuncts: &[&Predicate], key_prefix: &str) -> Option<(String, Vec<String>)> {
+    let indexes = store.indexes().all();
+    let is_eq = |p: &&&Predicate| matches!(p, Predicate::Eq(_) | Predicate::In(_));
+    let ordered = conjuncts.iter().filter(is_eq).chain(conjuncts.iter().filter(|p| !is_eq(p)));
+
+    for p in ordered {
+        let Some(field) = indexed_field(p) else { continue };
+        let usable = indexes
+            .iter()
+            .find(|i| i.def.field == field && key_prefix.starts_with(i.def.prefix.as_str()));
+
+        if let Some(index) = usable {
+            if let Some(mut keys) = index_candidates(index, p) {
+                keys.sort();
+                keys.dedup();
+                return Some((index.def.name.clone(), keys));
+            }
+        }
+    }
+
+    None
+}
+
+//
+// Sorting and projection
+//
+
+/// Sort order of one field: scalars in index order, then arrays and
+/// objects, then missing fields.
+fn sort_cmp(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    let rank = |v: Option<&Value>| match v {
+        Some(v) if IndexKey::from_value(v).is_some() => 0,
+        Some(_) => 1,
+        None => 2,
+    };
+
+    match (a.and_then(IndexKey::from_value), b.and_then(IndexKey::from_value)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// `{ "<field>": value, ... }` for the selected fields present in `value`.
+fn project(value: &Value, select: &[String]) -> Value {
+    let fields: Map<String, Value> = select
+        .iter()
+        .filter_map(|f| value.pointer(f).map(|v| (f.clone(), v.clone())))
+        .collect();
+    Value::Object(fields)
+}
+
+/// Run `query` against `store`.
+///
+/// Candidates come from a secondary index when one covers a comparison in
+/// the top-level conjunction, otherwise from a scan of the query's key
+/// prefix (a `key_prefix` conjunct, or all keys).
+pub fn run(store: &KvStore, query: &Query) -> Result<QueryPage, DodoError> {
+    if let Some(p) = &query.filter {
+        p.validate()?;
+    }
+    for f in query.sort.iter().map(|s| &s.field).chain(query.select.iter().flatten()) {
+        check_field(f)?;
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let conjuncts = query.filter.as_ref().map(Predicate::conjuncts).unwrap_or_default();
+    let key_prefix = conjuncts
+        .iter()
+        .filter_map(|p| match p {
+            Predicate::KeyPrefix(prefix) => Some(prefix.as_str()),
+            _ => None,
+        })
+        .max_by_key(|prefix| prefix.len())
+        .unwrap_or("");
+
+    let map = store.read_all();
+    let now = Utc::now().timestamp();
+    let planned = plan(store, &conjuncts, key_prefix);
+    let index = planned.as_ref().map(|(name, _)| name.clone());
+
+    let candidates: Box<dyn Iterator<Item = (&String, &Arc<Value>)>> = match &planned {
+        Some((_, keys)) => Box::new(keys.iter().filter_map(|k| {
+            let entry = map.get(k).filter(|e| !e.is_expired(now))?;
+            Some((k, &entry.value))
+        })),
+        None => Box::new(kv_service::scan_prefix(&map, key_prefix, now).map(|(k, e)| (k, &e.value))),
+    };
+    let mut matching = candidates
+        .filter(|(k, v)| query.filter.as_ref().is_none_or(|p| p.matches(k, v)));
+
+    // Candidates arrive in key order, so unsorted queries stop early.
+    let (page, more): (Vec<_>, bool) = if query.sort.is_empty() {
+        let page: Vec<_> = matching.by_ref().skip(query.offset).take(limit).collect();
+        let more = matching.next().is_some();
+        (page, more)
+    } else {
+        let mut all: Vec<_> = matching.collect();
+        all.sort_by(|(ka, va), (kb, vb)| {
+            query
+                .sort
+                .iter()
+                .map(|s| {
+                    let ord = sort_cmp(va.pointer(&s.field), vb.pointer(&s.field));
+                    if s.desc { ord.reverse() } else { ord }
+                })
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| ka.cmp(kb))
+        });
+        let more = all.len() > query.offset.saturating_add(limit);
+        (all.into_iter().skip(query.offset).take(limit).collect(), more)
+    };
+
+    let results = page
+        .into_iter()
+        .map(|(k, v)| QueryHit {
+            key: k.clone(),
+            value: match &query.select {
+                Some(select) => project(v, select),
+                None => (**v).clone(),
+            },
+        })
+        .collect();
+
+    Ok(QueryPage {
+        results,
+        next: more.then(|| query.offset + limit),
+        index,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::kv_service::{Expiry, Precondition};
+    use crate::state::index::IndexDef;
+    use crate::state::kv::new_store;
+    use serde_json::{json, Number};
+
+    fn keys(store: &KvStore, query: Value) -> (Vec<String>, Option<String>) {
+        let query: Query = serde_json::from_value(query).unwrap();
+        let page = run(store, &query).unwrap();
+        (page.results.into_iter().map(|h| h.key).collect(), page.index)
+    }
+
+    #[tokio::test]
+    async fn negative_zero_matches_zero_with_and_without_an_index() {
+        let plain = new_store("query-plain", 4);
+        let indexed = new_store("query-indexed", 4);
+        indexed.indexes().add(Arc::new(Index::new(IndexDef {
+            name: "by_f".to_string(),
+            prefix: "r:".to_string(),
+            field: "/f".to_string(),
+        })));
+
+        // serde_json reads -0.0 as a negative zero float.
+        let values = [json!({"f": 0}), serde_json::from_str("{\"f\": -0.0}").unwrap(), json!({"f": 1})];
+        for store in [&plain, &indexed] {
+            for (i, value) in values.iter().enumerate() {
+                let key = format!("r:{}", i);
+                kv_service::set(store, key, value.clone(), Expiry::None, &Precondition::None).unwrap();
+            }
+        }
+
+        for query in [
+            json!({"where": {"and": [{"key_prefix": "r:"}, {"eq": {"field": "/f", "value": 0}}]}}),
+            json!({"where": {"and": [{"key_prefix": "r:"}, {"lte": {"field": "/f", "value": -0.0}}]}}),
+        ] {
+            let (scanned, none) = keys(&plain, query.clone());
+            let (found, index) = keys(&indexed, query);
+            assert_eq!(none, None);
+            assert_eq!(index.as_deref(), Some("by_f"));
+            assert_eq!(scanned, vec!["r:0", "r:1"]);
+            assert_eq!(found, scanned);
+        }
+    }
+
+    #[tokio::test]
+    async fn large_integers_compare_exactly_with_and_without_an_index() {
+        let plain = new_store("query-big-plain", 4);
+        let indexed = new_store("query-big-indexed", 4);
+        indexed.indexes().add(Arc::new(Index::new(IndexDef {
+            name: "by_id".to_string(),
+            prefix: "r:".to_string(),
+            field: "/id".to_string(),
+        })));
+
+        let big = 9007199254740992u64;
+        let values = [json!({"id": big + 1}), json!({"id": big}), json!({"id": big as f64})];
+        for store in [&plain, &indexed] {
+            for (i, value) in values.iter().enumerate() {
+                let key = format!("r:{}", i);
+                kv_service::set(store, key, value.clone(), Expiry::None, &Precondition::None).unwrap();
+            }
+        }
+
+        for (query, expected) in [
+            (json!({"eq": {"field": "/id", "value": big}}), vec!["r:1", "r:2"]),
+            (json!({"eq": {"field": "/id", "value": big + 1}}), vec!["r:0"]),
+            (json!({"gt": {"field": "/id", "value": big}}), vec!["r:0"]),
+            (json!({"lt": {"field": "/id", "value": big + 1}}), vec!["r:1", "r:2"]),
+        ] {
+            let query = json!({"where": {"and": [{"key_prefix": "r:"}, query]}});
+            let (scanned, _) = keys(&plain, query.clone());
+            let (found, index) = keys(&indexed, query);
+            assert_eq!(index.as_deref(), Some("by_id"));
+            assert_eq!(scanned, expected);
+            assert_eq!(found, scanned);
+        }
+
+        let sorted = json!({"where": {"key_prefix": "r:"}, "sort": [{"field": "/id", "desc": true}]});
+        assert_eq!(keys(&plain, sorted).0, ["r:0", "r:1", "r:2"]);
+    }
+
+    fn num(v: Value) -> Number {
+        let Value::Number(n) = v else { panic!("not a number") };
+        n
+    }
+
+    #[test]
+    fn numbers_compare_exactly() {
+        let big = 9007199254740992u64;
+        let cmp = |a: Value, b: Value| number_cmp(&num(a), &num(b));
+
+        assert_eq!(cmp(json!(1), json!(1.0)), Ordering::Equal);
+        assert_eq!(cmp(json!(0), serde_json::from_str("-0.0").unwrap()), Ordering::Equal);
+        assert_eq!(cmp(json!(big + 1), json!(big)), Ordering::Greater);
+        assert_eq!(cmp(json!(big + 1), json!(big as f64)), Ordering::Greater);
+        assert_eq!(cmp(json!(big as f64), json!(big)), Ordering::Equal);
+        assert_eq!(cmp(json!(u64::MAX), json!(i64::MIN)), Ordering::Greater);
+        assert_eq!(cmp(json!(u64::MAX), json!(1e20)), Ordering::Less);
+        assert_eq!(cmp(json!(-1.5), json!(-1)), Ordering::Less);
+        assert_eq!(cmp(json!(2), json!(1.5)), Ordering::Greater);
+    }
+}
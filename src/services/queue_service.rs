@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::time::{sleep, Duration};
+
+use crate::errors::DodoError;
+use crate::services::kv_service;
+use crate::state::kv::{json_size, KvStore};
+use crate::state::queue::{Message, Queue, MESSAGE_OVERHEAD};
+
+fn not_in_flight(name: &str, receipt: u64) -> DodoError {
+    DodoError::Conflict(format!(
+        "receipt {} is not in flight in queue '{}' (already acked or timed out)",
+        receipt, name
+    ))
+}
+
+/// Append messages with the given bodies. Returns their ids.
+///
+/// `InsufficientStorage` if the messages do not fit in the memory limit
+/// (see `kv_service::make_room`; queued messages are never evicted).
+pub fn enqueue(store: &KvStore, name: &str, bodies: Vec<Value>) -> Result<Vec<u64>, DodoError> {
+    let bodies: Vec<Arc<Value>> = bodies.into_iter().map(Arc::new).collect();
+    let growth = bodies.iter().map(|b| json_size(b) + MESSAGE_OVERHEAD).sum();
+    kv_service::make_room(store, &[], growth)?;
+
+    let now = Utc::now().timestamp();
+    Ok(store.queues().lock().enqueue(name, now, bodies))
+}
+
+/// Take up to `count` messages from the front of the queue. They stay
+/// invisible to other consumers for `visibility` seconds, unless acked
+/// or nacked first.
+pub fn dequeue(store: &KvStore, name: &str, count: usize, visibility: u64) -> Result<Vec<Message>, DodoError> {
+    if visibility == 0 {
+        return Err(DodoError::InvalidRequest(
+            "visibility timeout must be at least 1 second".to_string(),
+        ));
+    }
+
+    let now = Utc::now().timestamp();
+    let visible_at = now.saturating_add_unsigned(visibility);
+    Ok(store.queues().lock().dequeue(name, now, count, visible_at))
+}
+
+/// Acknowledge a delivery: the message is done and removed.
+///
+/// Timed-out messages are returned to the queue first, so a late ack
+/// never wins over a redelivery.
+pub fn ack(store: &KvStore, name: &str, receipt: u64) -> Result<(), DodoError> {
+    let now = Utc::now().timestamp();
+    if store.queues().lock().ack(name, now, receipt) {
+        Ok(())
+    } else {
+        Err(not_in_flight(name, receipt))
+    }
+}
+
+/// Give a delivery back: the message returns to the front of the queue
+/// (or to the dead letters) now, or after `delay` seconds.
+pub fn nack(store: &KvStore, name: &str, receipt: u64, delay: u64) -> Result<(), DodoError> {
+    let now = Utc::now().timestamp();
+    let visible_at = (delay > 0).then(|| now.saturating_add_unsigned(delay));
+
+    if store.queues().lock().nack(name, now, receipt, visible_at) {
+        Ok(())
+    } else {
+        Err(not_in_flight(name, receipt))
+    }
+}
+
+/// Queue length and in-flight counts, as returned by GET /queues/:name.
+#[derive(Debug, Serialize)]
+pub struct QueueStats {
+    pub name: String,
+    pub ready: usize,
+    pub in_flight: usize,
+    pub dead: usize,
+    pub max_deliveries: u64,
+    /// When the oldest ready message was enqueued.
+    pub oldest_enqueued_at: Option<i64>,
+}
+
+impl QueueStats {
+    fn of(name: &str, queue: Option<&Queue>) -> Self {
+        let empty = Queue::default();
+        let queue = queue.unwrap_or(&empty);
+
+        QueueStats {
+            name: name.to_string(),
+            ready: queue.ready().len(),
+            in_flight: queue.in_flight_len(),
+            dead: queue.dead().len(),
+            max_deliveries: queue.max_deliveries(),
+            oldest_enqueued_at: queue.ready().iter().map(|m| m.enqueued_at).min(),
+        }
+    }
+}
+
+/// Counts of queue `name` (an empty queue if it does not exist).
+pub fn stats(store: &KvStore, name: &str) -> QueueStats {
+    QueueStats::of(name, store.queues().lock().get(name))
+}
+
+/// Stats of every queue, by name.
+pub fn list(store: &KvStore) -> Vec<QueueStats> {
+    let queues = store.queues().lock();
+    let mut all: Vec<QueueStats> = queues.iter().map(|(name, q)| QueueStats::of(name, Some(q))).collect();
+    all.sort_by(|a, b| a.name.cmp(&b.name));
+    all
+}
+
+/// Set how many deliveries a message gets before it is dead-lettered.
+pub fn set_max_deliveries(store: &KvStore, name: &str, max_deliveries: u64) -> Result<QueueStats, DodoError> {
+    if max_deliveries == 0 {
+        return Err(DodoError::InvalidRequest("max_deliveries must be at least 1".to_string()));
+    }
+
+    let now = Utc::now().timestamp();
+    let mut queues = store.queues().lock();
+    queues.set_max_deliveries(name, now, max_deliveries);
+    Ok(QueueStats::of(name, queues.get(name)))
+}
+
+/// Messages that exhausted their deliveries, oldest first.
+pub fn dead_letters(store: &KvStore, name: &str) -> Vec<Message> {
+    store
+        .queues()
+        .lock()
+        .get(name)
+        .map_or_else(Vec::new, |q| q.dead().to_vec())
+}
+
+/// Move every dead letter back to the end of the queue with a fresh
+/// delivery count. Returns how many were moved.
+pub fn requeue_dead(store: &KvStore, name: &str) -> usize {
+    let now = Utc::now().timestamp();
+    store.queues().lock().requeue_dead(name, now)
+}
+
+/// Delete queue `name` with all its messages, in flight or dead.
+/// Returns whether it existed.
+pub fn delete(store: &KvStore, name: &str) -> bool {
+    store.queues().lock().remove(name)
+}
+
+/// Return the expired in-flight messages of every queue in `store` to
+/// their queue (or dead letters). Returns how many were moved.
+pub fn requeue_expired(store: &KvStore) -> usize {
+    let now = Utc::now().timestamp();
+    store.queues().lock().reclaim_all(now)
+}
+
+/// Background task that periodically returns timed-out in-flight
+/// messages to their queues.
+pub async fn requeue_loop(store: KvStore, every_sec: u64) {
+    loop {
+        sleep(Duration::from_secs(every_sec)).await;
+
+        let moved = requeue_expired(&store);
+        if moved > 0 {
+            tracing::info!("Queues: returned {} timed-out messages in '{}'", moved, store.name());
+        }
+    }
+}
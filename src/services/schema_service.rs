@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::errors::DodoError;
+use crate::services::json_schema::{self, Violation};
+use crate::state::db::DbRegistry;
+
+/// A JSON Schema enforced on writes to keys starting with `prefix`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaRule {
+    pub prefix: String,
+    pub schema: Value,
+}
+
+lazy_static! {
+    /// Schemas by database, then by key prefix.
+    static ref SCHEMAS: RwLock<HashMap<String, BTreeMap<String, Value>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Schema file of a database, next to its snapshot:
+/// `snapshot.json` -> `snapshot.schemas.json`.
+fn schemas_path(snapshot_path: &str) -> String {
+    let path = Path::new(snapshot_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("snapshot");
+    path.with_file_name(format!("{}.schemas.json", stem))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Register (or replace) the schema of `prefix` on database `db`.
+///
+/// Only later writes are validated; existing keys are left as they are.
+pub fn register(registry: &DbRegistry, db: &str, rule: SchemaRule) -> Result<(), DodoError> {
+    let database = registry
+        .get(db)
+        .ok_or_else(|| DodoError::DatabaseNotFound(db.to_string()))?;
+
+    json_schema::check_schema(&rule.schema).map_err(DodoError::InvalidRequest)?;
+
+    SCHEMAS
+        .write()
+        .unwrap()
+        .entry(db.to_string())
+        .or_default()
+        .insert(rule.prefix, rule.schema);
+
+    save(db, &database.snapshot_path);
+    Ok(())
+}
+
+/// Remove the schema of `prefix` on database `db`.
+pub fn unregister(registry: &DbRegistry, db: &str, prefix: &str) -> Result<(), DodoError> {
+    let database = registry
+        .get(db)
+        .ok_or_else(|| DodoError::DatabaseNotFound(db.to_string()))?;
+
+    let removed = SCHEMAS
+        .write()
+        .unwrap()
+        .get_mut(db)
+        .and_then(|rules| rules.remove(prefix))
+        .is_some();
+
+    if !removed {
+        return Err(DodoError::NotFound);
+    }
+
+    save(db, &database.snapshot_path);
+    Ok(())
+}
+
+/// Schemas of database `db`, by prefix.
+pub fn list(db: &str) -> Vec<SchemaRule> {
+    SCHEMAS
+        .read()
+        .unwrap()
+        .get(db)
+        .map(|rules| {
+            rules
+                .iter()
+                .map(|(prefix, schema)| SchemaRule {
+                    prefix: prefix.clone(),
+                    schema: schema.clone(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Validate the values about to be written to keys of database `db`.
+///
+/// Each key is checked against the schema of its longest matching
+/// prefix, if any. Every violation of every key is reported at once.
+pub fn check<'a>(db: &str, writes: impl IntoIterator<Item = (&'a str, &'a Value)>) -> Result<(), DodoError> {
+    let schemas = SCHEMAS.read().unwrap();
+    let Some(rules) = schemas.get(db).filter(|rules| !rules.is_empty()) else {
+        return Ok(());
+    };
+
+    let mut violations = Vec::new();
+    for (key, value) in writes {
+        let schema = rules
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, schema)| schema);
+
+        if let Some(schema) = schema {
+            violations.extend(json_schema::validate(schema, value).into_iter().map(|v| Violation {
+                key: key.to_string(),
+                ..v
+            }));
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(DodoError::SchemaViolation(violations))
+    }
+}
+
+/// Write the schemas of database `db` next to its snapshot.
+fn save(db: &str, snapshot_path: &str) {
+    let json = serde_json::to_string_pretty(&list(db)).unwrap();
+    if let Err(e) = fs::write(schemas_path(snapshot_path), json) {
+        tracing::warn!("Failed to write schemas of '{}': {e}", db);
+    }
+}
+
+/// Load the schemas of database `db` from next to its snapshot (startup).
+pub fn load(db: &str, snapshot_path: &str) {
+    let rules: Vec<SchemaRule> = match fs::read_to_string(schemas_path(snapshot_path)) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            tracing::warn!("Failed to parse schemas of '{}': {e}", db);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+
+    let mut valid = BTreeMap::new();
+    for rule in rules {
+        match json_schema::check_schema(&rule.schema) {
+            Ok(()) => {
+                valid.insert(rule.prefix, rule.schema);
+            }
+            Err(e) => tracing::warn!("Skipping schema of '{}' prefix '{}': {e}", db, rule.prefix),
+        }
+    }
+
+    SCHEMAS.write().unwrap().insert(db.to_string(), valid);
+}
+
+/// Forget the schemas of database `db` and delete their file (used when
+/// the database is dropped).
+pub fn remove_database(db: &str, snapshot_path: &str) {
+    SCHEMAS.write().unwrap().remove(db);
+
+    if let Err(e) = fs::remove_file(schemas_path(snapshot_path)) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!("Failed to remove schemas of '{}': {e}", db);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::state::kv::KvStore;
+
+/// Persistent description of a named database (stored in the catalog file).
+///
+/// `retention_seconds` falls back to `AppConfig` when `None`. The
+/// snapshot path is always derived from the name (never taken from a
+/// request), so a database cannot point at arbitrary files or share
+/// them with another database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbSpec {
+    pub name: String,
+    #[serde(default)]
+    pub retention_seconds: Option<u64>,
+}
+
+/// A live database: its store, effective settings, the router serving
+/// its `/kv` and `/pubsub` routes, and its background loops.
+pub struct Database {
+    pub spec: DbSpec,
+    pub store: KvStore,
+    pub snapshot_path: String,
+    pub retention_seconds: Option<u64>,
+    pub router: Router,
+    pub tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Database {
+    /// Stop the autosave / cleanup loops once the database is gone.
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// All open databases, by name.
+#[derive(Clone, Default)]
+pub struct DbRegistry {
+    dbs: Arc<RwLock<HashMap<String, Arc<Database>>>>,
+}
+
+impl DbRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Database>> {
+        self.dbs.read().unwrap().get(name).cloned()
+    }
+
+    /// Insert `db` unless a database with the same name exists.
+    /// Returns whether it was inserted.
+    pub fn insert(&self, db: Arc<Database>) -> bool {
+        let mut dbs = self.dbs.write().unwrap();
+        if dbs.contains_key(db.store.name()) {
+            return false;
+        }
+        dbs.insert(db.store.name().to_string(), db);
+        true
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Arc<Database>> {
+        self.dbs.write().unwrap().remove(name)
+    }
+
+    /// All databases, sorted by name.
+    pub fn all(&self) -> Vec<Arc<Database>> {
+        let mut dbs: Vec<_> = self.dbs.read().unwrap().values().cloned().collect();
+        dbs.sort_by(|a, b| a.spec.name.cmp(&b.spec.name));
+        dbs
+    }
+}
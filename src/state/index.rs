@@ -0,0 +1,246 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+/// Declaration of a secondary index: every key starting with `prefix`
+/// whose value has a scalar at the JSON Pointer `field` is indexed by
+/// that scalar.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexDef {
+    pub name: String,
+    pub prefix: String,
+    pub field: String,
+}
+
+/// Indexed value. Scalars of different JSON types are ordered
+/// null < booleans < numbers < strings; arrays and objects are not indexed.
+///
+/// Numbers are ordered by value with `number_cmp`, like in query
+/// comparisons, so `-0.0`, `0.0` and `0` are one key.
+#[derive(Debug, Clone)]
+pub enum IndexKey {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+}
+
+impl IndexKey {
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(IndexKey::Null),
+            Value::Bool(b) => Some(IndexKey::Bool(*b)),
+            Value::Number(n) => Some(IndexKey::Number(n.clone())),
+            Value::String(s) => Some(IndexKey::String(s.clone())),
+            Value::Array(_) | Value::Object(_) => None,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            IndexKey::Null => 0,
+            IndexKey::Bool(_) => 1,
+            IndexKey::Number(_) => 2,
+            IndexKey::String(_) => 3,
+        }
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (IndexKey::Bool(a), IndexKey::Bool(b)) => a.cmp(b),
+            (IndexKey::Number(a), IndexKey::Number(b)) => number_cmp(a, b),
+            (IndexKey::String(a), IndexKey::String(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+/// Order two JSON numbers by value (`1 == 1.0`, `-0.0 == 0`).
+///
+/// Two integers compare exactly over the whole `i64` and `u64` range, and
+/// so does an integer with a float: rounding the integer to `f64` would
+/// make `2^53 + 1` equal to `2^53` as a float but not to `2^53` as an
+/// integer, and the order would no longer be total.
+pub fn number_cmp(a: &Number, b: &Number) -> Ordering {
+    let integer = |n: &Number| n.as_i64().map(i128::from).or_else(|| n.as_u64().map(i128::from));
+
+    match (integer(a), integer(b)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        (Some(x), None) => integer_float_cmp(x, float(b)),
+        (None, Some(y)) => integer_float_cmp(y, float(a)).reverse(),
+        (None, None) => float(a).partial_cmp(&float(b)).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// A JSON number that is not an integer (always finite).
+fn float(n: &Number) -> f64 {
+    n.as_f64().unwrap_or(0.0)
+}
+
+/// Exact order of an integer and a float.
+fn integer_float_cmp(i: i128, f: f64) -> Ordering {
+    // Integers stay within ±2^64, where whole floats convert exactly.
+    const LIMIT: f64 = 18_446_744_073_709_551_616.0;
+    let whole = f.trunc();
+    if whole >= LIMIT {
+        return Ordering::Less;
+    }
+    if whole <= -LIMIT {
+        return Ordering::Greater;
+    }
+
+    i.cmp(&(whole as i128)).then(if f > whole {
+        Ordering::Less
+    } else if f < whole {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    })
+}
+
+#[derive(Default)]
+struct IndexData {
+    by_value: BTreeMap<IndexKey, BTreeSet<String>>,
+    by_key: HashMap<String, IndexKey>,
+}
+
+/// A live index: its definition and the value -> keys mapping.
+pub struct Index {
+    pub def: IndexDef,
+    data: RwLock<IndexData>,
+}
+
+impl Index {
+    pub fn new(def: IndexDef) -> Self {
+        Index {
+            def,
+            data: RwLock::new(IndexData::default()),
+        }
+    }
+
+    pub fn covers(&self, key: &str) -> bool {
+        key.starts_with(&self.def.prefix)
+    }
+
+    /// Reindex `key` after it was written (`Some(value)`) or removed (`None`).
+    pub fn update(&self, key: &str, value: Option<&Value>) {
+        let new = value
+            .and_then(|v| v.pointer(&self.def.field))
+            .and_then(IndexKey::from_value);
+
+        let mut data = self.data.write().unwrap();
+
+        if let Some(old) = data.by_key.remove(key) {
+            if let Some(keys) = data.by_value.get_mut(&old) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    data.by_value.remove(&old);
+                }
+            }
+        }
+
+        if let Some(new) = new {
+            data.by_value.entry(new.clone()).or_default().insert(key.to_string());
+            data.by_key.insert(key.to_string(), new);
+        }
+    }
+
+    /// Keys whose indexed value is within `(lower, upper)`, ordered by
+    /// value then key, at most `limit` of them.
+    pub fn lookup(&self, lower: Bound<&IndexKey>, upper: Bound<&IndexKey>, limit: usize) -> Vec<String> {
+        let data = self.data.read().unwrap();
+
+        // BTreeMap::range panics on inverted or empty-excluded bounds.
+        let empty = match (lower, upper) {
+            (Bound::Included(a), Bound::Included(b)) => a > b,
+            (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => a >= b,
+            _ => false,
+        };
+        if empty {
+            return Vec::new();
+        }
+
+        data.by_value
+            .range::<IndexKey, _>((lower, upper))
+            .flat_map(|(_, keys)| keys.iter().cloned())
+            .take(limit)
+            .collect()
+    }
+
+    /// Number of indexed keys.
+    pub fn len(&self) -> usize {
+        self.data.read().unwrap().by_key.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The indexes of one store, shared with its shards so every write keeps
+/// them up to date (under the shard's lock).
+#[derive(Default)]
+pub struct IndexSet {
+    indexes: RwLock<Vec<Arc<Index>>>,
+}
+
+impl IndexSet {
+    /// Reindex `key` in every index covering it.
+    pub fn on_write(&self, key: &str, value: Option<&Value>) {
+        let indexes = self.indexes.read().unwrap();
+        for index in indexes.iter().filter(|i| i.covers(key)) {
+            index.update(key, value);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Index>> {
+        self.indexes
+            .read()
+            .unwrap()
+            .iter()
+            .find(|i| i.def.name == name)
+            .cloned()
+    }
+
+    /// Register `index` unless one with the same name exists.
+    /// Returns whether it was added.
+    pub fn add(&self, index: Arc<Index>) -> bool {
+        let mut indexes = self.indexes.write().unwrap();
+        if indexes.iter().any(|i| i.def.name == index.def.name) {
+            return false;
+        }
+        indexes.push(index);
+        true
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Arc<Index>> {
+        let mut indexes = self.indexes.write().unwrap();
+        let pos = indexes.iter().position(|i| i.def.name == name)?;
+        Some(indexes.remove(pos))
+    }
+
+    /// All indexes, in creation order.
+    pub fn all(&self) -> Vec<Arc<Index>> {
+        self.indexes.read().unwrap().clone()
+    }
+}
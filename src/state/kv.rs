@@ -1,25 +1,945 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::ops::{Bound, Deref};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-/// A single KV entry with a value and creation timestamp.
+use super::index::IndexSet;
+use super::lock::Locks;
+use super::queue::Queues;
+use super::wal::{Record, Wal};
+use super::watch::Watchers;
+
+/// A single KV entry with a value and its metadata.
+///
+/// `value` is stored already parsed and shared behind an `Arc`, so reads
+/// hand out cheap clones instead of re-parsing JSON text.
 ///
 /// `created_at` is the Unix timestamp (seconds since epoch) at which
-/// the key was last set.
+/// the key was first set, `updated_at` the one of its last write, and
+/// `write_count` the number of writes since it was created.
+///
+/// `expires_at` is an optional absolute Unix timestamp after which the
+/// key is considered gone. `None` means the key never expires on its own
+/// (the global `retention_seconds` still applies).
+///
+/// `sliding` is an optional idle timeout (seconds): the key also expires
+/// once it has not been read or written for that long. It is checked
+/// against `access`, so reads extend it without a write lock (see
+/// `deadline`).
+///
+/// `version` changes on every write and is exposed as the key's ETag.
+/// It is drawn from a process-wide counter (see `next_version`), so a key
+/// that is deleted and re-created never reuses an earlier version.
+///
+/// `data_type` says how the value is used: plain JSON, or one of the
+/// native collections whose operations keep it in a fixed shape (see
+/// `DataType`).
+///
+/// `size` is the approximate memory used by the value (its JSON length),
+/// and `access` tracks reads (used by `/meta` and the eviction policies).
+/// Neither is persisted.
+///
+/// `scores` is the member -> score index of a sorted set, kept next to
+/// its array by the sorted set operations so members are found without a
+/// scan. It is not persisted and is `None` until the next of those
+/// operations rebuilds it (e.g. after a load or a rollback).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
-    pub value: String,
+    pub value: Arc<Value>,
     pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub version: u64,
+    #[serde(default)]
+    pub write_count: u64,
+    #[serde(default)]
+    pub data_type: DataType,
+    #[serde(default)]
+    pub sliding: Option<u64>,
+    #[serde(skip)]
+    pub size: usize,
+    #[serde(skip)]
+    pub access: Access,
+    #[serde(skip)]
+    pub scores: Option<Arc<ZScores>>,
+}
+
+impl Entry {
+    /// Build the entry of a key first written at `now`, computing its
+    /// size. The entry counts as accessed now.
+    pub fn new(value: Arc<Value>, now: i64, expires_at: Option<i64>, version: u64) -> Self {
+        Entry {
+            size: json_size(&value),
+            value,
+            created_at: now,
+            updated_at: now,
+            expires_at,
+            version,
+            write_count: 1,
+            data_type: DataType::Json,
+            sliding: None,
+            access: Access::default(),
+            scores: None,
+        }
+    }
+
+    /// Make this entry the next write of `previous` (the live entry it
+    /// replaces): keep its creation time, write count and read count.
+    pub fn succeed(&mut self, previous: &Entry) {
+        self.created_at = previous.created_at;
+        self.write_count = previous.write_count + 1;
+        self.access.reads.store(previous.access.reads(), Ordering::Relaxed);
+    }
+
+    /// When the entry expires as of now: the earlier of `expires_at` and,
+    /// for a sliding entry, its last access plus the idle timeout.
+    pub fn deadline(&self) -> Option<i64> {
+        let idle = self
+            .sliding
+            .map(|secs| self.last_active().saturating_add_unsigned(secs));
+
+        match (self.expires_at, idle) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// True if the entry has a per-key expiry that is already in the past.
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.deadline().is_some_and(|t| t <= now)
+    }
+
+    /// Unix timestamp the entry's age is counted from: its last access
+    /// for a sliding entry, its last write otherwise.
+    pub fn last_active(&self) -> i64 {
+        match self.sliding {
+            Some(_) => self.access.last_millis().div_euclid(1000),
+            None => self.updated_at,
+        }
+    }
+}
+
+/// Type of the value held by a key.
+///
+/// Collections are stored as JSON too, so reads, snapshots and Pub/Sub
+/// events see them like any other value:
+/// - `List`: an array
+/// - `Set`: a sorted array of unique strings
+/// - `Hash`: an object
+/// - `ZSet`: an array of `{"member": <string>, "score": <number>}`
+///   objects with unique members, sorted by score then member
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+    #[default]
+    Json,
+    List,
+    Set,
+    Hash,
+    ZSet,
+}
+
+impl DataType {
+    pub fn name(self) -> &'static str {
+        match self {
+            DataType::Json => "json",
+            DataType::List => "list",
+            DataType::Set => "set",
+            DataType::Hash => "hash",
+            DataType::ZSet => "zset",
+        }
+    }
+
+    /// Value of a key of this type that does not exist yet.
+    pub fn empty(self) -> Value {
+        match self {
+            DataType::Json => Value::Null,
+            DataType::List | DataType::Set | DataType::ZSet => Value::Array(Vec::new()),
+            DataType::Hash => Value::Object(serde_json::Map::new()),
+        }
+    }
+
+    /// Whether `value` has the shape this type requires.
+    pub fn accepts(self, value: &Value) -> bool {
+        match self {
+            DataType::Json => true,
+            DataType::List => value.is_array(),
+            DataType::Set => value
+                .as_array()
+                .is_some_and(|a| a.iter().all(Value::is_string)),
+            DataType::Hash => value.is_object(),
+            DataType::ZSet => value.as_array().is_some_and(|a| {
+                a.iter().all(|m| {
+                    m.get("member").is_some_and(Value::is_string)
+                        && m.get("score").is_some_and(Value::is_number)
+                })
+            }),
+        }
+    }
+}
+
+/// Member -> score of a sorted set (see `Entry::scores`).
+pub type ZScores = HashMap<String, f64>;
+
+/// Access tracking for an entry, updated through shared references
+/// (reads only hold a shard's read lock).
+#[derive(Debug)]
+pub struct Access {
+    /// Last read or write, Unix timestamp in milliseconds.
+    last: AtomicI64,
+    /// Number of reads.
+    reads: AtomicU64,
+}
+
+impl Access {
+    /// Record a read now.
+    pub fn touch(&self) {
+        self.last.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn last_millis(&self) -> i64 {
+        self.last.load(Ordering::Relaxed)
+    }
+
+    /// Restore the last access time (e.g. from a snapshot).
+    pub fn set_last_millis(&self, millis: i64) {
+        self.last.store(millis, Ordering::Relaxed);
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Access {
+            last: AtomicI64::new(Utc::now().timestamp_millis()),
+            reads: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Clone for Access {
+    fn clone(&self) -> Self {
+        Access {
+            last: AtomicI64::new(self.last_millis()),
+            reads: AtomicU64::new(self.reads()),
+        }
+    }
+}
+
+/// Length of the JSON text of `value`, without allocating it.
+pub fn json_size(value: &Value) -> usize {
+    struct Counter(usize);
+
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+/// Fixed per-key bookkeeping cost added to `key.len() + entry.size` when
+/// accounting memory (map node, `Arc`, metadata).
+pub const ENTRY_OVERHEAD: usize = 96;
+
+/// Approximate memory used by `key` -> `entry`.
+pub fn entry_bytes(key: &str, entry: &Entry) -> usize {
+    key.len() + entry.size + ENTRY_OVERHEAD
+}
+
+/// What to do when a write would take a store past `max_memory_bytes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Reject the write.
+    #[default]
+    #[serde(rename = "noeviction")]
+    NoEviction,
+    /// Evict the least recently used keys.
+    #[serde(rename = "allkeys-lru")]
+    AllKeysLru,
+    /// Evict the least frequently used keys.
+    #[serde(rename = "allkeys-lfu")]
+    AllKeysLfu,
+    /// Evict keys with a per-key expiry, soonest to expire first.
+    #[serde(rename = "volatile-ttl")]
+    VolatileTtl,
+}
+
+/// Internal map type (one per shard).
+///
+/// Ordered by key, so listings are lexicographic and prefix scans are
+/// range queries (merged across shards, see `ShardsRead::range`).
+pub type InnerMap = BTreeMap<String, Entry>;
+
+/// A previous version of a key, kept by the value history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub version: u64,
+    pub updated_at: i64,
+    #[serde(rename = "type", default)]
+    pub data_type: DataType,
+    pub value: Arc<Value>,
+}
+
+impl Revision {
+    fn of(entry: &Entry) -> Self {
+        Revision {
+            version: entry.version,
+            updated_at: entry.updated_at,
+            data_type: entry.data_type,
+            value: entry.value.clone(),
+        }
+    }
+}
+
+/// How many previous versions to keep per key.
+///
+/// `prefixes` override `default` for the keys they match (longest prefix
+/// wins, so a full key name configures a single key). A depth of 0
+/// disables the history.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryPolicy {
+    pub default: usize,
+    pub prefixes: Vec<(String, usize)>,
+}
+
+impl HistoryPolicy {
+    pub fn depth(&self, key: &str) -> usize {
+        self.prefixes
+            .iter()
+            .filter(|(p, _)| key.starts_with(p.as_str()))
+            .max_by_key(|(p, _)| p.len())
+            .map_or(self.default, |(_, depth)| *depth)
+    }
+}
+
+/// Per-prefix idle timeouts: keys written without an expiry of their own
+/// under one of `prefixes` get a sliding expiry of that many seconds
+/// (longest prefix wins).
+#[derive(Debug, Clone, Default)]
+pub struct SlidingPolicy {
+    pub prefixes: Vec<(String, u64)>,
+}
+
+impl SlidingPolicy {
+    pub fn idle_timeout(&self, key: &str) -> Option<u64> {
+        self.prefixes
+            .iter()
+            .filter(|(p, _)| key.starts_with(p.as_str()))
+            .max_by_key(|(p, _)| p.len())
+            .map(|(_, secs)| *secs)
+    }
+}
+
+/// One shard of a store: its map plus memory accounting and the value
+/// history of its keys.
+///
+/// Reads go through `Deref` to the map; every mutation goes through the
+/// methods below so the store's `used_bytes` stays in sync.
+///
+/// `insert` and `remove` record the replaced entry in the key's history
+/// (newest first). Histories outlive deleted keys so they can be rolled
+/// back, and are not counted in `used_bytes`.
+///
+/// `insert` gives entries without an expiry the sliding expiry of their
+/// prefix, if any (see `SlidingPolicy`).
+///
+/// Every mutation also updates the store's secondary indexes, wakes the
+/// requests waiting on the changed key and, if the store has one, is
+/// appended to its write-ahead log (or to the open batch, see
+/// `ShardsWrite::batch`).
+pub struct Shard {
+    map: InnerMap,
+    used: Arc<AtomicUsize>,
+    history: HashMap<String, VecDeque<Revision>>,
+    history_policy: Arc<HistoryPolicy>,
+    sliding_policy: Arc<SlidingPolicy>,
+    indexes: Arc<IndexSet>,
+    watchers: Arc<Watchers>,
+    wal: Option<Arc<Wal>>,
+    batch: Option<Arc<Mutex<Vec<Record>>>>,
+}
+
+impl Deref for Shard {
+    type Target = InnerMap;
+
+    fn deref(&self) -> &InnerMap {
+        &self.map
+    }
+}
+
+impl Shard {
+    fn log(&self, record: impl FnOnce() -> Record) {
+        match (&self.batch, &self.wal) {
+            (Some(batch), _) => batch.lock().unwrap().push(record()),
+            (None, Some(wal)) => wal.append(&record()),
+            (None, None) => {}
+        }
+    }
+
+    /// Change the metadata of an entry with `f`, which must not replace
+    /// its value (use `insert` for that). Returns what `f` returned, or
+    /// `None` if the key does not exist.
+    pub fn modify<R>(&mut self, key: &str, f: impl FnOnce(&mut Entry) -> R) -> Option<R> {
+        let entry = self.map.get_mut(key)?;
+        let out = f(entry);
+
+        let entry = &self.map[key];
+        self.log(|| Record::Set { key: key.to_string(), entry: entry.clone() });
+        Some(out)
+    }
+
+    pub fn insert(&mut self, key: String, mut entry: Entry) -> Option<Entry> {
+        if entry.expires_at.is_none() && entry.sliding.is_none() {
+            entry.sliding = self.sliding_policy.idle_timeout(&key);
+        }
+        self.log(|| Record::Set { key: key.clone(), entry: entry.clone() });
+
+        let key_len = key.len();
+        self.used.fetch_add(entry_bytes(&key, &entry), Ordering::Relaxed);
+
+        self.indexes.on_write(&key, Some(&entry.value));
+        self.watchers.wake(&key);
+
+        // Only pay for a key copy when its history is kept.
+        let history_key = (self.history_policy.depth(&key) > 0).then(|| key.clone());
+
+        let old = self.map.insert(key, entry);
+        if let Some(e) = &old {
+            self.used.fetch_sub(key_len + e.size + ENTRY_OVERHEAD, Ordering::Relaxed);
+            if let Some(k) = &history_key {
+                self.record(k, e);
+            }
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        let old = self.take(key);
+        if let Some(e) = &old {
+            self.record(key, e);
+            self.log(|| Record::Delete { key: key.to_string() });
+        }
+        old
+    }
+
+    /// Mutable access to an entry that bypasses logging, history, indexes
+    /// and memory accounting: only for moving its value out to edit it in
+    /// place. The caller must then either `insert` the rebuilt entry or put
+    /// back the value unchanged.
+    pub fn entry_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        self.map.get_mut(key)
+    }
+
+    /// Whether replaced values of `key` are kept in its history.
+    pub fn keeps_history(&self, key: &str) -> bool {
+        self.history_policy.depth(key) > 0
+    }
+
+    /// Remove a key without recording it in its history.
+    pub fn evict(&mut self, key: &str) -> Option<Entry> {
+        let old = self.take(key);
+        if old.is_some() {
+            self.log(|| Record::Evict { key: key.to_string() });
+        }
+        old
+    }
+
+    fn take(&mut self, key: &str) -> Option<Entry> {
+        let old = self.map.remove(key);
+        if let Some(e) = &old {
+            self.used.fetch_sub(entry_bytes(key, e), Ordering::Relaxed);
+            self.indexes.on_write(key, None);
+            self.watchers.wake(key);
+        }
+        old
+    }
+
+    fn record(&mut self, key: &str, entry: &Entry) {
+        let depth = self.history_policy.depth(key);
+        if depth == 0 {
+            return;
+        }
+
+        if !self.history.contains_key(key) {
+            self.history.insert(key.to_string(), VecDeque::new());
+        }
+        if let Some(revisions) = self.history.get_mut(key) {
+            revisions.push_front(Revision::of(entry));
+            revisions.truncate(depth);
+        }
+    }
+
+    /// Previous versions of `key`, newest first.
+    pub fn history(&self, key: &str) -> Option<&VecDeque<Revision>> {
+        self.history.get(key)
+    }
+
+    /// Replace the history of `key` (e.g. when loading a snapshot),
+    /// keeping at most the configured depth.
+    pub fn set_history(&mut self, key: String, mut revisions: VecDeque<Revision>) {
+        revisions.truncate(self.history_policy.depth(&key));
+        if revisions.is_empty() {
+            self.history.remove(&key);
+        } else {
+            self.history.insert(key, revisions);
+        }
+    }
+
+    /// Keep only the entries `f` accepts; the others are logged as
+    /// expired.
+    pub fn retain(&mut self, f: impl FnMut(&String, &mut Entry) -> bool) {
+        self.drop_unless(f, true);
+    }
+
+    fn drop_unless(&mut self, mut f: impl FnMut(&String, &mut Entry) -> bool, log: bool) {
+        let used = &self.used;
+        let indexes = &self.indexes;
+        let watchers = &self.watchers;
+        let wal = self.wal.as_ref().filter(|_| log);
+        self.map.retain(|k, e| {
+            let keep = f(k, e);
+            if !keep {
+                used.fetch_sub(entry_bytes(k, e), Ordering::Relaxed);
+                indexes.on_write(k, None);
+                watchers.wake(k);
+                if let Some(wal) = wal {
+                    wal.append(&Record::Expire { key: k.clone() });
+                }
+            }
+            keep
+        });
+    }
+
+    /// Remove every key and all history (logged as a single `Clear` by
+    /// `ShardsWrite::clear`).
+    fn clear(&mut self) {
+        self.drop_unless(|_, _| false, false);
+        self.history.clear();
+    }
 }
 
-/// Internal HashMap type.
-pub type InnerMap = HashMap<String, Entry>;
+/// Name of the database served under the plain `/kv` routes.
+pub const DEFAULT_DB: &str = "default";
+
+/// Number of shards used when the config does not say otherwise.
+pub const DEFAULT_SHARDS: usize = 16;
 
 /// Shared KV store type used across the app.
-pub type KvStore = Arc<RwLock<InnerMap>>;
+///
+/// Keys are hash-partitioned over N shards, each behind its own `RwLock`,
+/// so writes to different keys rarely contend:
+/// - single-key operations lock only `shard(key)`
+/// - multi-key operations take `read_all()` / `write_all()`, which lock
+///   every shard in index order and therefore see a consistent view
+///
+/// Cheap to clone. Also carries the name of the database it belongs to
+/// (used to route Pub/Sub events to that database's subscribers) and its
+/// memory accounting / limit.
+#[derive(Clone)]
+pub struct KvStore {
+    name: Arc<str>,
+    shards: Arc<[RwLock<Shard>]>,
+    indexes: Arc<IndexSet>,
+    watchers: Arc<Watchers>,
+    queues: Arc<Queues>,
+    locks: Arc<Locks>,
+    wal: Option<Arc<Wal>>,
+    used: Arc<AtomicUsize>,
+    evicted: Arc<AtomicU64>,
+    sweep: Arc<Mutex<SweepCursor>>,
+    max_bytes: Option<usize>,
+    policy: EvictionPolicy,
+}
+
+/// Where `KvStore::sample` resumes: a shard and the last key visited in
+/// it (`None` = from its first key).
+#[derive(Default)]
+struct SweepCursor {
+    shard: usize,
+    after: Option<String>,
+}
+
+/// Shard index of `key` among `n` shards.
+///
+/// `DefaultHasher::new()` is deterministic (fixed keys), so a key always
+/// lands in the same shard.
+fn shard_index(key: &str, n: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % n as u64) as usize
+}
+
+impl KvStore {
+    /// Name of the database this store belongs to.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Limit the store to roughly `max_bytes` (see `entry_bytes`), applying
+    /// `policy` when a write would exceed it.
+    pub fn with_memory_limit(mut self, max_bytes: Option<usize>, policy: EvictionPolicy) -> Self {
+        self.max_bytes = max_bytes;
+        self.policy = policy;
+        self
+    }
+
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        self.policy
+    }
+
+    /// Approximate memory currently used by all entries and queued
+    /// messages.
+    pub fn used_bytes(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Secondary indexes of this store (maintained by every write).
+    pub fn indexes(&self) -> &IndexSet {
+        &self.indexes
+    }
+
+    /// Requests waiting for keys of this store to change (woken by every
+    /// write).
+    pub fn watchers(&self) -> &Arc<Watchers> {
+        &self.watchers
+    }
+
+    /// Work queues of this store (not keys; see `Queues`).
+    pub fn queues(&self) -> &Queues {
+        &self.queues
+    }
+
+    /// Locks of this store (not keys; see `Locks`).
+    pub fn locks(&self) -> &Locks {
+        &self.locks
+    }
+
+    /// Keep previous versions of keys according to `policy`.
+    pub fn with_history(self, policy: HistoryPolicy) -> Self {
+        let policy = Arc::new(policy);
+        for shard in self.shards.iter() {
+            shard.write().unwrap().history_policy = policy.clone();
+        }
+        self
+    }
+
+    /// Append every mutation to `wal` from now on.
+    pub fn with_wal(mut self, wal: Arc<Wal>) -> Self {
+        for shard in self.shards.iter() {
+            shard.write().unwrap().wal = Some(wal.clone());
+        }
+        self.queues.lock().set_wal(wal.clone());
+        self.locks.lock().set_wal(wal.clone());
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Write-ahead log of this store, if it has one.
+    pub fn wal(&self) -> Option<&Arc<Wal>> {
+        self.wal.as_ref()
+    }
+
+    /// Give keys under the policy's prefixes a sliding expiry.
+    pub fn with_sliding(self, policy: SlidingPolicy) -> Self {
+        let policy = Arc::new(policy);
+        for shard in self.shards.iter() {
+            shard.write().unwrap().sliding_policy = policy.clone();
+        }
+        self
+    }
+
+    /// Number of keys evicted since the store was created.
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    pub fn record_evictions(&self, n: u64) {
+        self.evicted.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Visit the next `n` entries of a sweep over the whole store, one
+    /// shard at a time (read-locked while visited), keeping what `f`
+    /// returns.
+    ///
+    /// Each call resumes where the previous one stopped and wraps around,
+    /// so repeated calls look at every key in turn while each costs
+    /// O(n log N). A store with fewer than `n` keys may have some visited
+    /// twice.
+    pub fn sample<T>(&self, n: usize, mut f: impl FnMut(&String, &Entry) -> Option<T>) -> Vec<T> {
+        let mut cursor = self.sweep.lock().unwrap();
+        let mut out = Vec::new();
+        let mut visited = 0;
+        let mut shards_left = self.shards.len() + 1;
+
+        while visited < n && shards_left > 0 {
+            let shard = self.shards[cursor.shard % self.shards.len()].read().unwrap();
+            let lower = match &cursor.after {
+                Some(k) => Bound::Excluded(k.as_str()),
+                None => Bound::Unbounded,
+            };
+
+            let wanted = n - visited;
+            let mut last = None;
+            for (k, e) in shard.range::<str, _>((lower, Bound::Unbounded)).take(wanted) {
+                visited += 1;
+                out.extend(f(k, e));
+                last = Some(k);
+            }
+
+            if visited == n {
+                cursor.after = last.cloned();
+            } else {
+                cursor.shard = (cursor.shard + 1) % self.shards.len();
+                cursor.after = None;
+                shards_left -= 1;
+            }
+        }
+        out
+    }
+
+    /// The shard holding `key`.
+    pub fn shard(&self, key: &str) -> &RwLock<Shard> {
+        &self.shards[shard_index(key, self.shards.len())]
+    }
 
-/// Create a new, empty store.
-pub fn new_store() -> KvStore {
-    Arc::new(RwLock::new(HashMap::new()))
-}
\ No newline at end of file
+    /// All shards, in index order (lock them one at a time for work that
+    /// does not need a consistent cross-shard view).
+    pub fn shards(&self) -> &[RwLock<Shard>] {
+        &self.shards
+    }
+
+    /// Read-lock every shard (in index order) for a consistent view.
+    pub fn read_all(&self) -> ShardsRead<'_> {
+        ShardsRead {
+            guards: self.shards.iter().map(|s| s.read().unwrap()).collect(),
+        }
+    }
+
+    /// Write-lock every shard (in index order) for a consistent,
+    /// exclusive view.
+    pub fn write_all(&self) -> ShardsWrite<'_> {
+        ShardsWrite {
+            guards: self.shards.iter().map(|s| s.write().unwrap()).collect(),
+        }
+    }
+}
+
+/// Create a new, empty store for database `name` with `shards` shards
+/// (at least one) and no memory limit.
+pub fn new_store(name: &str, shards: usize) -> KvStore {
+    let used = Arc::new(AtomicUsize::new(0));
+    let indexes = Arc::new(IndexSet::default());
+    let watchers = Arc::new(Watchers::default());
+    let queues = Arc::new(Queues::new(used.clone()));
+
+    KvStore {
+        name: Arc::from(name),
+        shards: (0..shards.max(1))
+            .map(|_| {
+                RwLock::new(Shard {
+                    map: BTreeMap::new(),
+                    used: used.clone(),
+                    history: HashMap::new(),
+                    history_policy: Arc::default(),
+                    sliding_policy: Arc::default(),
+                    indexes: indexes.clone(),
+                    watchers: watchers.clone(),
+                    wal: None,
+                    batch: None,
+                })
+            })
+            .collect(),
+        indexes,
+        watchers,
+        queues,
+        locks: Arc::default(),
+        wal: None,
+        used,
+        evicted: Arc::new(AtomicU64::new(0)),
+        sweep: Arc::default(),
+        max_bytes: None,
+        policy: EvictionPolicy::default(),
+    }
+}
+
+/// Read guards over all shards of a store.
+pub struct ShardsRead<'a> {
+    guards: Vec<RwLockReadGuard<'a, Shard>>,
+}
+
+impl ShardsRead<'_> {
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.guards[shard_index(key, self.guards.len())].get(key)
+    }
+
+    /// Entries with keys in `(lower, ∞)`, in key order across all shards.
+    pub fn range<'s>(&'s self, lower: Bound<&'s str>) -> impl Iterator<Item = (&'s String, &'s Entry)> + 's {
+        merge_sorted(
+            self.guards
+                .iter()
+                .map(|g| g.range::<str, _>((lower, Bound::Unbounded)))
+                .collect(),
+        )
+    }
+
+    /// All entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> + '_ {
+        self.range(Bound::Unbounded)
+    }
+
+    /// Every key with a value history (including deleted keys), in no
+    /// particular order.
+    pub fn histories(&self) -> impl Iterator<Item = (&String, &VecDeque<Revision>)> + '_ {
+        self.guards.iter().flat_map(|g| g.history.iter())
+    }
+}
+
+/// Write guards over all shards of a store.
+pub struct ShardsWrite<'a> {
+    guards: Vec<RwLockWriteGuard<'a, Shard>>,
+}
+
+impl ShardsWrite<'_> {
+    fn shard_mut(&mut self, key: &str) -> &mut Shard {
+        let i = shard_index(key, self.guards.len());
+        &mut self.guards[i]
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.guards[shard_index(key, self.guards.len())].get(key)
+    }
+
+    /// Entries with keys in `(lower, ∞)`, in key order across all shards.
+    pub fn range<'s>(&'s self, lower: Bound<&'s str>) -> impl Iterator<Item = (&'s String, &'s Entry)> + 's {
+        merge_sorted(
+            self.guards
+                .iter()
+                .map(|g| g.range::<str, _>((lower, Bound::Unbounded)))
+                .collect(),
+        )
+    }
+
+    /// See `Shard::modify`.
+    pub fn modify<R>(&mut self, key: &str, f: impl FnOnce(&mut Entry) -> R) -> Option<R> {
+        self.shard_mut(key).modify(key, f)
+    }
+
+    pub fn insert(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        self.shard_mut(&key).insert(key, entry)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        self.shard_mut(key).remove(key)
+    }
+
+    pub fn set_history(&mut self, key: String, revisions: VecDeque<Revision>) {
+        self.shard_mut(&key).set_history(key, revisions)
+    }
+
+    /// Run `f`, logging every mutation it makes as a single
+    /// `Record::Batch` so that replay applies them all or none of them
+    /// (a multi-key write interrupted by a crash is not half-replayed).
+    ///
+    /// The batch is appended once `f` returns, before the shards are
+    /// unlocked.
+    pub fn batch<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let Some(wal) = self.guards.first().and_then(|g| g.wal.clone()) else {
+            return f(self);
+        };
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        for g in self.guards.iter_mut() {
+            g.batch = Some(records.clone());
+        }
+        let out = f(self);
+        for g in self.guards.iter_mut() {
+            g.batch = None;
+        }
+
+        let mut records = std::mem::take(&mut *records.lock().unwrap());
+        match records.len() {
+            0 => {}
+            1 => wal.append(&records.remove(0)),
+            _ => wal.append(&Record::Batch { records }),
+        }
+        out
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(wal) = self.guards.first().and_then(|g| g.wal.clone()) {
+            wal.append(&Record::Clear);
+        }
+        for g in self.guards.iter_mut() {
+            g.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.guards.iter().map(|g| g.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.guards.iter().all(|g| g.is_empty())
+    }
+}
+
+/// Merge iterators that are each sorted by key into one sorted iterator.
+///
+/// Keys are unique across shards, so there are no ties to break.
+fn merge_sorted<'a, I>(iters: Vec<I>) -> impl Iterator<Item = (&'a String, &'a Entry)>
+where
+    I: Iterator<Item = (&'a String, &'a Entry)>,
+{
+    let mut heads: Vec<_> = iters.into_iter().map(|it| it.peekable()).collect();
+
+    std::iter::from_fn(move || {
+        let next = heads
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, it)| it.peek().map(|(k, _)| (i, *k)))
+            .min_by(|a, b| a.1.cmp(b.1))
+            .map(|(i, _)| i)?;
+
+        heads[next].next()
+    })
+}
+
+/// Last version handed out by `next_version`.
+static VERSION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Allocate a new, strictly increasing entry version.
+pub fn next_version() -> u64 {
+    VERSION_COUNTER.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Make sure future versions are greater than `version`
+/// (used when loading entries from a snapshot).
+pub fn observe_version(version: u64) {
+    VERSION_COUNTER.fetch_max(version, Ordering::Relaxed);
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use super::wal::{Record, Wal};
+use super::watch::{Watch, Watchers};
+
+/// A lease on a lock: who holds it, the fencing token of the grant and
+/// when it runs out (Unix seconds).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    pub owner: String,
+    pub token: u64,
+    pub expires_at: i64,
+}
+
+impl Grant {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// Stored form of the lock table, as saved next to the snapshot.
+///
+/// `last_token` is the largest fencing token ever issued, so tokens keep
+/// growing after a restart even when the lock that had it was released.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LocksDoc {
+    pub last_token: u64,
+    pub leases: BTreeMap<String, Grant>,
+}
+
+/// The locks of a store, by name.
+///
+/// Locks are not keys: they only change through `LockTable`, which logs
+/// every grant and release to the write-ahead log under one mutex and
+/// wakes the requests waiting for that lock.
+pub struct Locks {
+    table: Mutex<LockTable>,
+    watchers: Arc<Watchers>,
+}
+
+impl Default for Locks {
+    fn default() -> Self {
+        let watchers = Arc::new(Watchers::default());
+        Locks {
+            table: Mutex::new(LockTable {
+                leases: HashMap::new(),
+                last_token: 0,
+                wal: None,
+                watchers: watchers.clone(),
+            }),
+            watchers,
+        }
+    }
+}
+
+impl Locks {
+    pub fn lock(&self) -> MutexGuard<'_, LockTable> {
+        self.table.lock().unwrap()
+    }
+
+    /// Start watching lock `name`: the watch is woken whenever the lock
+    /// is granted, renewed or released (see `Watchers`).
+    pub fn watch(&self, name: &str) -> Watch {
+        self.watchers.watch(name)
+    }
+}
+
+pub struct LockTable {
+    leases: HashMap<String, Grant>,
+    last_token: u64,
+    wal: Option<Arc<Wal>>,
+    watchers: Arc<Watchers>,
+}
+
+impl LockTable {
+    /// Append every grant and release to `wal` from now on.
+    pub fn set_wal(&mut self, wal: Arc<Wal>) {
+        self.wal = Some(wal);
+    }
+
+    /// Current lease on `name`, unless it ran out by `now`.
+    pub fn get(&self, name: &str, now: i64) -> Option<&Grant> {
+        self.leases.get(name).filter(|g| !g.is_expired(now))
+    }
+
+    /// A fencing token larger than any issued before.
+    ///
+    /// Tokens are also at least `now` in microseconds, so a restart that
+    /// lost the last grants (no WAL, crash before the next snapshot)
+    /// still never hands out a token twice.
+    pub fn next_token(&mut self, now: i64) -> u64 {
+        let floor = u64::try_from(now).unwrap_or(0).saturating_mul(1_000_000);
+        self.last_token = (self.last_token + 1).max(floor);
+        self.last_token
+    }
+
+    /// Give `name` to `grant` (a new lease or an extended one).
+    pub fn grant(&mut self, name: &str, grant: Grant) {
+        if let Some(wal) = &self.wal {
+            wal.append(&Record::Lock {
+                lock: name.to_string(),
+                grant: grant.clone(),
+            });
+        }
+        self.leases.insert(name.to_string(), grant);
+        self.watchers.wake(name);
+    }
+
+    /// Release `name`, whoever holds it. Returns the lease it had.
+    pub fn release(&mut self, name: &str) -> Option<Grant> {
+        let grant = self.leases.remove(name)?;
+        if let Some(wal) = &self.wal {
+            wal.append(&Record::Unlock {
+                lock: name.to_string(),
+                token: grant.token,
+            });
+        }
+        self.watchers.wake(name);
+        Some(grant)
+    }
+
+    /// Forget the leases that ran out by `now`. Returns how many.
+    ///
+    /// Nothing is logged: a replayed lease is just as expired.
+    pub fn purge(&mut self, now: i64) -> usize {
+        let before = self.leases.len();
+        self.leases.retain(|_, g| !g.is_expired(now));
+        before - self.leases.len()
+    }
+
+    /// The live leases and the token high-water mark, for the snapshot.
+    pub fn save(&self, now: i64) -> LocksDoc {
+        LocksDoc {
+            last_token: self.last_token,
+            leases: self
+                .leases
+                .iter()
+                .filter(|(_, g)| !g.is_expired(now))
+                .map(|(name, g)| (name.clone(), g.clone()))
+                .collect(),
+        }
+    }
+
+    /// Add the leases and token high-water mark of a snapshot.
+    pub fn load(&mut self, doc: LocksDoc) {
+        self.last_token = self.last_token.max(doc.last_token);
+        for (name, grant) in doc.leases {
+            self.last_token = self.last_token.max(grant.token);
+            self.leases.insert(name, grant);
+        }
+    }
+
+    /// Apply a grant read back from the write-ahead log.
+    pub fn replay_grant(&mut self, name: String, grant: Grant) {
+        self.last_token = self.last_token.max(grant.token);
+        self.leases.insert(name, grant);
+    }
+
+    /// Apply a release read back from the write-ahead log, unless the
+    /// lock was granted again since.
+    pub fn replay_release(&mut self, name: &str, token: u64) {
+        if self.leases.get(name).is_some_and(|g| g.token == token) {
+            self.leases.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(owner: &str, token: u64, expires_at: i64) -> Grant {
+        Grant {
+            owner: owner.to_string(),
+            token,
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn expired_leases_are_free_and_purged() {
+        let locks = Locks::default();
+        let mut table = locks.lock();
+        let token = table.next_token(0);
+        table.grant("job", grant("a", token, 10));
+
+        assert_eq!(table.get("job", 9).map(|g| g.owner.as_str()), Some("a"));
+        assert!(table.get("job", 10).is_none());
+        assert_eq!(table.purge(10), 1);
+        assert!(table.save(0).leases.is_empty());
+    }
+
+    #[test]
+    fn tokens_survive_release_through_the_snapshot_form() {
+        let locks = Locks::default();
+        let mut table = locks.lock();
+        let first = table.next_token(0);
+        table.grant("job", grant("a", first, 10));
+        table.release("job");
+
+        let saved = serde_json::to_value(table.save(0)).unwrap();
+        let restarted = Locks::default();
+        let mut table = restarted.lock();
+        table.load(LocksDoc::deserialize(saved).unwrap());
+
+        assert!(table.next_token(0) > first);
+    }
+
+    #[test]
+    fn replayed_release_keeps_a_later_grant() {
+        let locks = Locks::default();
+        let mut table = locks.lock();
+        table.replay_grant("job".to_string(), grant("a", 1, 10));
+        table.replay_release("job", 1);
+        table.replay_grant("job".to_string(), grant("b", 2, 10));
+        table.replay_release("job", 1);
+
+        assert_eq!(table.get("job", 0).map(|g| g.token), Some(2));
+        assert_eq!(table.next_token(0), 3);
+    }
+}
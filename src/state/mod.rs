@@ -1,8 +1,10 @@
+pub mod db;
+pub mod index;
 pub mod kv;
-pub(crate) mod app;
+pub mod lock;
+pub mod queue;
+pub mod wal;
+pub mod watch;
 //pub mod main;
 //pub mod config;
 //pub mod errors;
-pub mod persistence;
-
-pub use app::{AppState, Subscription};
\ No newline at end of file
@@ -0,0 +1,598 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::kv::{json_size, next_version, observe_version};
+use super::wal::{Record, Wal};
+
+/// Deliveries after which a message goes to the dead letters, unless the
+/// queue says otherwise.
+pub const DEFAULT_MAX_DELIVERIES: u64 = 5;
+
+/// Approximate per-message memory overhead (ids, timestamps, bookkeeping)
+/// on top of the body.
+pub const MESSAGE_OVERHEAD: usize = 64;
+
+/// A queued message.
+///
+/// `receipt` and `visible_at` are only set while the message is in
+/// flight: `receipt` identifies this delivery for ack / nack, and the
+/// message goes back to the queue once `visible_at` passes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: u64,
+    pub body: Arc<Value>,
+    pub enqueued_at: i64,
+    pub deliveries: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visible_at: Option<i64>,
+}
+
+impl Message {
+    /// Bytes accounted for the message in the store's memory usage.
+    pub fn bytes(&self) -> usize {
+        json_size(&self.body) + MESSAGE_OVERHEAD
+    }
+}
+
+/// One change to a queue, as applied by `Queue::apply` and written to the
+/// write-ahead log.
+///
+/// Whatever is not derived from the queue itself (bodies, receipts,
+/// deadlines) is part of the change, so replaying it on the same queue
+/// gives the same result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum QueueOp {
+    /// Append messages with these bodies.
+    Enqueue { bodies: Vec<Arc<Value>> },
+    /// Deliver one message from the front per receipt.
+    Dequeue { receipts: Vec<u64>, visible_at: i64 },
+    Ack { receipt: u64 },
+    /// Give a delivery back now, or make it visible again at `visible_at`.
+    Nack { receipt: u64, visible_at: Option<i64> },
+    SetMaxDeliveries { max_deliveries: u64 },
+    RequeueDead,
+    /// Nothing but the timed-out messages every change returns first.
+    Reclaim,
+}
+
+/// Stored form of a queue, as saved in the snapshot.
+#[derive(Serialize, Deserialize)]
+struct QueueDoc {
+    next_id: u64,
+    max_deliveries: u64,
+    #[serde(default)]
+    version: u64,
+    ready: VecDeque<Message>,
+    in_flight: Vec<Message>,
+    dead: Vec<Message>,
+}
+
+/// A work queue with at-least-once delivery.
+///
+/// Messages are delivered from the front of `ready`. In-flight messages
+/// are kept by receipt and, ordered by the time they become visible
+/// again, in `timeouts`, so returning timed-out messages only looks at
+/// the ones that are due.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "QueueDoc", into = "QueueDoc")]
+pub struct Queue {
+    next_id: u64,
+    max_deliveries: u64,
+    /// Version of the last change applied (see `QueueTable::replay`).
+    version: u64,
+    ready: VecDeque<Message>,
+    in_flight: HashMap<u64, Message>,
+    timeouts: BTreeSet<(i64, u64)>,
+    dead: Vec<Message>,
+    /// Sum of `Message::bytes` over all messages.
+    bytes: usize,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Queue {
+            next_id: 1,
+            max_deliveries: DEFAULT_MAX_DELIVERIES,
+            version: 0,
+            ready: VecDeque::new(),
+            in_flight: HashMap::new(),
+            timeouts: BTreeSet::new(),
+            dead: Vec::new(),
+            bytes: 0,
+        }
+    }
+}
+
+impl From<QueueDoc> for Queue {
+    fn from(doc: QueueDoc) -> Self {
+        let mut queue = Queue {
+            next_id: doc.next_id,
+            max_deliveries: doc.max_deliveries,
+            version: doc.version,
+            ready: doc.ready,
+            dead: doc.dead,
+            ..Queue::default()
+        };
+
+        for msg in doc.in_flight {
+            if msg.receipt.is_some() && msg.visible_at.is_some() {
+                queue.fly(msg);
+            } else {
+                queue.land(msg);
+            }
+        }
+
+        queue.bytes = queue
+            .ready
+            .iter()
+            .chain(queue.in_flight.values())
+            .chain(&queue.dead)
+            .map(Message::bytes)
+            .sum();
+        queue
+    }
+}
+
+impl From<Queue> for QueueDoc {
+    fn from(queue: Queue) -> Self {
+        let mut in_flight: Vec<Message> = queue.in_flight.into_values().collect();
+        in_flight.sort_by_key(|m| m.receipt);
+
+        QueueDoc {
+            next_id: queue.next_id,
+            max_deliveries: queue.max_deliveries,
+            version: queue.version,
+            ready: queue.ready,
+            in_flight,
+            dead: queue.dead,
+        }
+    }
+}
+
+impl Queue {
+    pub fn max_deliveries(&self) -> u64 {
+        self.max_deliveries
+    }
+
+    /// Messages waiting to be delivered, front first.
+    pub fn ready(&self) -> &VecDeque<Message> {
+        &self.ready
+    }
+
+    pub fn in_flight_len(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Messages that used up their deliveries, oldest first.
+    pub fn dead(&self) -> &[Message] {
+        &self.dead
+    }
+
+    /// When the first in-flight message times out.
+    pub fn next_timeout(&self) -> Option<i64> {
+        self.timeouts.first().map(|&(t, _)| t)
+    }
+
+    /// Put a message in flight (it must have a receipt and `visible_at`).
+    fn fly(&mut self, msg: Message) {
+        if let (Some(receipt), Some(t)) = (msg.receipt, msg.visible_at) {
+            self.timeouts.insert((t, receipt));
+            self.in_flight.insert(receipt, msg);
+        }
+    }
+
+    fn take_in_flight(&mut self, receipt: u64) -> Option<Message> {
+        let msg = self.in_flight.remove(&receipt)?;
+        if let Some(t) = msg.visible_at {
+            self.timeouts.remove(&(t, receipt));
+        }
+        Some(msg)
+    }
+
+    /// Return a message that left flight to the queue, or to the dead
+    /// letters once it was delivered `max_deliveries` times.
+    fn land(&mut self, mut msg: Message) {
+        msg.receipt = None;
+        msg.visible_at = None;
+
+        if msg.deliveries >= self.max_deliveries {
+            self.dead.push(msg);
+        } else {
+            self.ready.push_front(msg);
+        }
+    }
+
+    /// Land every in-flight message whose visibility timeout has passed.
+    /// Returns how many there were.
+    pub fn reclaim(&mut self, now: i64) -> usize {
+        let due: Vec<u64> = self
+            .timeouts
+            .iter()
+            .take_while(|&&(t, _)| t <= now)
+            .map(|&(_, receipt)| receipt)
+            .collect();
+
+        let mut expired: Vec<Message> = due.into_iter().filter_map(|r| self.take_in_flight(r)).collect();
+
+        // Oldest first once back at the front of the queue.
+        expired.sort_by_key(|m| Reverse(m.id));
+        let count = expired.len();
+        for msg in expired {
+            self.land(msg);
+        }
+        count
+    }
+
+    /// Apply `op`, made at time `at`, after returning the messages that
+    /// timed out by then. Receipts that are no longer in flight are
+    /// ignored.
+    pub fn apply(&mut self, op: &QueueOp, at: i64) {
+        self.reclaim(at);
+
+        match op {
+            QueueOp::Enqueue { bodies } => {
+                for body in bodies {
+                    let msg = Message {
+                        id: self.next_id,
+                        body: body.clone(),
+                        enqueued_at: at,
+                        deliveries: 0,
+                        receipt: None,
+                        visible_at: None,
+                    };
+                    self.next_id += 1;
+                    self.bytes += msg.bytes();
+                    self.ready.push_back(msg);
+                }
+            }
+            QueueOp::Dequeue { receipts, visible_at } => {
+                for &receipt in receipts {
+                    let Some(mut msg) = self.ready.pop_front() else { break };
+                    msg.deliveries += 1;
+                    msg.receipt = Some(receipt);
+                    msg.visible_at = Some(*visible_at);
+                    self.fly(msg);
+                }
+            }
+            QueueOp::Ack { receipt } => {
+                if let Some(msg) = self.take_in_flight(*receipt) {
+                    self.bytes -= msg.bytes();
+                }
+            }
+            QueueOp::Nack { receipt, visible_at } => {
+                if let Some(mut msg) = self.take_in_flight(*receipt) {
+                    match visible_at {
+                        Some(t) => {
+                            msg.visible_at = Some(*t);
+                            self.fly(msg);
+                        }
+                        None => self.land(msg),
+                    }
+                }
+            }
+            QueueOp::SetMaxDeliveries { max_deliveries } => {
+                self.max_deliveries = *max_deliveries;
+            }
+            QueueOp::RequeueDead => {
+                for mut msg in std::mem::take(&mut self.dead) {
+                    msg.deliveries = 0;
+                    self.ready.push_back(msg);
+                }
+            }
+            QueueOp::Reclaim => {}
+        }
+    }
+}
+
+/// The queues of a store, by name.
+///
+/// Queues are not keys: they only change through `QueueTable`, which
+/// applies each change, counts message bytes in the store's memory usage
+/// and appends the change (not the whole queue) to the write-ahead log,
+/// all under one mutex so the log has the changes in applied order.
+pub struct Queues {
+    table: Mutex<QueueTable>,
+}
+
+impl Queues {
+    /// An empty set of queues counting its messages in `used`.
+    pub fn new(used: Arc<AtomicUsize>) -> Self {
+        Queues {
+            table: Mutex::new(QueueTable {
+                queues: HashMap::new(),
+                used,
+                wal: None,
+            }),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, QueueTable> {
+        self.table.lock().unwrap()
+    }
+}
+
+pub struct QueueTable {
+    queues: HashMap<String, Queue>,
+    used: Arc<AtomicUsize>,
+    wal: Option<Arc<Wal>>,
+}
+
+impl QueueTable {
+    /// Append every change to `wal` from now on.
+    pub fn set_wal(&mut self, wal: Arc<Wal>) {
+        self.wal = Some(wal);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Queue> {
+        self.queues.get(name)
+    }
+
+    /// All queues, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Queue)> + '_ {
+        self.queues.iter()
+    }
+
+    fn account(&self, before: usize, after: usize) {
+        if after > before {
+            self.used.fetch_add(after - before, Ordering::Relaxed);
+        } else {
+            self.used.fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+
+    /// Add a queue loaded from a snapshot, replacing any of that name.
+    pub fn load(&mut self, name: String, queue: Queue) {
+        observe_version(queue.version);
+        let added = queue.bytes;
+        let replaced = self.queues.insert(name, queue).map_or(0, |q| q.bytes);
+        self.account(replaced, added);
+    }
+
+    /// Return timed-out messages of queue `name` (created if missing),
+    /// then apply and log the change `plan` makes, if any. Returns it.
+    ///
+    /// A queue created here that ends up unchanged is dropped again.
+    fn change(&mut self, name: &str, now: i64, plan: impl FnOnce(&Queue) -> Option<QueueOp>) -> Option<QueueOp> {
+        let created = !self.queues.contains_key(name);
+        let queue = self.queues.entry(name.to_string()).or_default();
+        let before = queue.bytes;
+
+        let reclaimed = queue.reclaim(now) > 0;
+        let op = plan(queue);
+        let logged = match &op {
+            Some(op) => {
+                queue.apply(op, now);
+                Some(op.clone())
+            }
+            None => reclaimed.then_some(QueueOp::Reclaim),
+        };
+        let after = queue.bytes;
+
+        match logged {
+            Some(change) => {
+                queue.version = next_version();
+                if let Some(wal) = &self.wal {
+                    wal.append(&Record::Queue {
+                        queue: name.to_string(),
+                        version: queue.version,
+                        at: now,
+                        change,
+                    });
+                }
+            }
+            None if created => {
+                self.queues.remove(name);
+            }
+            None => {}
+        }
+
+        self.account(before, after);
+        op
+    }
+
+    /// Append messages with `bodies` to queue `name`. Returns their ids.
+    pub fn enqueue(&mut self, name: &str, now: i64, bodies: Vec<Arc<Value>>) -> Vec<u64> {
+        let count = bodies.len() as u64;
+        self.change(name, now, |_| (count > 0).then_some(QueueOp::Enqueue { bodies }));
+
+        let next = self.queues.get(name).map_or(1, |q| q.next_id);
+        (next - count..next).collect()
+    }
+
+    /// Deliver up to `count` messages from the front of queue `name`,
+    /// invisible to other consumers until `visible_at`.
+    pub fn dequeue(&mut self, name: &str, now: i64, count: usize, visible_at: i64) -> Vec<Message> {
+        let op = self.change(name, now, |queue| {
+            let n = count.min(queue.ready.len());
+            (n > 0).then(|| QueueOp::Dequeue {
+                receipts: (0..n).map(|_| next_version()).collect(),
+                visible_at,
+            })
+        });
+
+        match (op, self.queues.get(name)) {
+            (Some(QueueOp::Dequeue { receipts, .. }), Some(queue)) => receipts
+                .iter()
+                .filter_map(|r| queue.in_flight.get(r).cloned())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Finish a delivery. False if `receipt` is not in flight (already
+    /// acked, or timed out by `now`).
+    pub fn ack(&mut self, name: &str, now: i64, receipt: u64) -> bool {
+        self.change(name, now, |queue| {
+            queue.in_flight.contains_key(&receipt).then_some(QueueOp::Ack { receipt })
+        })
+        .is_some()
+    }
+
+    /// Give a delivery back now, or make it visible again at
+    /// `visible_at`. False if `receipt` is not in flight.
+    pub fn nack(&mut self, name: &str, now: i64, receipt: u64, visible_at: Option<i64>) -> bool {
+        self.change(name, now, |queue| {
+            queue
+                .in_flight
+                .contains_key(&receipt)
+                .then_some(QueueOp::Nack { receipt, visible_at })
+        })
+        .is_some()
+    }
+
+    pub fn set_max_deliveries(&mut self, name: &str, now: i64, max_deliveries: u64) {
+        self.change(name, now, |queue| {
+            (queue.max_deliveries != max_deliveries).then_some(QueueOp::SetMaxDeliveries { max_deliveries })
+        });
+    }
+
+    /// Move the dead letters of queue `name` back to its end. Returns
+    /// how many were moved.
+    pub fn requeue_dead(&mut self, name: &str, now: i64) -> usize {
+        let mut count = 0;
+        self.change(name, now, |queue| {
+            count = queue.dead.len();
+            (count > 0).then_some(QueueOp::RequeueDead)
+        });
+        count
+    }
+
+    /// Return the timed-out messages of every queue. Returns how many
+    /// were moved.
+    pub fn reclaim_all(&mut self, now: i64) -> usize {
+        let due: Vec<String> = self
+            .queues
+            .iter()
+            .filter(|(_, q)| q.next_timeout().is_some_and(|t| t <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut moved = 0;
+        for name in due {
+            let before = self.queues.get(&name).map_or(0, |q| q.in_flight.len());
+            self.change(&name, now, |_| None);
+            moved += before - self.queues.get(&name).map_or(0, |q| q.in_flight.len());
+        }
+        moved
+    }
+
+    /// Delete queue `name` with all its messages. Returns whether it
+    /// existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let Some(queue) = self.queues.remove(name) else {
+            return false;
+        };
+        self.account(queue.bytes, 0);
+
+        if let Some(wal) = &self.wal {
+            wal.append(&Record::DropQueue {
+                queue: name.to_string(),
+                version: next_version(),
+            });
+        }
+        true
+    }
+
+    /// Apply a change read back from the write-ahead log, unless the
+    /// queue already has it (it was loaded from a snapshot taken after
+    /// the change was logged).
+    pub fn replay(&mut self, name: String, version: u64, at: i64, change: &QueueOp) {
+        observe_version(version);
+        let queue = self.queues.entry(name).or_default();
+        if version <= queue.version {
+            return;
+        }
+
+        let before = queue.bytes;
+        queue.apply(change, at);
+        queue.version = version;
+        let after = queue.bytes;
+        self.account(before, after);
+    }
+
+    /// Replay the deletion of queue `name`, unless the queue was
+    /// created again after it.
+    pub fn replay_remove(&mut self, name: &str, version: u64) {
+        observe_version(version);
+        if self.queues.get(name).is_some_and(|q| q.version < version) {
+            if let Some(queue) = self.queues.remove(name) {
+                self.account(queue.bytes, 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> QueueTable {
+        QueueTable {
+            queues: HashMap::new(),
+            used: Arc::new(AtomicUsize::new(0)),
+            wal: None,
+        }
+    }
+
+    #[test]
+    fn timed_out_messages_return_to_the_front_then_die() {
+        let mut queues = table();
+        queues.set_max_deliveries("q", 0, 2);
+        queues.enqueue("q", 0, vec![Arc::new(serde_json::json!(1)), Arc::new(serde_json::json!(2))]);
+
+        let first = queues.dequeue("q", 0, 1, 10);
+        assert_eq!(first[0].id, 1);
+
+        // Timed out: delivered again before message 2.
+        let again = queues.dequeue("q", 10, 1, 20);
+        assert_eq!(again[0].id, 1);
+        assert!(!queues.ack("q", 10, first[0].receipt.unwrap()));
+
+        // Second timeout uses up its deliveries.
+        queues.reclaim_all(20);
+        let queue = queues.get("q").unwrap();
+        assert_eq!(queue.dead().len(), 1);
+        assert_eq!(queue.ready().len(), 1);
+        assert_eq!(queue.in_flight_len(), 0);
+    }
+
+    #[test]
+    fn replay_skips_changes_the_queue_already_has() {
+        let mut queues = table();
+        let enqueue = QueueOp::Enqueue { bodies: vec![Arc::new(serde_json::json!("a"))] };
+        queues.replay("q".to_string(), 10, 0, &enqueue);
+        queues.replay("q".to_string(), 10, 0, &enqueue);
+        queues.replay("q".to_string(), 5, 0, &enqueue);
+        assert_eq!(queues.get("q").unwrap().ready().len(), 1);
+
+        queues.replay_remove("q", 8);
+        assert!(queues.get("q").is_some());
+        queues.replay_remove("q", 11);
+        assert!(queues.get("q").is_none());
+        assert_eq!(queues.used.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn snapshot_form_round_trips() {
+        let mut queues = table();
+        queues.enqueue("q", 0, vec![Arc::new(serde_json::json!({"n": 1})), Arc::new(serde_json::json!({"n": 2}))]);
+        let taken = queues.dequeue("q", 0, 1, 30);
+
+        let saved = serde_json::to_value(queues.get("q").unwrap()).unwrap();
+        let loaded = Queue::deserialize(saved).unwrap();
+        let original = queues.get("q").unwrap();
+
+        assert_eq!(loaded.bytes, original.bytes);
+        assert_eq!(loaded.next_timeout(), Some(30));
+        assert_eq!(loaded.in_flight[&taken[0].receipt.unwrap()].id, taken[0].id);
+        assert_eq!(loaded.ready().len(), 1);
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use super::kv::Entry;
+use super::lock::Grant;
+use super::queue::QueueOp;
+
+/// When the write-ahead log is flushed to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FsyncPolicy {
+    /// fsync after every record, before the write is acknowledged.
+    #[serde(rename = "always")]
+    Always,
+    /// fsync once a second (see `Wal::sync`): a crash of the machine may
+    /// lose the last second of writes, a crash of the server loses none.
+    #[default]
+    #[serde(rename = "everysec")]
+    EverySec,
+    /// Never fsync; the OS decides when the log reaches the disk.
+    #[serde(rename = "never")]
+    Never,
+}
+
+/// One mutation of a store, as written to the log (one JSON object per
+/// line).
+///
+/// `Delete` records the removed entry in the key's history on replay;
+/// `Expire` and `Evict` do not (see `Shard::remove` / `Shard::evict`).
+/// Queue records carry the change and its version, which replay compares
+/// with the queue's (see `QueueTable::replay`). Lock records carry the
+/// whole lease, or the token of the lease released.
+///
+/// `Batch` groups the records of a multi-key write (transaction, mset,
+/// mdel, bulk delete) on a single line, so a torn tail drops the whole
+/// write rather than part of it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Record {
+    Set { key: String, entry: Entry },
+    Delete { key: String },
+    Expire { key: String },
+    Evict { key: String },
+    Clear,
+    Queue { queue: String, version: u64, at: i64, change: QueueOp },
+    DropQueue { queue: String, version: u64 },
+    Lock { lock: String, grant: Grant },
+    Unlock { lock: String, token: u64 },
+    Batch { records: Vec<Record> },
+}
+
+struct LogFile {
+    file: File,
+    /// Bytes written so far (the position of the next record).
+    len: u64,
+    /// Records written since the last fsync.
+    dirty: bool,
+}
+
+/// Append-only log of the mutations of one store.
+///
+/// Records are appended by the store's shards under their write lock, so
+/// a write is in the log before it is acknowledged, and the records of a
+/// key are in the order its writes were applied. After a snapshot the
+/// part of the log it covers is dropped (see `truncate_before`).
+pub struct Wal {
+    path: PathBuf,
+    policy: FsyncPolicy,
+    log: Mutex<LogFile>,
+}
+
+impl Wal {
+    /// Open (or create) the log at `path`, appending to existing records.
+    ///
+    /// Whatever follows the last record that decodes (a record torn by a
+    /// crash) is cut off first: replay stops there, so records appended
+    /// after it would never be read back.
+    pub fn open(path: impl AsRef<Path>, policy: FsyncPolicy) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let (_, len) = scan(&path)?;
+        if file.metadata()?.len() > len {
+            file.set_len(len)?;
+        }
+
+        Ok(Wal {
+            path,
+            policy,
+            log: Mutex::new(LogFile { file, len, dirty: false }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn policy(&self) -> FsyncPolicy {
+        self.policy
+    }
+
+    /// Append a record (and fsync it under `FsyncPolicy::Always`).
+    ///
+    /// Failures are logged: the write has already been applied in memory
+    /// and will still be in the next snapshot.
+    pub fn append(&self, record: &Record) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(l) => l,
+            Err(e) => {
+                tracing::error!("Failed to encode WAL record: {e}");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let mut log = self.log.lock().unwrap();
+        if let Err(e) = log.file.write_all(&line) {
+            tracing::error!("Failed to append to WAL {}: {e}", self.path.display());
+            return;
+        }
+        log.len += line.len() as u64;
+
+        if self.policy == FsyncPolicy::Always {
+            if let Err(e) = log.file.sync_data() {
+                tracing::error!("Failed to fsync WAL {}: {e}", self.path.display());
+            }
+        } else {
+            log.dirty = true;
+        }
+    }
+
+    /// fsync records written since the last call (`FsyncPolicy::EverySec`).
+    pub fn sync(&self) {
+        let mut log = self.log.lock().unwrap();
+        if !log.dirty {
+            return;
+        }
+
+        match log.file.sync_data() {
+            Ok(()) => log.dirty = false,
+            Err(e) => tracing::error!("Failed to fsync WAL {}: {e}", self.path.display()),
+        }
+    }
+
+    /// Position of the next record. Taken while the store is locked for a
+    /// snapshot, it separates the records the snapshot covers from later
+    /// ones.
+    pub fn position(&self) -> u64 {
+        self.log.lock().unwrap().len
+    }
+
+    /// Drop the records before `position` (covered by a saved snapshot),
+    /// keeping the ones appended since.
+    ///
+    /// The remaining records are written to a new file that replaces the
+    /// log atomically, so a crash never leaves a partial log behind.
+    pub fn truncate_before(&self, position: u64) -> io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+
+        let mut tail = Vec::new();
+        let mut reader = File::open(&self.path)?;
+        reader.seek(SeekFrom::Start(position.min(log.len)))?;
+        reader.read_to_end(&mut tail)?;
+
+        let tmp = self.path.with_extension("wal.tmp");
+        {
+            let mut out = File::create(&tmp)?;
+            out.write_all(&tail)?;
+            out.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+
+        log.file = OpenOptions::new().append(true).open(&self.path)?;
+        log.len = tail.len() as u64;
+        log.dirty = false;
+
+        Ok(())
+    }
+}
+
+/// Read the records of the log at `path`, oldest first. A missing log
+/// has none.
+///
+/// Reading stops at the first record that cannot be decoded: that is the
+/// last one, torn by a crash while it was written.
+pub fn read_records(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+    Ok(scan(path.as_ref())?.0)
+}
+
+/// The records of the log at `path` and the length of the part of the
+/// file they were read from.
+///
+/// A line counts only if it decodes and ends with a newline, so the
+/// length is always where the next record can be appended.
+fn scan(path: &Path) -> io::Result<(Vec<Record>, u64)> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut len = 0;
+    let mut line = Vec::new();
+    for n in 1.. {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+
+        let record = match line.strip_suffix(b"\n") {
+            Some(l) => serde_json::from_slice(l).map_err(|e| e.to_string()),
+            None => Err("no end of line".to_string()),
+        };
+
+        match record {
+            Ok(record) => {
+                records.push(record);
+                len += read as u64;
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring the rest of WAL {} from line {}: {e}", path.display(), n);
+                break;
+            }
+        }
+    }
+
+    Ok((records, len))
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+/// Keys that clients are waiting on (long-poll reads), each with the
+/// `Notify` its waiters park on.
+///
+/// Every mutation of a shard calls `wake` for the key it changed, while
+/// still holding the shard's write lock. A waiter therefore enables its
+/// `Notified` future first and then reads the key: a change is either
+/// already visible to the read or wakes the future.
+#[derive(Default)]
+pub struct Watchers {
+    keys: Mutex<HashMap<String, Arc<Notify>>>,
+    /// Number of watched keys, so writes skip the mutex when nobody waits.
+    watched: AtomicUsize,
+}
+
+impl Watchers {
+    /// Start watching `key`. The key stays watched until every `Watch`
+    /// on it is dropped.
+    pub fn watch(self: &Arc<Self>, key: &str) -> Watch {
+        let mut keys = self.keys.lock().unwrap();
+
+        let notify = match keys.get(key) {
+            Some(n) => n.clone(),
+            None => {
+                let n = Arc::new(Notify::new());
+                keys.insert(key.to_string(), n.clone());
+                self.watched.store(keys.len(), Ordering::Release);
+                n
+            }
+        };
+
+        Watch {
+            watchers: self.clone(),
+            key: key.to_string(),
+            notify: Some(notify),
+        }
+    }
+
+    /// Wake everyone waiting on `key`.
+    pub fn wake(&self, key: &str) {
+        if self.watched.load(Ordering::Acquire) == 0 {
+            return;
+        }
+
+        if let Some(n) = self.keys.lock().unwrap().get(key) {
+            n.notify_waiters();
+        }
+    }
+}
+
+/// Registration of one waiter on a key (see `Watchers::watch`).
+pub struct Watch {
+    watchers: Arc<Watchers>,
+    key: String,
+    notify: Option<Arc<Notify>>,
+}
+
+impl Watch {
+    pub fn notify(&self) -> &Notify {
+        self.notify.as_ref().expect("watch is live")
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let mut keys = self.watchers.keys.lock().unwrap();
+
+        // Release our handle under the mutex so the count below is exact:
+        // only the map's own handle left means we were the last waiter.
+        drop(self.notify.take());
+        if keys.get(&self.key).is_some_and(|n| Arc::strong_count(n) == 1) {
+            keys.remove(&self.key);
+            self.watchers.watched.store(keys.len(), Ordering::Release);
+        }
+    }
+}